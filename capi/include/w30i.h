@@ -0,0 +1,179 @@
+#ifndef W30I_H
+#define W30I_H
+
+/* Generated by cbindgen from capi/src/lib.rs, don't edit - regenerate with
+   W30I_WRITE_HEADER=1 cargo test -p weath3rb0i-capi --test header */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Success
+#define W30I_OK 0
+
+// Success, but there's more output than fit into `dst` - call again
+#define W30I_MORE_OUTPUT 1
+
+// A null pointer, an unknown preset, a memory budget above
+// `W30I_MAX_MEMORY`, more input than declared or a stream used after it
+// finished
+#define W30I_ERROR_INVALID_ARGUMENT -1
+
+// `dst` can't hold the output, `dst_len` is set to the size needed
+#define W30I_ERROR_DST_TOO_SMALL -2
+
+// The input isn't a valid stream
+#define W30I_ERROR_CORRUPT -3
+
+// The input ends before the stream does
+#define W30I_ERROR_TRUNCATED -4
+
+// The stream needs a dictionary or a reference, or (streaming) was written
+// with options that need the whole input up front
+#define W30I_ERROR_UNSUPPORTED -5
+
+// A bug, the library panicked
+#define W30I_ERROR_INTERNAL -6
+
+// The stream needs models and buffers of more than `W30I_MAX_MEMORY` bytes or
+// (streaming) decompresses to more than the `max_output` given
+#define W30I_ERROR_LIMIT -7
+
+// Picks the preset from the start of the input
+#define W30I_PRESET_AUTO 0
+
+#define W30I_PRESET_TEXT 1
+
+#define W30I_PRESET_BINARY 2
+
+// The input is copied behind the header as is
+#define W30I_PRESET_STORE 3
+
+// Memory budget of the models when 0 is passed
+#define W30I_DEFAULT_MEMORY (8 << 10)
+
+// Largest memory budget to compress with and of streams to decompress
+#define W30I_MAX_MEMORY (256 << 20)
+
+// A compression in progress, the same stream `w30i_compress` writes
+typedef struct W30iCStream W30iCStream;
+
+// A decompression in progress, of a stream `w30i_compress` or a
+// `W30iCStream` wrote
+typedef struct W30iDStream W30iDStream;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Upper bound of what `w30i_compress` writes for `src_len` bytes
+size_t w30i_compress_bound(size_t src_len);
+
+// Compresses `src_len` bytes at `src` into `dst`, with models of `memory`
+// bytes (0 for the default) and one of the `W30I_PRESET_*` presets
+//
+// # Safety
+//
+// `src` must point to `src_len` readable bytes and `dst` to `*dst_len`
+// writable ones (either may be null if its length is 0).
+int w30i_compress(const uint8_t *src,
+                  size_t src_len,
+                  uint8_t *dst,
+                  size_t *dst_len,
+                  uint64_t memory,
+                  int preset);
+
+// Decompresses the stream in the `src_len` bytes at `src` into `dst`
+//
+// # Safety
+//
+// `src` must point to `src_len` readable bytes and `dst` to `*dst_len`
+// writable ones (either may be null if its length is 0).
+int w30i_decompress(const uint8_t *src, size_t src_len, uint8_t *dst, size_t *dst_len);
+
+// Reads the decompressed size from the header at the start of `src`
+//
+// # Safety
+//
+// `src` must point to `src_len` readable bytes and `len` to a writable
+// `uint64_t`.
+int w30i_decompressed_size(const uint8_t *src, size_t src_len, uint64_t *len);
+
+// Name of a status code, e.g. "W30I_ERROR_CORRUPT", as a static string
+const char *w30i_status_name(int status);
+
+// Starts compressing `len` bytes of input, null for an unknown preset or a
+// memory budget above `W30I_MAX_MEMORY` - without a preset, the first call's
+// input picks it
+struct W30iCStream *w30i_cstream_new(uint64_t len, uint64_t memory, int preset);
+
+// Compresses the `src_len` bytes at `src` and writes as much of the output
+// so far as fits into `dst`, `W30I_MORE_OUTPUT` means some didn't
+//
+// # Safety
+//
+// `stream` must come from `w30i_cstream_new`, `src` must point to `src_len`
+// readable bytes and `dst` to `*dst_len` writable ones.
+int w30i_cstream_compress(struct W30iCStream *stream,
+                          const uint8_t *src,
+                          size_t src_len,
+                          uint8_t *dst,
+                          size_t *dst_len);
+
+// Ends the stream and writes as much of the rest of the output as fits into
+// `dst`, `W30I_MORE_OUTPUT` means call again for the rest
+//
+// # Safety
+//
+// `stream` must come from `w30i_cstream_new` and `dst` must point to
+// `*dst_len` writable bytes.
+int w30i_cstream_finish(struct W30iCStream *stream, uint8_t *dst, size_t *dst_len);
+
+// Frees a stream from `w30i_cstream_new`, null is ignored
+//
+// # Safety
+//
+// `stream` must come from `w30i_cstream_new` and not be used afterwards.
+void w30i_cstream_free(struct W30iCStream *stream);
+
+// Starts decompressing a stream of at most `max_output` bytes (`UINT64_MAX`
+// for any), longer ones fail with `W30I_ERROR_LIMIT`
+struct W30iDStream *w30i_dstream_new(uint64_t max_output);
+
+// Decompresses what the `src_len` bytes at `src` complete of the stream and
+// writes as much of it as fits into `dst`, `W30I_MORE_OUTPUT` means some
+// didn't
+//
+// # Safety
+//
+// `stream` must come from `w30i_dstream_new`, `src` must point to `src_len`
+// readable bytes and `dst` to `*dst_len` writable ones.
+int w30i_dstream_decompress(struct W30iDStream *stream,
+                            const uint8_t *src,
+                            size_t src_len,
+                            uint8_t *dst,
+                            size_t *dst_len);
+
+// Ends the input, fails with `W30I_ERROR_TRUNCATED` if the stream isn't
+// complete, and writes as much of the rest of the output as fits into `dst`
+//
+// # Safety
+//
+// `stream` must come from `w30i_dstream_new` and `dst` must point to
+// `*dst_len` writable bytes.
+int w30i_dstream_finish(struct W30iDStream *stream, uint8_t *dst, size_t *dst_len);
+
+// Frees a stream from `w30i_dstream_new`, null is ignored
+//
+// # Safety
+//
+// `stream` must come from `w30i_dstream_new` and not be used afterwards.
+void w30i_dstream_free(struct W30iDStream *stream);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* W30I_H */
@@ -0,0 +1,504 @@
+//! Stable C API of weath3rb0i, declared in `include/w30i.h` (generated from
+//! this file, see `tests/header.rs`)
+//!
+//! Functions return one of the `W30I_*` status codes unless noted otherwise,
+//! negative codes are errors. Sizes go in and out through `dst_len`: the
+//! capacity of `dst` on the way in, the bytes written on the way out.
+//!
+//! No call allocates models of more than `W30I_MAX_MEMORY` bytes, whatever a
+//! stream's header asks for. A failed allocation still aborts the process, as
+//! everywhere in Rust, and so does a panic in builds with `panic = "abort"` -
+//! build with the `capi` profile to get `W30I_ERROR_INTERNAL` instead.
+
+use std::ffi::{c_char, c_int};
+use std::io::{self, ErrorKind, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+
+use weath3rb0i::compressor::{self, Header, Limits, Options, Preset, StreamDecoder, StreamEncoder};
+use weath3rb0i::entropy_coding::huffman::CanonicalCode;
+use weath3rb0i::transform::TextTransform;
+
+/// Success
+pub const W30I_OK: c_int = 0;
+/// Success, but there's more output than fit into `dst` - call again
+pub const W30I_MORE_OUTPUT: c_int = 1;
+/// A null pointer, an unknown preset, a memory budget above
+/// `W30I_MAX_MEMORY`, more input than declared or a stream used after it
+/// finished
+pub const W30I_ERROR_INVALID_ARGUMENT: c_int = -1;
+/// `dst` can't hold the output, `dst_len` is set to the size needed
+pub const W30I_ERROR_DST_TOO_SMALL: c_int = -2;
+/// The input isn't a valid stream
+pub const W30I_ERROR_CORRUPT: c_int = -3;
+/// The input ends before the stream does
+pub const W30I_ERROR_TRUNCATED: c_int = -4;
+/// The stream needs a dictionary or a reference, or (streaming) was written
+/// with options that need the whole input up front
+pub const W30I_ERROR_UNSUPPORTED: c_int = -5;
+/// A bug, the library panicked
+pub const W30I_ERROR_INTERNAL: c_int = -6;
+/// The stream needs models and buffers of more than `W30I_MAX_MEMORY` bytes or
+/// (streaming) decompresses to more than the `max_output` given
+pub const W30I_ERROR_LIMIT: c_int = -7;
+
+/// Picks the preset from the start of the input
+pub const W30I_PRESET_AUTO: c_int = 0;
+pub const W30I_PRESET_TEXT: c_int = 1;
+pub const W30I_PRESET_BINARY: c_int = 2;
+/// The input is copied behind the header as is
+pub const W30I_PRESET_STORE: c_int = 3;
+
+/// Memory budget of the models when 0 is passed
+pub const W30I_DEFAULT_MEMORY: u64 = 8 << 10;
+/// Largest memory budget to compress with and of streams to decompress
+pub const W30I_MAX_MEMORY: u64 = 256 << 20;
+// spelled out for the header
+const _: () = assert!(W30I_DEFAULT_MEMORY == compressor::DEFAULT_MEMORY);
+const _: () = assert!(W30I_MAX_MEMORY <= compressor::MAX_MEMORY);
+
+/// Upper bound of what `w30i_compress` writes for `src_len` bytes
+#[no_mangle]
+pub extern "C" fn w30i_compress_bound(src_len: usize) -> usize {
+    src_len.saturating_add(Header::FIXED_SIZE)
+}
+
+/// Compresses `src_len` bytes at `src` into `dst`, with models of `memory`
+/// bytes (0 for the default) and one of the `W30I_PRESET_*` presets
+///
+/// # Safety
+///
+/// `src` must point to `src_len` readable bytes and `dst` to `*dst_len`
+/// writable ones (either may be null if its length is 0).
+#[no_mangle]
+pub unsafe extern "C" fn w30i_compress(
+    src: *const u8,
+    src_len: usize,
+    dst: *mut u8,
+    dst_len: *mut usize,
+    memory: u64,
+    preset: c_int,
+) -> c_int {
+    guard(W30I_ERROR_INTERNAL, || {
+        let (Some(src), Some(options)) = (input(src, src_len), options(memory, preset)) else {
+            return W30I_ERROR_INVALID_ARGUMENT;
+        };
+        let len = src.len() as u64;
+        let mut compressed = Vec::new();
+        if let Err(err) = compressor::compress_with(src, len, &mut compressed, &options) {
+            return status(&err, W30I_ERROR_INVALID_ARGUMENT);
+        }
+        // what the models would expand is stored, to keep within the bound
+        if compressed.len() > w30i_compress_bound(src.len()) {
+            compressed.clear();
+            let options = options.preset(Preset::Store);
+            if let Err(err) = compressor::compress_with(src, len, &mut compressed, &options) {
+                return status(&err, W30I_ERROR_INVALID_ARGUMENT);
+            }
+        }
+        copy_out(&mut compressed, dst, dst_len, false)
+    })
+}
+
+/// Decompresses the stream in the `src_len` bytes at `src` into `dst`
+///
+/// # Safety
+///
+/// `src` must point to `src_len` readable bytes and `dst` to `*dst_len`
+/// writable ones (either may be null if its length is 0).
+#[no_mangle]
+pub unsafe extern "C" fn w30i_decompress(
+    src: *const u8,
+    src_len: usize,
+    dst: *mut u8,
+    dst_len: *mut usize,
+) -> c_int {
+    guard(W30I_ERROR_INTERNAL, || {
+        let Some(src) = input(src, src_len) else {
+            return W30I_ERROR_INVALID_ARGUMENT;
+        };
+        let Some(dst) = output(dst, dst_len) else {
+            return W30I_ERROR_INVALID_ARGUMENT;
+        };
+        let header = match Header::read(&mut &src[..]) {
+            Ok(header) => header,
+            Err(err) => return status(&err, W30I_ERROR_UNSUPPORTED),
+        };
+        if header.dictionary.is_some() || header.reference.is_some() {
+            return W30I_ERROR_UNSUPPORTED;
+        }
+        let Ok(len) = usize::try_from(header.len) else {
+            return W30I_ERROR_DST_TOO_SMALL;
+        };
+        if len > dst.len() {
+            *dst_len = len;
+            return W30I_ERROR_DST_TOO_SMALL;
+        }
+        let limits = limits(header.len);
+        if let Err(err) = compressor::decompress_with_limits(src, &mut dst[..len], &limits) {
+            return status(&err, W30I_ERROR_UNSUPPORTED);
+        }
+        *dst_len = len;
+        W30I_OK
+    })
+}
+
+/// Reads the decompressed size from the header at the start of `src`
+///
+/// # Safety
+///
+/// `src` must point to `src_len` readable bytes and `len` to a writable
+/// `uint64_t`.
+#[no_mangle]
+pub unsafe extern "C" fn w30i_decompressed_size(
+    src: *const u8,
+    src_len: usize,
+    len: *mut u64,
+) -> c_int {
+    guard(W30I_ERROR_INTERNAL, || {
+        let (Some(src), false) = (input(src, src_len), len.is_null()) else {
+            return W30I_ERROR_INVALID_ARGUMENT;
+        };
+        match Header::read(&mut &src[..]) {
+            Ok(header) => {
+                *len = header.len;
+                W30I_OK
+            }
+            Err(err) => status(&err, W30I_ERROR_UNSUPPORTED),
+        }
+    })
+}
+
+/// Name of a status code, e.g. "W30I_ERROR_CORRUPT", as a static string
+#[no_mangle]
+pub extern "C" fn w30i_status_name(status: c_int) -> *const c_char {
+    let name = match status {
+        W30I_OK => c"W30I_OK",
+        W30I_MORE_OUTPUT => c"W30I_MORE_OUTPUT",
+        W30I_ERROR_INVALID_ARGUMENT => c"W30I_ERROR_INVALID_ARGUMENT",
+        W30I_ERROR_DST_TOO_SMALL => c"W30I_ERROR_DST_TOO_SMALL",
+        W30I_ERROR_CORRUPT => c"W30I_ERROR_CORRUPT",
+        W30I_ERROR_TRUNCATED => c"W30I_ERROR_TRUNCATED",
+        W30I_ERROR_UNSUPPORTED => c"W30I_ERROR_UNSUPPORTED",
+        W30I_ERROR_INTERNAL => c"W30I_ERROR_INTERNAL",
+        W30I_ERROR_LIMIT => c"W30I_ERROR_LIMIT",
+        _ => c"unknown status",
+    };
+    name.as_ptr()
+}
+
+/// A compression in progress, the same stream `w30i_compress` writes
+pub struct W30iCStream {
+    /// `None` once finished
+    encoder: Option<StreamEncoder<Vec<u8>>>,
+    /// Output not handed out yet
+    pending: Vec<u8>,
+    /// The first error, every later call returns it too
+    error: c_int,
+}
+
+/// Starts compressing `len` bytes of input, null for an unknown preset or a
+/// memory budget above `W30I_MAX_MEMORY` - without a preset, the first call's
+/// input picks it
+#[no_mangle]
+pub extern "C" fn w30i_cstream_new(len: u64, memory: u64, preset: c_int) -> *mut W30iCStream {
+    guard(ptr::null_mut(), || {
+        let Some(options) = options(memory, preset) else {
+            return ptr::null_mut();
+        };
+        let encoder =
+            StreamEncoder::new(Vec::new(), len, &options).expect("no whole input options");
+        let stream = W30iCStream {
+            encoder: Some(encoder),
+            pending: Vec::new(),
+            error: W30I_OK,
+        };
+        Box::into_raw(Box::new(stream))
+    })
+}
+
+/// Compresses the `src_len` bytes at `src` and writes as much of the output
+/// so far as fits into `dst`, `W30I_MORE_OUTPUT` means some didn't
+///
+/// # Safety
+///
+/// `stream` must come from `w30i_cstream_new`, `src` must point to `src_len`
+/// readable bytes and `dst` to `*dst_len` writable ones.
+#[no_mangle]
+pub unsafe extern "C" fn w30i_cstream_compress(
+    stream: *mut W30iCStream,
+    src: *const u8,
+    src_len: usize,
+    dst: *mut u8,
+    dst_len: *mut usize,
+) -> c_int {
+    guard_stream(stream, |stream| {
+        let Some(src) = input(src, src_len) else {
+            return W30I_ERROR_INVALID_ARGUMENT;
+        };
+        let Some(encoder) = stream.encoder.as_mut() else {
+            return W30I_ERROR_INVALID_ARGUMENT;
+        };
+        if let Err(err) = encoder.write_all(src) {
+            stream.error = status(&err, W30I_ERROR_INVALID_ARGUMENT);
+            return stream.error;
+        }
+        stream.pending.append(encoder.get_mut());
+        copy_out(&mut stream.pending, dst, dst_len, true)
+    })
+}
+
+/// Ends the stream and writes as much of the rest of the output as fits into
+/// `dst`, `W30I_MORE_OUTPUT` means call again for the rest
+///
+/// # Safety
+///
+/// `stream` must come from `w30i_cstream_new` and `dst` must point to
+/// `*dst_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn w30i_cstream_finish(
+    stream: *mut W30iCStream,
+    dst: *mut u8,
+    dst_len: *mut usize,
+) -> c_int {
+    guard_stream(stream, |stream| {
+        if let Some(encoder) = stream.encoder.take() {
+            match encoder.finish() {
+                Ok((rest, _)) => stream.pending.extend(rest),
+                Err(err) => {
+                    stream.error = status(&err, W30I_ERROR_INVALID_ARGUMENT);
+                    return stream.error;
+                }
+            }
+        }
+        copy_out(&mut stream.pending, dst, dst_len, true)
+    })
+}
+
+/// Frees a stream from `w30i_cstream_new`, null is ignored
+///
+/// # Safety
+///
+/// `stream` must come from `w30i_cstream_new` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn w30i_cstream_free(stream: *mut W30iCStream) {
+    if !stream.is_null() {
+        guard((), || drop(Box::from_raw(stream)));
+    }
+}
+
+/// A decompression in progress, of a stream `w30i_compress` or a
+/// `W30iCStream` wrote
+pub struct W30iDStream {
+    /// `None` once finished
+    decoder: Option<StreamDecoder<Vec<u8>>>,
+    /// Output not handed out yet
+    pending: Vec<u8>,
+    /// The first error, every later call returns it too
+    error: c_int,
+}
+
+/// Starts decompressing a stream of at most `max_output` bytes (`UINT64_MAX`
+/// for any), longer ones fail with `W30I_ERROR_LIMIT`
+#[no_mangle]
+pub extern "C" fn w30i_dstream_new(max_output: u64) -> *mut W30iDStream {
+    guard(ptr::null_mut(), || {
+        let stream = W30iDStream {
+            decoder: Some(StreamDecoder::with_limits(Vec::new(), limits(max_output))),
+            pending: Vec::new(),
+            error: W30I_OK,
+        };
+        Box::into_raw(Box::new(stream))
+    })
+}
+
+/// Decompresses what the `src_len` bytes at `src` complete of the stream and
+/// writes as much of it as fits into `dst`, `W30I_MORE_OUTPUT` means some
+/// didn't
+///
+/// # Safety
+///
+/// `stream` must come from `w30i_dstream_new`, `src` must point to `src_len`
+/// readable bytes and `dst` to `*dst_len` writable ones.
+#[no_mangle]
+pub unsafe extern "C" fn w30i_dstream_decompress(
+    stream: *mut W30iDStream,
+    src: *const u8,
+    src_len: usize,
+    dst: *mut u8,
+    dst_len: *mut usize,
+) -> c_int {
+    guard_stream(stream, |stream| {
+        let Some(src) = input(src, src_len) else {
+            return W30I_ERROR_INVALID_ARGUMENT;
+        };
+        let Some(decoder) = stream.decoder.as_mut() else {
+            return W30I_ERROR_INVALID_ARGUMENT;
+        };
+        if let Err(err) = decoder.write_all(src) {
+            stream.error = status(&err, W30I_ERROR_UNSUPPORTED);
+            return stream.error;
+        }
+        stream.pending.append(decoder.get_mut());
+        copy_out(&mut stream.pending, dst, dst_len, true)
+    })
+}
+
+/// Ends the input, fails with `W30I_ERROR_TRUNCATED` if the stream isn't
+/// complete, and writes as much of the rest of the output as fits into `dst`
+///
+/// # Safety
+///
+/// `stream` must come from `w30i_dstream_new` and `dst` must point to
+/// `*dst_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn w30i_dstream_finish(
+    stream: *mut W30iDStream,
+    dst: *mut u8,
+    dst_len: *mut usize,
+) -> c_int {
+    guard_stream(stream, |stream| {
+        if let Some(decoder) = stream.decoder.take() {
+            match decoder.finish() {
+                Ok((rest, _)) => stream.pending.extend(rest),
+                Err(err) => {
+                    stream.error = status(&err, W30I_ERROR_UNSUPPORTED);
+                    return stream.error;
+                }
+            }
+        }
+        copy_out(&mut stream.pending, dst, dst_len, true)
+    })
+}
+
+/// Frees a stream from `w30i_dstream_new`, null is ignored
+///
+/// # Safety
+///
+/// `stream` must come from `w30i_dstream_new` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn w30i_dstream_free(stream: *mut W30iDStream) {
+    if !stream.is_null() {
+        guard((), || drop(Box::from_raw(stream)));
+    }
+}
+
+/// Runs `f`, returning `on_panic` instead of unwinding into C
+fn guard<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+/// The first error of a stream
+trait Sticky {
+    fn error(&mut self) -> &mut c_int;
+}
+
+impl Sticky for W30iCStream {
+    fn error(&mut self) -> &mut c_int {
+        &mut self.error
+    }
+}
+
+impl Sticky for W30iDStream {
+    fn error(&mut self) -> &mut c_int {
+        &mut self.error
+    }
+}
+
+/// Runs `f` on `stream` unless it failed before, a panic fails it for good
+unsafe fn guard_stream<S: Sticky>(stream: *mut S, f: impl FnOnce(&mut S) -> c_int) -> c_int {
+    let Some(stream) = stream.as_mut() else {
+        return W30I_ERROR_INVALID_ARGUMENT;
+    };
+    if *stream.error() != W30I_OK {
+        return *stream.error();
+    }
+    let status = guard(None, || Some(f(stream)));
+    status.unwrap_or_else(|| {
+        *stream.error() = W30I_ERROR_INTERNAL;
+        W30I_ERROR_INTERNAL
+    })
+}
+
+/// The status code for `err`, `invalid_input` tells what `InvalidInput`
+/// means for the call
+fn status(err: &io::Error, invalid_input: c_int) -> c_int {
+    match err.kind() {
+        ErrorKind::InvalidInput => invalid_input,
+        ErrorKind::InvalidData => W30I_ERROR_CORRUPT,
+        ErrorKind::UnexpectedEof => W30I_ERROR_TRUNCATED,
+        ErrorKind::WriteZero => W30I_ERROR_DST_TOO_SMALL,
+        ErrorKind::FileTooLarge | ErrorKind::OutOfMemory => W30I_ERROR_LIMIT,
+        _ => W30I_ERROR_INTERNAL,
+    }
+}
+
+/// `Options` for the C arguments, `None` for an unknown preset or too much
+/// memory
+fn options(memory: u64, preset: c_int) -> Option<Options> {
+    let memory = match memory {
+        0 => W30I_DEFAULT_MEMORY,
+        memory if memory <= W30I_MAX_MEMORY => memory,
+        _ => return None,
+    };
+    let options = Options::default().memory(memory);
+    match preset {
+        W30I_PRESET_AUTO => Some(options),
+        W30I_PRESET_TEXT => Some(options.preset(Preset::Text)),
+        W30I_PRESET_BINARY => Some(options.preset(Preset::Binary)),
+        W30I_PRESET_STORE => Some(options.preset(Preset::Store)),
+        _ => None,
+    }
+}
+
+/// Limits for decoding at most `max_output` bytes with models of at most
+/// `W30I_MAX_MEMORY` bytes
+fn limits(max_output: u64) -> Limits {
+    // the text transform at most doubles what's coded, codewords are longer
+    // than 8 bits
+    let max_bits =
+        TextTransform::max_len(max_output).saturating_mul(u64::from(CanonicalCode::MAX_LEN));
+    Limits::unlimited()
+        .max_output(max_output)
+        .max_bits(max_bits)
+        .max_memory(usize::try_from(W30I_MAX_MEMORY).unwrap_or(usize::MAX))
+}
+
+/// The input buffer, `None` if it's null but not empty
+unsafe fn input<'a>(src: *const u8, src_len: usize) -> Option<&'a [u8]> {
+    match (src.is_null(), src_len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(src, src_len)),
+    }
+}
+
+/// The output buffer of `*dst_len` bytes, `None` if a pointer is null
+unsafe fn output<'a>(dst: *mut u8, dst_len: *mut usize) -> Option<&'a mut [u8]> {
+    match (dst.is_null(), dst_len.as_ref()) {
+        (_, None) => None,
+        (true, Some(0)) => Some(&mut []),
+        (true, Some(_)) => None,
+        (false, Some(&len)) => Some(slice::from_raw_parts_mut(dst, len)),
+    }
+}
+
+/// Moves as much of `bytes` as fits into `dst`, the rest stays pending when
+/// `partial` (`W30I_MORE_OUTPUT`), otherwise it's `W30I_ERROR_DST_TOO_SMALL`
+unsafe fn copy_out(bytes: &mut Vec<u8>, dst: *mut u8, dst_len: *mut usize, partial: bool) -> c_int {
+    let Some(dst) = output(dst, dst_len) else {
+        return W30I_ERROR_INVALID_ARGUMENT;
+    };
+    if !partial && bytes.len() > dst.len() {
+        *dst_len = bytes.len();
+        return W30I_ERROR_DST_TOO_SMALL;
+    }
+    let len = bytes.len().min(dst.len());
+    dst[..len].copy_from_slice(&bytes[..len]);
+    bytes.drain(..len);
+    *dst_len = len;
+    match bytes.is_empty() {
+        true => W30I_OK,
+        false => W30I_MORE_OUTPUT,
+    }
+}
@@ -0,0 +1,46 @@
+use std::path::Path;
+use std::{env, process::Command};
+
+/// Builds `round_trip.c` against the static library and runs it
+#[test]
+fn round_trip() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // integration tests run from target/<profile>/deps, and cargo doesn't
+    // build the static library for them - build it with the same profile,
+    // offline from the dependencies the test was built with
+    let exe = env::current_exe().unwrap();
+    let deps_dir = exe.parent().unwrap();
+    let lib_dir = deps_dir.parent().unwrap();
+    let target_dir = lib_dir.parent().unwrap();
+    let profile = match lib_dir.file_name().unwrap().to_str().unwrap() {
+        "debug" => "dev",
+        profile => profile,
+    };
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .args(["build", "--lib", "--offline", "--profile", profile])
+        .arg("--manifest-path")
+        .arg(dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(target_dir)
+        .status()
+        .expect("cargo");
+    assert!(status.success(), "the static library doesn't build");
+    let out = deps_dir.join("round_trip");
+
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(cc)
+        .arg(dir.join("tests/round_trip.c"))
+        .arg("-I")
+        .arg(dir.join("include"))
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-o"])
+        .arg(&out)
+        .arg(lib_dir.join("libw30i.a"))
+        .args(["-lpthread", "-ldl", "-lm"])
+        .status()
+        .expect("C compiler");
+    assert!(status.success(), "round_trip.c doesn't compile");
+
+    let status = Command::new(&out).status().unwrap();
+    assert!(status.success(), "round_trip failed");
+}
@@ -0,0 +1,26 @@
+use std::{env, fs, path::Path};
+
+/// Checks that `include/w30i.h` is what cbindgen generates from `src/lib.rs`,
+/// with `W30I_WRITE_HEADER` set it writes it instead
+#[test]
+fn header_is_current() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(dir.join("cbindgen.toml")).unwrap();
+    let mut header = Vec::new();
+    cbindgen::Builder::new()
+        .with_src(dir.join("src/lib.rs"))
+        .with_config(config)
+        .generate()
+        .expect("Unable to generate the C header")
+        .write(&mut header);
+
+    let path = dir.join("include/w30i.h");
+    if env::var_os("W30I_WRITE_HEADER").is_some() {
+        fs::write(&path, &header).unwrap();
+    }
+    assert!(
+        fs::read(&path).unwrap() == header,
+        "include/w30i.h is stale, regenerate it with \
+         `W30I_WRITE_HEADER=1 cargo test -p weath3rb0i-capi --test header`"
+    );
+}
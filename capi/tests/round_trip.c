@@ -0,0 +1,194 @@
+/* Round trips data through the buffer and the streaming API, exits with 1 on
+ * the first mismatch or unexpected status */
+#include <stdio.h>
+#include <string.h>
+
+#include "w30i.h"
+
+#define LEN (100 * 1024)
+/* small output buffers, to go through W30I_MORE_OUTPUT */
+#define CHUNK 1000
+
+static int failed(const char *what, int status) {
+    fprintf(stderr, "%s: %s\n", what, w30i_status_name(status));
+    return 1;
+}
+
+static void generate(uint8_t *data, size_t len) {
+    static const char *words[] = {"weather", "boy", "compresses", "the", "bits", "of", "text"};
+    uint32_t state = 30;
+    size_t pos = 0;
+    while (pos < len) {
+        state = state * 1103515245 + 12345;
+        const char *word = words[(state >> 16) % 7];
+        for (size_t i = 0; word[i] && pos < len; i++) {
+            data[pos++] = (uint8_t)word[i];
+        }
+        if (pos < len) {
+            data[pos++] = (state >> 24) % 8 ? ' ' : '\n';
+        }
+    }
+}
+
+static int buffers(const uint8_t *data) {
+    static uint8_t compressed[LEN + 64];
+    static uint8_t decompressed[LEN];
+    int presets[] = {W30I_PRESET_AUTO, W30I_PRESET_TEXT, W30I_PRESET_BINARY, W30I_PRESET_STORE};
+    for (size_t i = 0; i < 4; i++) {
+        size_t compressed_len = w30i_compress_bound(LEN);
+        int status = w30i_compress(data, LEN, compressed, &compressed_len, 0, presets[i]);
+        if (status != W30I_OK) {
+            return failed("w30i_compress", status);
+        }
+        if (compressed_len > w30i_compress_bound(LEN) ||
+            (presets[i] != W30I_PRESET_STORE && compressed_len >= LEN / 2)) {
+            fprintf(stderr, "preset %d: %zu bytes\n", presets[i], compressed_len);
+            return 1;
+        }
+
+        uint64_t len = 0;
+        status = w30i_decompressed_size(compressed, compressed_len, &len);
+        if (status != W30I_OK || len != LEN) {
+            return failed("w30i_decompressed_size", status);
+        }
+        size_t decompressed_len = LEN - 1;
+        status = w30i_decompress(compressed, compressed_len, decompressed, &decompressed_len);
+        if (status != W30I_ERROR_DST_TOO_SMALL || decompressed_len != LEN) {
+            return failed("w30i_decompress into a short buffer", status);
+        }
+        status = w30i_decompress(compressed, compressed_len, decompressed, &decompressed_len);
+        if (status != W30I_OK) {
+            return failed("w30i_decompress", status);
+        }
+        if (decompressed_len != LEN || memcmp(data, decompressed, LEN) != 0) {
+            fprintf(stderr, "preset %d: round trip differs\n", presets[i]);
+            return 1;
+        }
+
+        compressed[compressed_len / 2] ^= 0x55;
+        decompressed_len = LEN;
+        status = w30i_decompress(compressed, compressed_len, decompressed, &decompressed_len);
+        if (status >= 0 && memcmp(data, decompressed, LEN) == 0) {
+            fprintf(stderr, "preset %d: corruption went unnoticed\n", presets[i]);
+            return 1;
+        }
+        decompressed_len = LEN;
+        status = w30i_decompress(compressed, compressed_len / 3, decompressed, &decompressed_len);
+        if (status >= 0) {
+            return failed("w30i_decompress of a truncated stream", status);
+        }
+    }
+
+    size_t compressed_len = sizeof(compressed);
+    int status = w30i_compress(data, LEN, compressed, &compressed_len, W30I_MAX_MEMORY + 1,
+                               W30I_PRESET_AUTO);
+    if (status != W30I_ERROR_INVALID_ARGUMENT) {
+        return failed("w30i_compress with too much memory", status);
+    }
+    if (w30i_cstream_new(LEN, W30I_MAX_MEMORY + 1, W30I_PRESET_AUTO)) {
+        fprintf(stderr, "w30i_cstream_new with too much memory\n");
+        return 1;
+    }
+    return 0;
+}
+
+static int streams(const uint8_t *data) {
+    static uint8_t compressed[LEN + 64];
+    static uint8_t decompressed[LEN];
+    size_t compressed_len = 0;
+    W30iCStream *cs = w30i_cstream_new(LEN, 0, W30I_PRESET_TEXT);
+    if (!cs) {
+        return failed("w30i_cstream_new", W30I_ERROR_INTERNAL);
+    }
+    for (size_t pos = 0; pos < LEN; pos += 7000) {
+        size_t src_len = LEN - pos < 7000 ? LEN - pos : 7000;
+        int status;
+        do {
+            size_t dst_len = CHUNK;
+            status = w30i_cstream_compress(cs, data + pos, src_len, compressed + compressed_len,
+                                           &dst_len);
+            compressed_len += dst_len;
+            src_len = 0;
+        } while (status == W30I_MORE_OUTPUT);
+        if (status != W30I_OK) {
+            return failed("w30i_cstream_compress", status);
+        }
+    }
+    int status;
+    do {
+        size_t dst_len = CHUNK;
+        status = w30i_cstream_finish(cs, compressed + compressed_len, &dst_len);
+        compressed_len += dst_len;
+    } while (status == W30I_MORE_OUTPUT);
+    w30i_cstream_free(cs);
+    if (status != W30I_OK) {
+        return failed("w30i_cstream_finish", status);
+    }
+
+    /* the same stream as in one go */
+    static uint8_t whole[LEN + 64];
+    size_t whole_len = sizeof(whole);
+    status = w30i_compress(data, LEN, whole, &whole_len, 0, W30I_PRESET_TEXT);
+    if (status != W30I_OK || whole_len != compressed_len ||
+        memcmp(whole, compressed, whole_len) != 0) {
+        return failed("w30i_cstream_* differs from w30i_compress", status);
+    }
+
+    size_t decompressed_len = 0;
+    W30iDStream *ds = w30i_dstream_new(UINT64_MAX);
+    for (size_t pos = 0; pos < compressed_len; pos += 333) {
+        size_t src_len = compressed_len - pos < 333 ? compressed_len - pos : 333;
+        do {
+            size_t dst_len = CHUNK < LEN - decompressed_len ? CHUNK : LEN - decompressed_len;
+            status = w30i_dstream_decompress(ds, compressed + pos, src_len,
+                                             decompressed + decompressed_len, &dst_len);
+            decompressed_len += dst_len;
+            src_len = 0;
+        } while (status == W30I_MORE_OUTPUT);
+        if (status != W30I_OK) {
+            return failed("w30i_dstream_decompress", status);
+        }
+    }
+    do {
+        size_t dst_len = CHUNK < LEN - decompressed_len ? CHUNK : LEN - decompressed_len;
+        status = w30i_dstream_finish(ds, decompressed + decompressed_len, &dst_len);
+        decompressed_len += dst_len;
+    } while (status == W30I_MORE_OUTPUT);
+    w30i_dstream_free(ds);
+    if (status != W30I_OK) {
+        return failed("w30i_dstream_finish", status);
+    }
+    if (decompressed_len != LEN || memcmp(data, decompressed, LEN) != 0) {
+        fprintf(stderr, "streams: round trip differs\n");
+        return 1;
+    }
+
+    /* a stream longer than allowed */
+    ds = w30i_dstream_new(LEN - 1);
+    size_t dst_len = LEN;
+    status = w30i_dstream_decompress(ds, compressed, compressed_len, decompressed, &dst_len);
+    w30i_dstream_free(ds);
+    if (status != W30I_ERROR_LIMIT) {
+        return failed("w30i_dstream_decompress over max_output", status);
+    }
+
+    /* a stream that ends early */
+    ds = w30i_dstream_new(UINT64_MAX);
+    dst_len = LEN;
+    status = w30i_dstream_decompress(ds, compressed, compressed_len / 2, decompressed, &dst_len);
+    if (status == W30I_OK) {
+        dst_len = LEN;
+        status = w30i_dstream_finish(ds, decompressed, &dst_len);
+    }
+    w30i_dstream_free(ds);
+    if (status != W30I_ERROR_TRUNCATED) {
+        return failed("w30i_dstream_finish of a truncated stream", status);
+    }
+    return 0;
+}
+
+int main(void) {
+    static uint8_t data[LEN];
+    generate(data, LEN);
+    return buffers(data) || streams(data);
+}
@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use weath3rb0i::entropy_coding::{arithmetic_coder::ArithmeticCoder, io::ACReader};
+
+// Probabilities cycle through the extremes the coder has to survive
+const PROBS: [u16; 6] = [0, 1, 1 << 15, u16::MAX - 1, u16::MAX, 12345];
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = ACReader::new(data);
+    let mut ac = ArithmeticCoder::new_decoder(&mut reader).unwrap();
+    for i in 0..data.len() * 8 + 64 {
+        let bit = ac.decode(PROBS[i % PROBS.len()], &mut reader).unwrap();
+        assert!(bit <= 1);
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use weath3rb0i::compressor::decompress;
+
+// Must return (an error) instead of panicking or decoding forever
+fuzz_target!(|data: &[u8]| {
+    let _ = decompress(data, std::io::sink());
+});
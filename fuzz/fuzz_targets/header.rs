@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use weath3rb0i::compressor::Header;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = Header::read(&mut &data[..]) {
+        let mut buf = Vec::new();
+        header.write(&mut buf).unwrap();
+        assert_eq!(buf, data[..buf.len()]);
+    }
+});
@@ -0,0 +1,52 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use weath3rb0i::entropy_coding::package_merge::{canonical, package_merge};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&max_len, data)) = data.split_first() else {
+        return;
+    };
+    let max_len = max_len % 17; // canonical codes are u16
+    let counts: Vec<u32> = data
+        .chunks_exact(4)
+        .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+        .collect();
+    let symbols = counts.iter().filter(|&&x| x != 0).count();
+    if symbols == 0 || symbols > 1 << max_len {
+        return; // rejected by asserts
+    }
+
+    let code_lens = package_merge(&counts, max_len);
+    assert_eq!(code_lens.len(), counts.len());
+    for (&count, &len) in counts.iter().zip(&code_lens) {
+        assert!(len <= max_len);
+        if symbols > 1 {
+            assert_eq!(count == 0, len == 0);
+        } else {
+            assert_eq!(len, 0); // a single symbol takes no bits
+        }
+    }
+
+    // Kraft's inequality holds with equality for complete codes
+    if symbols > 1 {
+        let kraft: u64 = code_lens
+            .iter()
+            .filter(|&&len| len != 0)
+            .map(|&len| 1 << (16 - len))
+            .sum();
+        assert_eq!(kraft, 1 << 16);
+    }
+
+    // no code is a prefix of another
+    let codes: Vec<_> = canonical(&code_lens)
+        .into_iter()
+        .filter(|&(_, len)| len != 0)
+        .collect();
+    for (i, &(c1, l1)) in codes.iter().enumerate() {
+        for &(c2, l2) in &codes[i + 1..] {
+            let len = l1.min(l2);
+            assert_ne!(c1 >> (l1 - len), c2 >> (l2 - len));
+        }
+    }
+});
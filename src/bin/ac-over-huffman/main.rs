@@ -71,7 +71,7 @@ fn compress(buf: &[u8], huffman_size: u8, ctx_bits: u8) -> Result<u64> {
     let mut model = OrderN::new(ctx_bits, 0);
     let mut writer = ACStats::new();
 
-    let counts = histogram(&buf);
+    let counts = histogram(buf);
     let code_lens = package_merge(&counts, huffman_size);
     let huffman = canonical(&code_lens);
 
@@ -0,0 +1,324 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use weath3rb0i::compressor;
+
+const HEADER: &str = "file,size,csize,bpc,ctime_ms,dtime_ms,cspeed_mbs,dspeed_mbs,cmem_kb,dmem_kb";
+// speeds are noisy, only flag drops larger than this
+const SPEED_TOLERANCE: f64 = 0.1;
+
+struct Entry {
+    name: String,
+    size: u64,
+    csize: u64,
+    ctime: Duration,
+    dtime: Duration,
+    cmem: Option<u64>,
+    dmem: Option<u64>,
+}
+
+impl Entry {
+    fn bpc(&self) -> f64 {
+        if self.size == 0 {
+            return 0.0;
+        }
+        self.csize as f64 * 8.0 / self.size as f64
+    }
+
+    fn cspeed(&self) -> f64 {
+        speed(self.size, self.ctime)
+    }
+
+    fn dspeed(&self) -> f64 {
+        speed(self.size, self.dtime)
+    }
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{:.4},{:.3},{:.3},{:.3},{:.3},{},{}",
+            quote(&self.name),
+            self.size,
+            self.csize,
+            self.bpc(),
+            self.ctime.as_secs_f64() * 1000.0,
+            self.dtime.as_secs_f64() * 1000.0,
+            self.cspeed(),
+            self.dspeed(),
+            self.cmem.map(|m| m.to_string()).unwrap_or_default(),
+            self.dmem.map(|m| m.to_string()).unwrap_or_default(),
+        )
+    }
+}
+
+/// A row of a previous results file, only the fields we diff against
+struct Baseline {
+    csize: u64,
+    cspeed: f64,
+    dspeed: f64,
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let mut input = None;
+    let mut out_path = PathBuf::from("results.csv");
+    let mut baseline_path = None;
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" | "--out" => out_path = PathBuf::from(expect_value(iter.next(), arg)),
+            "-d" | "--diff" => baseline_path = Some(PathBuf::from(expect_value(iter.next(), arg))),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => print_usage_and_exit(&format!("Unexpected argument: {}", arg)),
+        }
+    }
+    let input =
+        input.unwrap_or_else(|| print_usage_and_exit("Provide a corpus directory or manifest!"));
+
+    let files = collect_files(&input)?;
+    if files.is_empty() {
+        print_usage_and_exit("Corpus contains no files!");
+    }
+
+    let mut entries = Vec::with_capacity(files.len());
+    let mut failures = 0;
+    for (name, file) in &files {
+        match exec(name, file) {
+            Ok(entry) => {
+                println!(
+                    "[bench] {:<24} {:>10} -> {:>10} ({:.3} bpc), c: {:8.2} MB/s, d: {:8.2} MB/s",
+                    entry.name,
+                    entry.size,
+                    entry.csize,
+                    entry.bpc(),
+                    entry.cspeed(),
+                    entry.dspeed()
+                );
+                entries.push(entry);
+            }
+            Err(err) => {
+                println!("[bench] {}: FAILED ({})", file.display(), err);
+                failures += 1;
+            }
+        }
+    }
+
+    let mut table = String::from(HEADER);
+    table.push('\n');
+    for entry in &entries {
+        table.push_str(&entry.to_csv());
+        table.push('\n');
+    }
+    fs::write(&out_path, table)?;
+    println!("Results written to {}", out_path.display());
+
+    if let Some(baseline_path) = baseline_path {
+        let baseline = read_baseline(&baseline_path)?;
+        failures += diff(&entries, &baseline);
+    }
+
+    if failures > 0 {
+        println!("{} failure(s)", failures);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Compresses and decompresses a single file in memory, verifying the round trip
+fn exec(name: &str, path: &Path) -> Result<Entry> {
+    let buf = fs::read(path)?;
+    let size = buf.len() as u64;
+
+    reset_peak_memory();
+    let timer = Instant::now();
+    let mut compressed = Vec::new();
+    compressor::compress(buf.as_slice(), size, &mut compressed)?;
+    let ctime = timer.elapsed();
+    let cmem = peak_memory();
+
+    reset_peak_memory();
+    let timer = Instant::now();
+    let mut decompressed = Vec::with_capacity(buf.len());
+    compressor::decompress(compressed.as_slice(), &mut decompressed)?;
+    let dtime = timer.elapsed();
+    let dmem = peak_memory();
+
+    if let Some(pos) = buf.iter().zip(&decompressed).position(|(a, b)| a != b) {
+        let msg = format!("round trip differs at byte {}", pos);
+        return Err(Error::new(ErrorKind::InvalidData, msg));
+    }
+    if buf.len() != decompressed.len() {
+        let msg = format!(
+            "round trip is {} bytes, expected {}",
+            decompressed.len(),
+            buf.len()
+        );
+        return Err(Error::new(ErrorKind::InvalidData, msg));
+    }
+
+    Ok(Entry {
+        name: name.to_string(),
+        size,
+        csize: compressed.len() as u64,
+        ctime,
+        dtime,
+        cmem,
+        dmem,
+    })
+}
+
+/// A directory is shallow traversed, any other file is read as a manifest:
+/// one path per line (relative to the manifest), `#` starts a comment.
+/// Files are named by their path relative to the directory or manifest, so
+/// same-named files in different directories keep their own results
+fn collect_files(input: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    if input.is_dir() {
+        for file in fs::read_dir(input)? {
+            let file = file?;
+            if file.path().is_file() {
+                let name = file.file_name().to_string_lossy().into_owned();
+                files.push((name, file.path()));
+            }
+        }
+    } else {
+        let root = input.parent().unwrap_or(Path::new("."));
+        for line in fs::read_to_string(input)?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            files.push((line.to_string(), root.join(line)));
+        }
+    }
+    // reproducible order regardless of the filesystem
+    files.sort();
+    Ok(files)
+}
+
+fn read_baseline(path: &Path) -> Result<HashMap<String, Baseline>> {
+    let mut baseline = HashMap::new();
+    for line in fs::read_to_string(path)?.lines().skip(1) {
+        let fields = split_csv(line);
+        if fields.len() != HEADER.split(',').count() {
+            continue;
+        }
+        let parse_err = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Bad results line: {}", line),
+            )
+        };
+        let entry = Baseline {
+            csize: fields[2].parse().map_err(|_| parse_err())?,
+            cspeed: fields[6].parse().map_err(|_| parse_err())?,
+            dspeed: fields[7].parse().map_err(|_| parse_err())?,
+        };
+        baseline.insert(fields[0].clone(), entry);
+    }
+    Ok(baseline)
+}
+
+/// Prints a comparison against the baseline, returns the number of size regressions
+fn diff(entries: &[Entry], baseline: &HashMap<String, Baseline>) -> usize {
+    let mut regressions = 0;
+    for entry in entries {
+        let Some(prev) = baseline.get(&entry.name) else {
+            println!("[diff] {:<24} new file", entry.name);
+            continue;
+        };
+
+        let delta = entry.csize as i64 - prev.csize as i64;
+        let mut flags = Vec::new();
+        if delta > 0 {
+            flags.push("SIZE REGRESSION");
+            regressions += 1;
+        }
+        if entry.cspeed() < prev.cspeed * (1.0 - SPEED_TOLERANCE) {
+            flags.push("slower compression");
+        }
+        if entry.dspeed() < prev.dspeed * (1.0 - SPEED_TOLERANCE) {
+            flags.push("slower decompression");
+        }
+        println!(
+            "[diff] {:<24} {:>10} -> {:>10} ({:+}) {}",
+            entry.name,
+            prev.csize,
+            entry.csize,
+            delta,
+            flags.join(", ")
+        );
+    }
+    regressions
+}
+
+/// Quotes a CSV field if it holds a separator or a quote, doubling the quotes
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Splits a line written with `quote` back into its fields
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+fn speed(size: u64, time: Duration) -> f64 {
+    let secs = time.as_secs_f64();
+    if secs == 0.0 {
+        return 0.0;
+    }
+    size as f64 / secs / 1e6
+}
+
+/// Peak resident memory in KB since the last reset (Linux only)
+fn peak_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn reset_peak_memory() {
+    // writing 5 to clear_refs resets the peak RSS counter
+    let _ = fs::write("/proc/self/clear_refs", "5");
+}
+
+fn expect_value<'a>(value: Option<&'a String>, flag: &str) -> &'a str {
+    match value {
+        Some(value) => value,
+        None => print_usage_and_exit(&format!("Missing value for {}", flag)),
+    }
+}
+
+fn print_usage_and_exit(msg: &str) -> ! {
+    println!("Usage: bench <Corpus> [-o <results.csv>] [-d <previous.csv>]");
+    println!("<Corpus> is a directory (shallow traversed) or a manifest with one path per line");
+    println!("-o, --out: where to write the results table (default: results.csv)");
+    println!(
+        "-d, --diff: previous results table to compare against, size regressions fail the run"
+    );
+    println!("\n{}", msg);
+    std::process::exit(1);
+}
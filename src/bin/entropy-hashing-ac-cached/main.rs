@@ -7,85 +7,94 @@ use std::{
 use weath3rb0i::{
     entropy_coding::arithmetic_coder::ArithmeticCoder,
     helpers::ACStats,
-    history::{ACHistoryCached, History},
-    models::{ac_hash::StationaryModel, Model, OrderNEntropy},
+    history::ACHistoryCached,
+    models::{ac_hash::StationaryModel, ACHashModel, Model, OrderNEntropy},
     u64, unroll_for,
 };
 
+// cache capacities to search, speed vs memory
+const CACHE_BYTES: [usize; 3] = [1 << 16, 1 << 20, 1 << 24];
+
 fn main() -> Result<()> {
     let buf = std::fs::read("/Users/mitiko/_data/book1")?;
 
     let levels = 3;
     let mut best = vec![(u64!(buf.len()), Duration::MAX); levels];
-    let mut params = vec![(0, 0, 0); levels];
+    let mut params = vec![(0, 0, 0, 0); levels];
 
     let model = StationaryModel::new(&buf);
 
     for ctx_bits in 8..=30 {
         best[1] = (u64!(buf.len()), Duration::MAX);
-        params[1] = (0, 0, 0);
+        params[1] = (0, 0, 0, 0);
         for alignment_bits in 0..=4 {
             // for alignment_bits in [3] { // go faster
             best[2] = (u64!(buf.len()), Duration::MAX);
-            params[2] = (0, 0, 0);
-            let results: Vec<_> = [0, 4, 8, 12, 16, 20, 24]
+            params[2] = (0, 0, 0, 0);
+            let configs: Vec<_> = [0, 4, 8, 12, 16, 20, 24]
+                .into_iter()
+                .flat_map(|cache_size| CACHE_BYTES.map(|cache_bytes| (cache_size, cache_bytes)))
+                .collect();
+            let results: Vec<_> = configs
                 .into_par_iter()
-                .map(|cache_size| {
+                .map(|(cache_size, cache_bytes)| {
                     let history =
-                        ACHistoryCached::new(ctx_bits - alignment_bits, model.clone(), cache_size);
-                    let results =
-                        exec(&buf, ctx_bits, alignment_bits, history, cache_size).unwrap();
-                    (cache_size, results)
+                        ACHistoryCached::new(ctx_bits - alignment_bits, model.clone(), cache_size)
+                            .cache_bytes(cache_bytes);
+                    let results = exec(&buf, ctx_bits, alignment_bits, history).unwrap();
+                    (cache_size, cache_bytes, results)
                 })
                 .collect();
-            for (cache_size, (res, time)) in results {
+            for (cache_size, cache_bytes, (res, time)) in results {
                 for i in 0..levels {
                     if res > best[i].0 || (res == best[i].0 && time > best[i].1) {
                         continue;
                     }
                     best[i] = (res, time);
-                    params[i] = (ctx_bits, alignment_bits, cache_size);
+                    params[i] = (ctx_bits, alignment_bits, cache_size, cache_bytes);
                 }
             }
             println!(
-                "-> fastest: {} in {:?} ({:?} per bit) for [ctx: {}, align: {}, cache: {}]",
+                "-> fastest: {} in {:?} ({:?} per bit) for [ctx: {}, align: {}, cache: {}, cache bytes: {}]",
                 best[2].0,
                 best[2].1,
                 best[2].1.div_f64(buf.len() as f64 * 8.0),
                 params[2].0,
                 params[2].1,
-                params[2].2
+                params[2].2,
+                params[2].3
             );
         }
         println!(
-            "--> best: {} in {:?} ({:?} per bit) for [ctx: {}, align: {}, cache: {}]",
+            "--> best: {} in {:?} ({:?} per bit) for [ctx: {}, align: {}, cache: {}, cache bytes: {}]",
             best[1].0,
             best[1].1,
             best[1].1.div_f64(buf.len() as f64 * 8.0),
             params[1].0,
             params[1].1,
-            params[1].2
+            params[1].2,
+            params[1].3
         );
     }
     println!(
-        "--> gloabl best: {} in {:?} ({:?} per bit) for [ctx: {}, align: {}, cache: {}]",
+        "--> gloabl best: {} in {:?} ({:?} per bit) for [ctx: {}, align: {}, cache: {}, cache bytes: {}]",
         best[0].0,
         best[0].1,
         best[0].1.div_f64(buf.len() as f64 * 8.0),
         params[0].0,
         params[0].1,
-        params[0].2
+        params[0].2,
+        params[0].3
     );
 
     Ok(())
 }
 
-fn exec(
+fn exec<M: ACHashModel>(
     buf: &[u8],
     ctx_bits: u8,
     alignment_bits: u8,
-    history: impl History,
-    cache_size: u8,
+    history: ACHistoryCached<M>,
 ) -> Result<(u64, Duration)> {
     let timer = Instant::now();
     let mut ac = ArithmeticCoder::new_coder();
@@ -102,15 +111,22 @@ fn exec(
     ac.flush(&mut writer)?;
 
     let time = timer.elapsed();
+    let history = model.history();
+    let stats = history.stats();
     println!(
-        "[eh-ac] [ctx: {:2}, align: {} cache: {:2}] csize: {} (ratio {:.3}), ctime: {:?} ({:?} per bit)",
+        "[eh-ac] [ctx: {:2}, align: {} cache: {:2}, {:5} KB] csize: {} (ratio {:.3}), ctime: {:?} ({:?} per bit), hit rate: {:.3} ({} hits, {} misses, {} evictions)",
         ctx_bits,
         alignment_bits,
-        cache_size,
+        history.cache_size(),
+        history.cache_memory() >> 10,
         writer.result(),
         writer.result() as f64 / buf.len() as f64,
         time,
-        time.div_f64(buf.len() as f64 * 8.0)
+        time.div_f64(buf.len() as f64 * 8.0),
+        stats.hit_rate(),
+        stats.hits,
+        stats.misses,
+        stats.evictions
     );
 
     Ok((writer.result(), time))
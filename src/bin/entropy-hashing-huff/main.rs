@@ -8,6 +8,10 @@ use weath3rb0i::{
     u64, unroll_for,
 };
 
+// bytes between rebuilds of the adaptive code, a decoder rebuilds it the same
+// way from the bytes it decoded
+const REBUILD_INTERVAL: u64 = 4096;
+
 fn main() -> Result<()> {
     let buf = std::fs::read("/Users/mitiko/_data/book1")?;
 
@@ -15,7 +19,8 @@ fn main() -> Result<()> {
     let mut best = vec![u64!(buf.len()); levels];
     let mut params = vec![(0, 0, 0); levels];
 
-    for rem_huff_size in 7..=12 {
+    // adaptive codes cover all bytes, at least 8 bits
+    for rem_huff_size in 8..=12 {
         best[1] = u64!(buf.len());
         params[1] = (0, 0, 0);
         for huff_size in 8..=15 {
@@ -75,7 +80,7 @@ fn exec(buf: &[u8], huff_size: u8, rem_huff_size: u8, ctx_bits: u8) -> Result<u6
 
 fn compress(buf: &[u8], huff_size: u8, rem_huff_size: u8, ctx_bits: u8) -> Result<u64> {
     let mut ac = ArithmeticCoder::new_coder();
-    let history = HuffHistory::new(buf, huff_size, rem_huff_size);
+    let history = HuffHistory::adaptive(huff_size, rem_huff_size, REBUILD_INTERVAL);
     let mut model = OrderNEntropy::new(ctx_bits, 0, history);
     let mut writer = ACStats::new();
 
@@ -0,0 +1,37 @@
+use std::{fs, io::Result, path::PathBuf};
+
+use weath3rb0i::corpus::corpus;
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 || args.len() > 4 {
+        print_usage_and_exit("Invokation doesn't match usage!");
+    }
+    let out_dir = PathBuf::from(&args[1]);
+    let len = match args.get(2).map(|x| x.parse()) {
+        None => 1 << 20,
+        Some(Ok(len)) => len,
+        Some(Err(_)) => print_usage_and_exit("<Len> must be a number!"),
+    };
+    let seed = match args.get(3).map(|x| x.parse()) {
+        None => 0,
+        Some(Ok(seed)) => seed,
+        Some(Err(_)) => print_usage_and_exit("<Seed> must be a number!"),
+    };
+
+    fs::create_dir_all(&out_dir)?;
+    for (kind, buf) in corpus(len, seed) {
+        let path = out_dir.join(kind.name());
+        fs::write(&path, &buf)?;
+        println!("[gen-corpus] {} ({} bytes)", path.display(), buf.len());
+    }
+
+    Ok(())
+}
+
+fn print_usage_and_exit(msg: &str) -> ! {
+    println!("Usage: gen-corpus <OutDir> [Len] [Seed]");
+    println!("<Len> bytes per file, defaults to 1 MiB; <Seed> defaults to 0");
+    println!("\n{}", msg);
+    std::process::exit(1);
+}
@@ -0,0 +1,80 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Result},
+};
+
+use weath3rb0i::state_table::{
+    generate_state_table, train_state_table, StateTable, StateTableParams,
+};
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let mut params = StateTableParams::default();
+    let mut train = None;
+    let mut csv = None;
+    let mut dot = None;
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let Some(value) = iter.next() else {
+            print_usage_and_exit(&format!("Missing value for {}", arg));
+        };
+        match arg.as_str() {
+            "--max-level" => params.max_level = parse(value, arg),
+            "--discount" => params.discount = parse(value, arg),
+            "--train" => train = Some(value),
+            "--csv" => csv = Some(value),
+            "--dot" => dot = Some(value),
+            _ => print_usage_and_exit(&format!("Unexpected argument: {}", arg)),
+        }
+    }
+    if !(1..=StateTableParams::MAX_LEVEL).contains(&params.max_level) || params.discount == 0 {
+        print_usage_and_exit("Parameters out of range!");
+    }
+
+    // info goes to stderr, the table may be written to stdout
+    let mut table = generate_state_table(&params);
+    eprintln!(
+        "[gen-state-table] [max_level: {}, discount: {}] states: {}",
+        params.max_level,
+        params.discount,
+        table.size()
+    );
+    if let Some(path) = train {
+        let buf = fs::read(path)?;
+        table = train_state_table(&table, &buf);
+        eprintln!(
+            "[gen-state-table] trained on {} ({} bytes)",
+            path,
+            buf.len()
+        );
+    }
+
+    match csv {
+        Some(path) => table.write_csv(BufWriter::new(File::create(path)?))?,
+        None if dot.is_none() => table.write_csv(std::io::stdout().lock())?,
+        None => {}
+    }
+    if let Some(path) = dot {
+        table.write_dot(BufWriter::new(File::create(path)?))?;
+    }
+
+    Ok(())
+}
+
+fn parse(value: &str, arg: &str) -> usize {
+    match value.parse() {
+        Ok(value) => value,
+        Err(_) => print_usage_and_exit(&format!("{} expects a number", arg)),
+    }
+}
+
+fn print_usage_and_exit(msg: &str) -> ! {
+    println!("Usage: gen-state-table [--max-level <N>] [--discount <D>] [--train <File>] [--csv <Out>] [--dot <Out>]");
+    println!("--max-level: levels of counts before discounting, 1..=44 (default 44)");
+    println!("--discount: divide counts by this at the max level (default 2)");
+    println!("--train: set probabilities from bit histories observed in a file");
+    println!("--csv/--dot: where to export the table, CSV goes to stdout by default");
+    println!("\n{}", msg);
+    std::process::exit(1);
+}
@@ -0,0 +1,200 @@
+//! Framing of block streams: every block of the coded stream is either
+//! arithmetic coded or, when that would be larger, stored as is
+//!
+//! `[kind: u8][payload length: u32 BE][payload]`, the block's decoded length
+//! follows from the block size in the header.
+//!
+//! Seekable streams end in an index of every block's (uncompressed offset,
+//! compressed offset), as u64 BE pairs, followed by the number of blocks
+//! (u64 BE) and `INDEX_MAGIC`, so readers can find it from the end.
+
+use std::io::{self, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+
+use crate::u32;
+
+/// Largest block size the header may declare, payload lengths fit in a u32
+pub const MAX_BLOCK_SIZE: u64 = 1 << 30;
+
+const INDEX_MAGIC: &[u8; 4] = b"w30x";
+// block count and magic
+const INDEX_TRAILER_SIZE: u64 = 12;
+const INDEX_ENTRY_SIZE: u64 = 16;
+// kind and payload length
+const BLOCK_FRAME_SIZE: u64 = 5;
+
+const MODELED: u8 = 0;
+const STORED: u8 = 1;
+
+pub enum Block {
+    /// The arithmetic coded bytes
+    Modeled(Vec<u8>),
+    Stored(Vec<u8>),
+}
+
+/// Writes `raw` as its `coded` form, unless that's no smaller, returns the
+/// bytes written
+pub fn write_block(writer: &mut impl Write, raw: &[u8], coded: &[u8]) -> io::Result<u64> {
+    let (kind, payload) = match coded.len() < raw.len() {
+        true => (MODELED, coded),
+        false => (STORED, raw),
+    };
+    writer.write_all(&[kind])?;
+    writer.write_all(&u32!(payload.len()).to_be_bytes())?;
+    writer.write_all(payload)?;
+    Ok(BLOCK_FRAME_SIZE + payload.len() as u64)
+}
+
+/// Reads a block that decodes to `len` bytes
+pub fn read_block(reader: &mut impl Read, len: u64) -> io::Result<Block> {
+    let mut buf = [0; 5];
+    reader.read_exact(&mut buf)?;
+    let payload_len = u64::from(u32::from_be_bytes(buf[1..].try_into().unwrap()));
+    let valid = match buf[0] {
+        MODELED => payload_len < len,
+        STORED => payload_len == len,
+        _ => false,
+    };
+    if !valid {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Invalid block of kind {} with {} bytes",
+                buf[0], payload_len
+            ),
+        ));
+    }
+
+    let mut payload = Vec::new();
+    reader.take(payload_len).read_to_end(&mut payload)?;
+    if payload.len() as u64 != payload_len {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Block ended early"));
+    }
+    Ok(match buf[0] {
+        MODELED => Block::Modeled(payload),
+        _ => Block::Stored(payload),
+    })
+}
+
+/// (uncompressed offset, compressed offset) of every block
+pub type Index = Vec<(u64, u64)>;
+
+pub fn write_index(writer: &mut impl Write, index: &Index) -> io::Result<()> {
+    for &(uncompressed, compressed) in index {
+        writer.write_all(&uncompressed.to_be_bytes())?;
+        writer.write_all(&compressed.to_be_bytes())?;
+    }
+    writer.write_all(&(index.len() as u64).to_be_bytes())?;
+    writer.write_all(INDEX_MAGIC)
+}
+
+/// Reads the index that follows the last block, it must have `blocks` entries
+pub fn read_index(reader: &mut impl Read, blocks: u64) -> io::Result<Index> {
+    // don't trust the count with an allocation, the reads fail soon enough
+    let mut index = Vec::new();
+    for _ in 0..blocks {
+        let mut buf = [0; INDEX_ENTRY_SIZE as usize];
+        reader.read_exact(&mut buf)?;
+        let uncompressed = u64::from_be_bytes(buf[..8].try_into().unwrap());
+        let compressed = u64::from_be_bytes(buf[8..].try_into().unwrap());
+        index.push((uncompressed, compressed));
+    }
+    let mut buf = [0; INDEX_TRAILER_SIZE as usize];
+    reader.read_exact(&mut buf)?;
+    if u64::from_be_bytes(buf[..8].try_into().unwrap()) != blocks || &buf[8..] != INDEX_MAGIC {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Invalid seek index trailer",
+        ));
+    }
+    Ok(index)
+}
+
+/// Finds the index from the end of a seekable stream and checks it against
+/// the stream's layout: blocks of `block_size` bytes of the `len` byte coded
+/// stream, the first starting at `start` - returns it and where the last
+/// block ends
+pub fn seek_index(
+    reader: &mut (impl Read + Seek),
+    len: u64,
+    block_size: u64,
+    start: u64,
+) -> io::Result<(Index, u64)> {
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_owned());
+    let blocks = len.div_ceil(block_size);
+    let index_size = blocks
+        .checked_mul(INDEX_ENTRY_SIZE)
+        .and_then(|size| size.checked_add(INDEX_TRAILER_SIZE))
+        .ok_or_else(|| invalid("Seek index too large"))?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    let index_start = end
+        .checked_sub(index_size)
+        .filter(|&index_start| index_start >= start)
+        .ok_or_else(|| invalid("Stream too short for its seek index"))?;
+    reader.seek(SeekFrom::Start(index_start))?;
+    let index = read_index(reader, blocks)?;
+
+    // blocks follow each other, each at least a frame long
+    let mut next = start;
+    for (i, &(uncompressed, compressed)) in index.iter().enumerate() {
+        if uncompressed != i as u64 * block_size || compressed < next {
+            return Err(invalid("Seek index doesn't match the blocks"));
+        }
+        next = compressed + BLOCK_FRAME_SIZE;
+    }
+    if next > index_start {
+        return Err(invalid("Seek index points past the blocks"));
+    }
+    Ok((index, index_start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut buf = Vec::new();
+        write_block(&mut buf, b"hello", b"hi").unwrap();
+        write_block(&mut buf, b"hello", b"hello").unwrap();
+        let mut reader = buf.as_slice();
+        assert!(matches!(read_block(&mut reader, 5).unwrap(), Block::Modeled(x) if x == b"hi"));
+        assert!(matches!(read_block(&mut reader, 5).unwrap(), Block::Stored(x) if x == b"hello"));
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn index() {
+        let mut buf = vec![0; 40];
+        let index = vec![(0, 10), (100, 20), (200, 30)];
+        write_index(&mut buf, &index).unwrap();
+        let mut reader = std::io::Cursor::new(buf);
+        assert_eq!(
+            seek_index(&mut reader, 250, 100, 10).unwrap(),
+            (index.clone(), 40)
+        );
+        reader.set_position(40);
+        assert_eq!(read_index(&mut reader, 3).unwrap(), index);
+
+        for (len, block_size, start) in [(350, 100, 10), (250, 50, 10), (250, 100, 11)] {
+            let err = seek_index(&mut reader, len, block_size, start).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+        reader.set_position(40);
+        let err = read_index(&mut reader, 2).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_bad_blocks() {
+        let mut buf = Vec::new();
+        write_block(&mut buf, b"hello", b"hi").unwrap();
+        // a modeled block as long as its bytes is never written
+        let err = read_block(&mut buf.as_slice(), 2).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = read_block(&mut &buf[..6], 5).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        buf[0] = 2;
+        let err = read_block(&mut buf.as_slice(), 5).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,377 @@
+use std::io::{self, BufRead, Error, ErrorKind, Read, Write};
+
+use super::{
+    check_len, encode_bits, read_sample, ByteBits, ByteCoding, Header, ModelConfig, Options,
+    Preset, Summary,
+};
+use crate::entropy_coding::{arithmetic_coder::ArithmeticCoder, io::ACWriter};
+use crate::models::Model;
+use crate::state::{read_bytes, State};
+
+const CHECKPOINT_MAGIC: &[u8; 4] = b"w30k";
+
+/// Where an interrupted `compress_with_checkpoints` continues from
+///
+/// To resume, the input must be read from `input_pos` on and the output cut
+/// back to `output_pos` bytes - anything written after the checkpoint is
+/// written again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Bytes of the input coded so far
+    pub input_pos: u64,
+    /// Bytes of the output complete so far
+    pub output_pos: u64,
+    pub header: Header,
+    /// The models', arithmetic coder's and its writer's state, empty before
+    /// the first byte
+    state: Vec<u8>,
+}
+
+impl Checkpoint {
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_all(&self.input_pos.to_be_bytes())?;
+        writer.write_all(&self.output_pos.to_be_bytes())?;
+        self.header.write(writer)?;
+        writer.write_all(&(self.state.len() as u64).to_be_bytes())?;
+        writer.write_all(&self.state)
+    }
+
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_owned());
+        if &read_bytes::<4>(reader)? != CHECKPOINT_MAGIC {
+            return Err(invalid("Not a weath3rb0i checkpoint"));
+        }
+        let input_pos = u64::from_be_bytes(read_bytes(reader)?);
+        let output_pos = u64::from_be_bytes(read_bytes(reader)?);
+        let header = Header::read(reader)?;
+        if input_pos > header.len || output_pos < header.size() as u64 {
+            return Err(invalid("Checkpoint past the end of the stream"));
+        }
+        let state_len = u64::from_be_bytes(read_bytes(reader)?);
+        let mut state = Vec::new();
+        reader.take(state_len).read_to_end(&mut state)?;
+        if state.len() as u64 != state_len {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Checkpoint ended early",
+            ));
+        }
+        Ok(Self { input_pos, output_pos, header, state })
+    }
+}
+
+/// Compresses like `compress_with`, to the same output, handing a checkpoint
+/// to `save` after every `interval` bytes of input - except a stream the
+/// models expand isn't stored instead, the output is gone by then
+///
+/// `save` also gets the writer, flushed up to the checkpoint: a checkpoint
+/// must not outlive the output it covers, so make that durable first (e.g.
+/// `File::sync_data`).
+///
+/// Only single streams can be checkpointed: no Huffman coding, transforms,
+/// blocks or seekable streams.
+pub fn compress_with_checkpoints<W: Write>(
+    reader: impl BufRead,
+    len: u64,
+    writer: W,
+    options: &Options,
+    interval: u64,
+    save: impl FnMut(&Checkpoint, &mut W) -> io::Result<()>,
+) -> io::Result<Summary> {
+    options.check()?;
+    if options.huffman
+        || options.dedup
+        || options.text.is_some()
+        || options.block_size.is_some()
+        || options.seekable
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Checkpoints need a single stream without Huffman coding, transforms or blocks",
+        ));
+    }
+    let mut reader = reader;
+    let mut sample = Vec::new();
+    let preset = match options.preset {
+        Some(preset) => preset,
+        None => {
+            sample = read_sample(&mut reader)?;
+            Preset::detect(&sample)
+        }
+    };
+    let reader = sample.as_slice().chain(reader);
+    let mut header = Header::new(len, options.memory).preset(preset);
+    if preset == Preset::Text {
+        header = header.models(options.models);
+    }
+    let mut writer = Counted { inner: writer, written: 0 };
+    header.write(&mut writer)?;
+    let start = Checkpoint {
+        input_pos: 0,
+        output_pos: writer.written,
+        header,
+        state: Vec::new(),
+    };
+    compress_from(reader, writer, &start, interval, save)
+}
+
+/// Continues `compress_with_checkpoints` from `checkpoint`, `reader` must
+/// start at its `input_pos` and `writer` at its `output_pos`
+pub fn resume_compression<W: Write>(
+    reader: impl BufRead,
+    writer: W,
+    checkpoint: &Checkpoint,
+    interval: u64,
+    save: impl FnMut(&Checkpoint, &mut W) -> io::Result<()>,
+) -> io::Result<Summary> {
+    let writer = Counted { inner: writer, written: checkpoint.output_pos };
+    compress_from(reader, writer, checkpoint, interval, save)
+}
+
+fn compress_from<W: Write>(
+    reader: impl BufRead,
+    writer: Counted<W>,
+    checkpoint: &Checkpoint,
+    interval: u64,
+    save: impl FnMut(&Checkpoint, &mut W) -> io::Result<()>,
+) -> io::Result<Summary> {
+    assert!(interval > 0, "Checkpoints need a positive interval");
+    let header = &checkpoint.header;
+    let config = ModelConfig::for_header(header);
+    match header.preset {
+        Preset::Store => store_from(reader, writer, checkpoint, interval, save),
+        Preset::Binary => {
+            let mut coder = Coder::new(config.init_binary_model(), writer);
+            coder.run(reader, checkpoint, interval, save)
+        }
+        Preset::Text => {
+            let mut coder = Coder::new(config.init_model(), writer);
+            coder.run(reader, checkpoint, interval, save)
+        }
+    }
+}
+
+/// The model, coder and writer of a single stream
+struct Coder<M: Model + State, W: Write> {
+    model: M,
+    ac: ArithmeticCoder<ACWriter<Counted<W>>>,
+    writer: ACWriter<Counted<W>>,
+}
+
+impl<M: Model + State, W: Write> Coder<M, W> {
+    fn new(model: M, writer: Counted<W>) -> Self {
+        Self {
+            model,
+            ac: ArithmeticCoder::new_coder(),
+            writer: ACWriter::new(writer),
+        }
+    }
+
+    fn run(
+        &mut self,
+        mut reader: impl BufRead,
+        checkpoint: &Checkpoint,
+        interval: u64,
+        mut save: impl FnMut(&Checkpoint, &mut W) -> io::Result<()>,
+    ) -> io::Result<Summary> {
+        if !checkpoint.state.is_empty() {
+            let mut state = checkpoint.state.as_slice();
+            self.model.read_state(&mut state)?;
+            self.ac.read_state(&mut state)?;
+            self.writer.read_state(&mut state)?;
+        }
+
+        let len = checkpoint.header.len;
+        let mut pos = checkpoint.input_pos;
+        let mut chunk = Vec::new();
+        while pos < len {
+            chunk.clear();
+            (&mut reader)
+                .take(interval.min(len - pos))
+                .read_to_end(&mut chunk)?;
+            if chunk.is_empty() {
+                break;
+            }
+            for &byte in &chunk {
+                encode_bits(
+                    ByteBits.bits(byte),
+                    &mut self.model,
+                    &mut self.ac,
+                    &mut self.writer,
+                )?;
+            }
+            pos += chunk.len() as u64;
+            if pos < len {
+                let checkpoint = self.checkpoint(checkpoint, pos)?;
+                save(&checkpoint, &mut self.writer.get_mut().inner)?;
+            }
+        }
+        check_len(pos, len)?;
+        self.ac.flush(&mut self.writer)?;
+        Ok(Summary {
+            preset: checkpoint.header.preset,
+            memory_usage: self.model.memory_usage(),
+        })
+    }
+
+    fn checkpoint(&mut self, last: &Checkpoint, input_pos: u64) -> io::Result<Checkpoint> {
+        // the checkpoint only covers what reached the inner writer
+        self.writer.get_mut().flush()?;
+        let mut state = Vec::new();
+        self.model.write_state(&mut state)?;
+        self.ac.write_state(&mut state)?;
+        self.writer.write_state(&mut state)?;
+        Ok(Checkpoint {
+            input_pos,
+            output_pos: self.writer.get_mut().written,
+            header: last.header.clone(),
+            state,
+        })
+    }
+}
+
+/// Copies the input behind a `Preset::Store` header, the position is all the
+/// state there is
+fn store_from<W: Write>(
+    mut reader: impl BufRead,
+    mut writer: Counted<W>,
+    checkpoint: &Checkpoint,
+    interval: u64,
+    mut save: impl FnMut(&Checkpoint, &mut W) -> io::Result<()>,
+) -> io::Result<Summary> {
+    let len = checkpoint.header.len;
+    let mut pos = checkpoint.input_pos;
+    while pos < len {
+        let copied = io::copy(
+            &mut (&mut reader).take(interval.min(len - pos)),
+            &mut writer,
+        )?;
+        if copied == 0 {
+            break;
+        }
+        pos += copied;
+        writer.flush()?;
+        if pos < len {
+            let checkpoint = Checkpoint {
+                input_pos: pos,
+                output_pos: writer.written,
+                header: checkpoint.header.clone(),
+                state: Vec::new(),
+            };
+            save(&checkpoint, &mut writer.inner)?;
+        }
+    }
+    check_len(pos, len)?;
+    writer.flush()?;
+    Ok(Summary { preset: Preset::Store, ..Summary::default() })
+}
+
+/// Counts the bytes written through it
+struct Counted<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::{compress_with, decompress, HashModel, TextModels};
+    use crate::corpus::{generate, Kind};
+
+    #[test]
+    fn resumed_output_is_identical() {
+        let mut input = generate(Kind::Markov, 10 << 10, 50);
+        input.extend(generate(Kind::Repeated, 6 << 10, 50));
+        let len = input.len() as u64;
+        let hash = HashModel::Adaptive1;
+        let models = TextModels { states: true, run: true, bytes: true, hash };
+        let models = Options::default().models(models);
+        let presets = Preset::ALL.map(|preset| Options::default().preset(preset));
+        for options in presets.into_iter().chain([models]) {
+            let options = options.memory(1 << 16);
+            let mut plain = Vec::new();
+            compress_with(input.as_slice(), len, &mut plain, &options).unwrap();
+
+            let mut checkpoints = Vec::new();
+            let mut compressed = Vec::new();
+            let save = |checkpoint: &Checkpoint, output: &mut &mut Vec<u8>| {
+                // all the checkpoint covers has been written
+                assert_eq!(output.len() as u64, checkpoint.output_pos);
+                let mut buf = Vec::new();
+                checkpoint.write(&mut buf)?;
+                checkpoints.push(buf);
+                Ok(())
+            };
+            compress_with_checkpoints(input.as_slice(), len, &mut compressed, &options, 3000, save)
+                .unwrap();
+            assert_eq!(compressed, plain, "{:?}", options);
+            assert_eq!(checkpoints.len(), 5);
+
+            // as if compression died right after each checkpoint
+            for buf in checkpoints {
+                let checkpoint = Checkpoint::read(&mut buf.as_slice()).unwrap();
+                let mut resumed = plain[..checkpoint.output_pos as usize].to_vec();
+                let rest = &input[checkpoint.input_pos as usize..];
+                resume_compression(rest, &mut resumed, &checkpoint, 3000, |_, _| Ok(())).unwrap();
+                assert_eq!(
+                    resumed, plain,
+                    "{:?} from {}",
+                    options, checkpoint.input_pos
+                );
+            }
+            let mut decompressed = Vec::new();
+            decompress(plain.as_slice(), &mut decompressed).unwrap();
+            assert_eq!(decompressed, input);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_checkpoints() {
+        let input = generate(Kind::Markov, 4 << 10, 51);
+        let len = input.len() as u64;
+        let options = Options::default().preset(Preset::Text);
+        let mut checkpoint = None;
+        let save = |last: &Checkpoint, _: &mut io::Sink| {
+            checkpoint = Some(last.clone());
+            Ok(())
+        };
+        compress_with_checkpoints(input.as_slice(), len, io::sink(), &options, 1000, save).unwrap();
+        let checkpoint = checkpoint.unwrap();
+
+        let mut buf = Vec::new();
+        checkpoint.write(&mut buf).unwrap();
+        let err = Checkpoint::read(&mut &buf[..buf.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        buf[0] = b'x';
+        let err = Checkpoint::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // the input ends before the header's length
+        let rest = &input[checkpoint.input_pos as usize + 1..];
+        let err =
+            resume_compression(rest, io::sink(), &checkpoint, 1000, |_, _| Ok(())).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        for options in [options.clone().huffman(true), options.seekable(true)] {
+            let err =
+                compress_with_checkpoints(&input[..], len, io::sink(), &options, 1000, |_, _| {
+                    Ok(())
+                })
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use crate::helpers::histogram;
+use std::collections::HashSet;
+use std::io::{self, Read};
+
+/// The model setup for an input, recorded in the header
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Preset {
+    /// Entropy hashed contexts tuned on English text
+    #[default]
+    Text,
+    /// Order-1 contexts, no assumptions about the byte values
+    Binary,
+    /// No modeling, the input is copied as is - for random or already
+    /// compressed data that any model would only expand
+    Store,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 3] = [Preset::Text, Preset::Binary, Preset::Store];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::Text => "text",
+            Preset::Binary => "binary",
+            Preset::Store => "store",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|preset| preset.name() == name)
+    }
+
+    /// Picks the preset for an input starting with `sample`, only the first
+    /// `SAMPLE_SIZE` bytes are looked at
+    pub fn detect(sample: &[u8]) -> Self {
+        let sample = &sample[..sample.len().min(SAMPLE_SIZE)];
+        let stats = SampleStats::new(sample);
+        if sample.len() >= MIN_STORE_SAMPLE
+            && stats.entropy >= STORE_ENTROPY
+            && stats.repeats <= MAX_STORE_REPEATS
+        {
+            Preset::Store
+        } else if stats.utf8
+            && stats.control <= MAX_TEXT_CONTROL
+            && stats.mean_line_len <= MAX_TEXT_LINE
+        {
+            Preset::Text
+        } else {
+            Preset::Binary
+        }
+    }
+}
+
+/// Bytes of the input `Preset::detect` looks at
+pub const SAMPLE_SIZE: usize = 1 << 16;
+
+/// The first `SAMPLE_SIZE` bytes of `reader` (fewer for shorter inputs), no
+/// matter how much of them it buffers
+pub fn read_sample(reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut sample = Vec::new();
+    reader.take(SAMPLE_SIZE as u64).read_to_end(&mut sample)?;
+    Ok(sample)
+}
+// smaller samples can't show an order-0 entropy close to 8 bits
+const MIN_STORE_SAMPLE: usize = 1 << 10;
+// bits per byte from which the coder's overhead isn't worth it
+const STORE_ENTROPY: f64 = 7.8;
+// fraction of repeated 4-grams from which high entropy data still compresses
+const MAX_STORE_REPEATS: f64 = 0.05;
+// fraction of control bytes (other than whitespace) text may have
+const MAX_TEXT_CONTROL: f64 = 0.01;
+const MAX_TEXT_LINE: f64 = 4096.0;
+
+/// What `Preset::detect` looks at
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampleStats {
+    /// Order-0 entropy in bits per byte
+    pub entropy: f64,
+    /// Valid UTF-8, except maybe a character cut off at the end
+    pub utf8: bool,
+    /// Fraction of ASCII control bytes other than tab, line breaks and form feed
+    pub control: f64,
+    /// Average bytes per line, lines end in '\n'
+    pub mean_line_len: f64,
+    /// Fraction of 4-grams seen before in the sample
+    pub repeats: f64,
+}
+
+impl SampleStats {
+    pub fn new(sample: &[u8]) -> Self {
+        let counts = histogram(sample);
+        let len = sample.len().max(1) as f64;
+        let entropy = counts
+            .iter()
+            .filter(|&&count| count != 0)
+            .map(|&count| {
+                let p = f64::from(count) / len;
+                -p * p.log2()
+            })
+            .sum();
+        let utf8 = match std::str::from_utf8(sample) {
+            Ok(_) => true,
+            Err(err) => err.error_len().is_none(),
+        };
+        let control: u32 = (0..0x20)
+            .chain([0x7f])
+            .filter(|byte| !b"\t\n\r\x0c".contains(byte))
+            .map(|byte| counts[usize::from(byte)])
+            .sum();
+        let lines = counts[usize::from(b'\n')] + 1;
+        let mut seen = HashSet::new();
+        let repeats = sample
+            .windows(4)
+            .filter(|window| !seen.insert(u32::from_le_bytes((*window).try_into().unwrap())))
+            .count();
+        Self {
+            entropy,
+            utf8,
+            control: f64::from(control) / len,
+            mean_line_len: len / f64::from(lines),
+            repeats: repeats as f64 / len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::{corpus, Kind};
+
+    #[test]
+    fn corpus_presets() {
+        for (kind, input) in corpus(1 << 12, 0) {
+            let expected = match kind {
+                Kind::Random => Preset::Store,
+                Kind::Markov | Kind::Empty => Preset::Text,
+                // a single byte says nothing about the data
+                Kind::SingleByte => continue,
+                _ => Preset::Binary,
+            };
+            assert_eq!(Preset::detect(&input), expected, "{}", kind.name());
+        }
+    }
+
+    #[test]
+    fn text_samples() {
+        assert_eq!(
+            Preset::detect("Grüße\r\n\tfrom the README".as_bytes()),
+            Preset::Text
+        );
+        // a multi-byte character cut off by the sample
+        assert_eq!(Preset::detect(&"ü".as_bytes()[..1]), Preset::Text);
+        assert_eq!(Preset::detect(b"\xff\xfe text"), Preset::Binary);
+        assert_eq!(Preset::detect(&[b'a'; 5000]), Preset::Binary);
+        assert_eq!(Preset::detect(b"\x00\x01\x02\x03 text"), Preset::Binary);
+    }
+
+    #[test]
+    fn names() {
+        for preset in Preset::ALL {
+            assert_eq!(Preset::from_name(preset.name()), Some(preset));
+        }
+        assert_eq!(Preset::from_name("auto"), None);
+    }
+}
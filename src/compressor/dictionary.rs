@@ -0,0 +1,275 @@
+use std::fmt;
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+use super::{Header, ModelConfig, Preset, MAX_MEMORY};
+use crate::helpers::{hash_bytes, hash_u64};
+use crate::models::{train, Model, Tables};
+use crate::u8;
+
+const DICT_MAGIC: &[u8; 4] = b"w30d";
+
+/// Model tables trained on a corpus of inputs like the ones to compress, so
+/// small inputs don't spend their whole output on teaching cold models
+///
+/// Both sides start from the trained tables, streams record the dictionary's
+/// `id` and only decode with the same dictionary.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Dictionary {
+    /// Hash of everything below, identifies the dictionary in headers
+    pub id: u64,
+    /// Memory budget the models were sized with, streams use the same
+    pub memory: u64,
+    /// The models trained, streams use the same
+    pub preset: Preset,
+    tables: Vec<u8>,
+}
+
+impl Dictionary {
+    /// Trains the models `memory` and `preset` describe on `corpus`
+    pub fn train(corpus: &[u8], memory: u64, preset: Preset) -> Self {
+        assert!(
+            preset != Preset::Store,
+            "Stored streams have no models to train"
+        );
+        let config = ModelConfig::new(memory, preset, false);
+        let tables = match preset {
+            Preset::Binary => trained_tables(config.init_binary_model(), corpus),
+            _ => trained_tables(config.init_model(), corpus),
+        };
+        let id = dictionary_id(memory, preset, &tables);
+        Self { id, memory, preset, tables }
+    }
+
+    /// Bytes of the trained tables
+    pub fn size(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// Loads the trained tables into `model`, which `ModelConfig` built with
+    /// the dictionary's memory and preset
+    pub(super) fn prime(&self, model: &mut impl Tables) {
+        model
+            .read_tables(&mut self.tables.as_slice())
+            .expect("Dictionary::read checks the tables fit the models");
+    }
+
+    /// The dictionary to decode the stream behind `header` with, fails with
+    /// `InvalidInput` unless `dictionary` is the one it was compressed with
+    pub(super) fn for_header<'a>(
+        header: &Header,
+        dictionary: Option<&'a Self>,
+    ) -> io::Result<Option<&'a Self>> {
+        let Some(id) = header.dictionary else {
+            return Ok(None);
+        };
+        match dictionary {
+            Some(dictionary)
+                if dictionary.id == id
+                    && dictionary.memory == header.memory
+                    && dictionary.preset == header.preset =>
+            {
+                Ok(Some(dictionary))
+            }
+            Some(dictionary) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Stream needs dictionary {:016x}, got {:016x}",
+                    id, dictionary.id
+                ),
+            )),
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Stream needs dictionary {:016x}", id),
+            )),
+        }
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let preset = Preset::ALL.iter().position(|&preset| preset == self.preset);
+        writer.write_all(DICT_MAGIC)?;
+        writer.write_all(&self.id.to_be_bytes())?;
+        writer.write_all(&self.memory.to_be_bytes())?;
+        writer.write_all(&[u8!(preset.unwrap())])?;
+        writer.write_all(&self.tables)
+    }
+
+    /// Parses what `write` wrote, checking the tables against the id
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_owned());
+        let mut buf = [0; 21];
+        reader.read_exact(&mut buf)?;
+        if &buf[..4] != DICT_MAGIC {
+            return Err(invalid("Not a weath3rb0i dictionary"));
+        }
+        let id = u64::from_be_bytes(buf[4..12].try_into().unwrap());
+        let memory = u64::from_be_bytes(buf[12..20].try_into().unwrap());
+        if memory > MAX_MEMORY {
+            return Err(invalid("Invalid dictionary memory budget"));
+        }
+        let preset = match Preset::ALL.get(usize::from(buf[20])) {
+            Some(&preset) if preset != Preset::Store => preset,
+            _ => return Err(invalid("Invalid dictionary preset")),
+        };
+
+        let config = ModelConfig::new(memory, preset, false);
+        let mut tables = Vec::new();
+        reader
+            .take(config.memory() as u64 + 1)
+            .read_to_end(&mut tables)?;
+        if tables.len() != config.memory() {
+            return Err(invalid("Dictionary tables don't fit its models"));
+        }
+        if dictionary_id(memory, preset, &tables) != id {
+            return Err(invalid("Dictionary tables don't match its id"));
+        }
+        let dictionary = Self { id, memory, preset, tables };
+        // the counters must be valid too, `prime` can't fail
+        match preset {
+            Preset::Binary => dictionary.validate(config.init_binary_model())?,
+            _ => dictionary.validate(config.init_model())?,
+        }
+        Ok(dictionary)
+    }
+
+    fn validate(&self, mut model: impl Tables) -> io::Result<()> {
+        model.read_tables(&mut self.tables.as_slice())
+    }
+}
+
+// the tables are as large as the models, leave them out
+impl fmt::Debug for Dictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dictionary")
+            .field("id", &format_args!("{:016x}", self.id))
+            .field("memory", &self.memory)
+            .field("preset", &self.preset)
+            .finish_non_exhaustive()
+    }
+}
+
+fn trained_tables(mut model: impl Model + Tables, corpus: &[u8]) -> Vec<u8> {
+    train(&mut model, corpus);
+    let mut tables = Vec::new();
+    model
+        .write_tables(&mut tables)
+        .expect("Writing to a Vec can't fail");
+    tables
+}
+
+fn dictionary_id(memory: u64, preset: Preset, tables: &[u8]) -> u64 {
+    hash_bytes(hash_u64(memory) ^ preset as u64, tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::{
+        compress_with, compress_with_dictionary, decompress, decompress_with_dictionary, Limits,
+        Options, SeekableReader,
+    };
+    use crate::corpus::{generate, Kind};
+    use std::io::{Cursor, Read};
+
+    fn compress(input: &[u8], options: &Options, dictionary: Option<&Dictionary>) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        let len = input.len() as u64;
+        match dictionary {
+            Some(dictionary) => {
+                compress_with_dictionary(input, len, &mut compressed, options, dictionary)
+            }
+            None => compress_with(input, len, &mut compressed, options),
+        }
+        .unwrap();
+        compressed
+    }
+
+    #[test]
+    fn small_inputs() {
+        let corpus = generate(Kind::Markov, 1 << 14, 30);
+        let limits = Limits::unlimited();
+        for preset in [Preset::Text, Preset::Binary] {
+            let dictionary = Dictionary::train(&corpus, 1 << 16, preset);
+            let options = Options::default().memory(1 << 16).preset(preset);
+            for seed in 31..35 {
+                let input = generate(Kind::Markov, 500, seed);
+                let cold = compress(&input, &options, None);
+                let warm = compress(&input, &options, Some(&dictionary));
+                assert!(warm.len() < cold.len(), "{:?}", preset);
+
+                let mut decompressed = Vec::new();
+                let reader = warm.as_slice();
+                decompress_with_dictionary(reader, &mut decompressed, &limits, &dictionary)
+                    .unwrap();
+                assert_eq!(input, decompressed);
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_dictionary() {
+        let dictionary =
+            Dictionary::train(&generate(Kind::Markov, 1 << 12, 36), 1 << 12, Preset::Text);
+        let other = Dictionary::train(&generate(Kind::Markov, 1 << 12, 37), 1 << 12, Preset::Text);
+        assert_ne!(dictionary.id, other.id);
+        let input = generate(Kind::Markov, 1 << 10, 38);
+        let compressed = compress(&input, &Options::default(), Some(&dictionary));
+
+        let err = decompress(compressed.as_slice(), io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let limits = Limits::unlimited();
+        let err = decompress_with_dictionary(compressed.as_slice(), io::sink(), &limits, &other)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        // a dictionary a stream doesn't need is ignored
+        let plain = compress(&input, &Options::default(), None);
+        let mut decompressed = Vec::new();
+        decompress_with_dictionary(plain.as_slice(), &mut decompressed, &limits, &other).unwrap();
+        assert_eq!(input, decompressed);
+
+        let options = Options::default().huffman(true);
+        let err = compress_with_dictionary(&input[..], 1 << 10, io::sink(), &options, &dictionary)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn seekable() {
+        let dictionary =
+            Dictionary::train(&generate(Kind::Markov, 1 << 12, 39), 1 << 12, Preset::Text);
+        let input = generate(Kind::Markov, 3 << 10, 40);
+        let options = Options::default().seekable(true).blocks(1 << 10);
+        let compressed = compress(&input, &options, Some(&dictionary));
+        let err = SeekableReader::new(Cursor::new(&compressed)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let mut reader =
+            SeekableReader::with_dictionary(Cursor::new(&compressed), dictionary).unwrap();
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(input, decompressed);
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let dictionary = Dictionary::train(
+            &generate(Kind::Markov, 1 << 12, 41),
+            1 << 12,
+            Preset::Binary,
+        );
+        let mut buf = Vec::new();
+        dictionary.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), 21 + dictionary.size());
+        assert_eq!(Dictionary::read(&mut buf.as_slice()).unwrap(), dictionary);
+
+        let err = Dictionary::read(&mut &buf[..buf.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let last = buf.len() - 1;
+        buf[last] ^= 1;
+        let err = Dictionary::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        buf[20] = 2;
+        let err = Dictionary::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,624 @@
+use super::{blocks::MAX_BLOCK_SIZE, HashModel, Preset, TextModels, MAX_MEMORY};
+use crate::{
+    entropy_coding::huffman::CanonicalCode,
+    transform::{Dedup, TextTransform},
+};
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+pub const MAGIC_STR: &[u8; 4] = b"w30i";
+pub const MAGIC_NUM: u32 = u32::from_be_bytes(*MAGIC_STR);
+
+// bits of the flags byte
+const FLAG_HUFFMAN: u8 = 1;
+const FLAG_TEXT: u8 = 2;
+const FLAG_DEDUP: u8 = 4;
+const FLAG_BINARY: u8 = 8;
+const FLAG_STORE: u8 = 16;
+const FLAG_BLOCKS: u8 = 32;
+const FLAG_SEEKABLE: u8 = 64;
+// a second flags byte follows
+const FLAG_EXTENDED: u8 = 128;
+
+// bits of the extended flags byte
+const EXT_DICTIONARY: u8 = 1;
+const EXT_REFERENCE: u8 = 2;
+// a byte of `MODEL_*` bits follows
+const EXT_MODELS: u8 = 4;
+const KNOWN_EXT_FLAGS: u8 = EXT_DICTIONARY | EXT_REFERENCE | EXT_MODELS;
+
+// bits of the models byte
+const MODEL_STATES: u8 = 1;
+const MODEL_RUN: u8 = 2;
+const MODEL_BYTES: u8 = 4;
+// the hash model, at most one of them
+const MODEL_ADAPTIVE0: u8 = 8;
+const MODEL_ADAPTIVE1: u8 = 16;
+const KNOWN_MODELS: u8 = MODEL_STATES | MODEL_RUN | MODEL_BYTES | MODEL_ADAPTIVE0 | MODEL_ADAPTIVE1;
+
+/// The container header, written in front of the coded stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// Length of the original input in bytes
+    pub len: u64,
+    /// Memory budget the models were sized with
+    pub memory: u64,
+    /// The models used, `Store` means the input follows the header as is
+    pub preset: Preset,
+    /// Code lengths of every byte when bytes are coded as Huffman codewords
+    /// instead of their 8 bits
+    pub huffman: Option<Vec<u8>>,
+    /// Repeats cut out of the input, before the text transform
+    pub dedup: Option<Dedup>,
+    /// The text transform applied before modeling, the coded stream is
+    /// `TextTransform::len` bytes of transformed text
+    pub text: Option<TextTransform>,
+    /// Bytes of the coded stream per block, each block is modeled or stored
+    pub block_size: Option<u64>,
+    /// Every block starts with fresh models and the blocks are followed by a
+    /// seek index, needs blocks and no transforms
+    pub seekable: bool,
+    /// Id of the `Dictionary` the models start from
+    pub dictionary: Option<u64>,
+    /// Hash of the `Reference` the models were run over before the stream
+    pub reference: Option<u64>,
+    /// Models mixed into `Preset::Text`
+    pub models: TextModels,
+}
+
+impl Header {
+    /// Size of the fields every header has
+    pub const FIXED_SIZE: usize = std::mem::size_of::<u32>() + 2 * std::mem::size_of::<u64>() + 1;
+
+    pub fn new(len: u64, memory: u64) -> Self {
+        Self {
+            len,
+            memory,
+            preset: Preset::Text,
+            huffman: None,
+            dedup: None,
+            text: None,
+            block_size: None,
+            seekable: false,
+            dictionary: None,
+            reference: None,
+            models: TextModels::default(),
+        }
+    }
+
+    pub fn preset(mut self, preset: Preset) -> Self {
+        self.preset = preset;
+        self
+    }
+
+    pub fn huffman(mut self, code_lens: Vec<u8>) -> Self {
+        self.huffman = Some(code_lens);
+        self
+    }
+
+    pub fn dedup(mut self, dedup: Dedup) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    pub fn text(mut self, transform: TextTransform) -> Self {
+        self.text = Some(transform);
+        self
+    }
+
+    pub fn block_size(mut self, block_size: u64) -> Self {
+        self.block_size = Some(block_size);
+        self
+    }
+
+    pub fn seekable(mut self, seekable: bool) -> Self {
+        self.seekable = seekable;
+        self
+    }
+
+    pub fn dictionary(mut self, id: u64) -> Self {
+        self.dictionary = Some(id);
+        self
+    }
+
+    pub fn reference(mut self, hash: u64) -> Self {
+        self.reference = Some(hash);
+        self
+    }
+
+    pub fn models(mut self, models: TextModels) -> Self {
+        self.models = models;
+        self
+    }
+
+    /// Bytes `write` produces
+    pub fn size(&self) -> usize {
+        Self::FIXED_SIZE
+            + self.huffman.as_ref().map_or(0, Vec::len)
+            + self.dedup.as_ref().map_or(0, Dedup::size)
+            + self.text.as_ref().map_or(0, TextTransform::size)
+            + self.block_size.map_or(0, |_| std::mem::size_of::<u64>())
+            + usize::from(self.ext_flags() != 0)
+            + self.dictionary.map_or(0, |_| std::mem::size_of::<u64>())
+            + self.reference.map_or(0, |_| std::mem::size_of::<u64>())
+            + usize::from(self.ext_flags() & EXT_MODELS != 0)
+    }
+
+    /// Bytes of the coded stream
+    pub fn coded_len(&self) -> u64 {
+        self.text
+            .as_ref()
+            .map_or(self.deduped_len(), |transform| transform.len)
+    }
+
+    /// Bytes left after deduplication, the input of the text transform
+    pub fn deduped_len(&self) -> u64 {
+        self.dedup.as_ref().map_or(self.len, |dedup| dedup.len)
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut flags = match self.preset {
+            Preset::Text => 0,
+            Preset::Binary => FLAG_BINARY,
+            Preset::Store => FLAG_STORE,
+        };
+        if self.huffman.is_some() {
+            flags |= FLAG_HUFFMAN;
+        }
+        if self.text.is_some() {
+            flags |= FLAG_TEXT;
+        }
+        if self.dedup.is_some() {
+            flags |= FLAG_DEDUP;
+        }
+        if self.block_size.is_some() {
+            flags |= FLAG_BLOCKS;
+        }
+        if self.seekable {
+            flags |= FLAG_SEEKABLE;
+        }
+        let ext_flags = self.ext_flags();
+        if ext_flags != 0 {
+            flags |= FLAG_EXTENDED;
+        }
+        writer.write_all(MAGIC_STR)?;
+        writer.write_all(&self.len.to_be_bytes())?;
+        writer.write_all(&self.memory.to_be_bytes())?;
+        writer.write_all(&[flags])?;
+        if ext_flags != 0 {
+            writer.write_all(&[ext_flags])?;
+        }
+        if let Some(code_lens) = &self.huffman {
+            writer.write_all(code_lens)?;
+        }
+        if let Some(dedup) = &self.dedup {
+            dedup.write(writer)?;
+        }
+        if let Some(transform) = &self.text {
+            transform.write(writer)?;
+        }
+        if let Some(block_size) = self.block_size {
+            writer.write_all(&block_size.to_be_bytes())?;
+        }
+        if let Some(id) = self.dictionary {
+            writer.write_all(&id.to_be_bytes())?;
+        }
+        if let Some(hash) = self.reference {
+            writer.write_all(&hash.to_be_bytes())?;
+        }
+        if ext_flags & EXT_MODELS != 0 {
+            writer.write_all(&[models_to_byte(self.models)])?;
+        }
+        Ok(())
+    }
+
+    fn ext_flags(&self) -> u8 {
+        let mut ext_flags = 0;
+        if self.dictionary.is_some() {
+            ext_flags |= EXT_DICTIONARY;
+        }
+        if self.reference.is_some() {
+            ext_flags |= EXT_REFERENCE;
+        }
+        if self.models != TextModels::default() {
+            ext_flags |= EXT_MODELS;
+        }
+        ext_flags
+    }
+
+    /// Parses the header, rejecting anything that wasn't written by `Header::write`
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let mut buf = [0; Self::FIXED_SIZE];
+        reader.read_exact(&mut buf)?;
+
+        let magic_num = u32::from_be_bytes(buf[..4].try_into().unwrap());
+        if magic_num != MAGIC_NUM {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Magic numbers don't match up - file wasn't compressed with (this version of) weath3rb0i!",
+            ));
+        }
+        let len = u64::from_be_bytes(buf[4..12].try_into().unwrap());
+        let memory = u64::from_be_bytes(buf[12..20].try_into().unwrap());
+        if memory > MAX_MEMORY {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid memory budget {}", memory),
+            ));
+        }
+        let flags = buf[20];
+
+        let preset = match (flags & FLAG_BINARY != 0, flags & FLAG_STORE != 0) {
+            (false, false) => Preset::Text,
+            (true, false) => Preset::Binary,
+            (false, true) if flags == FLAG_STORE => Preset::Store,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Conflicting header flags {:#010b}", flags),
+                ))
+            }
+        };
+
+        let ext_flags = match flags & FLAG_EXTENDED != 0 {
+            true => {
+                let mut ext_flags = [0];
+                reader.read_exact(&mut ext_flags)?;
+                ext_flags[0]
+            }
+            false => 0,
+        };
+        // `write` leaves out an empty extended byte
+        if flags & FLAG_EXTENDED != 0 && (ext_flags == 0 || ext_flags & !KNOWN_EXT_FLAGS != 0) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unknown extended header flags {:#010b}", ext_flags),
+            ));
+        }
+
+        let mut header = Self::new(len, memory).preset(preset);
+        if flags & FLAG_HUFFMAN != 0 {
+            let mut code_lens = vec![0; 256];
+            reader.read_exact(&mut code_lens)?;
+            if CanonicalCode::from_code_lens(&code_lens).is_none() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Huffman code lengths don't describe a complete code",
+                ));
+            }
+            header = header.huffman(code_lens);
+        }
+        if flags & FLAG_DEDUP != 0 {
+            let dedup = Dedup::read(reader)?;
+            if dedup.output_len() != len {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Dedup matches don't add up to the length in the header",
+                ));
+            }
+            header = header.dedup(dedup);
+        }
+        if flags & FLAG_TEXT != 0 {
+            let transform = TextTransform::read(reader)?;
+            // the coded stream is decoded into memory before the inverse
+            if transform.len > TextTransform::max_len(header.deduped_len()) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Text transform of {} bytes can't restore {} bytes",
+                        transform.len,
+                        header.deduped_len()
+                    ),
+                ));
+            }
+            header = header.text(transform);
+        }
+        if flags & FLAG_BLOCKS != 0 {
+            let mut buf = [0; 8];
+            reader.read_exact(&mut buf)?;
+            let block_size = u64::from_be_bytes(buf);
+            if !(1..=MAX_BLOCK_SIZE).contains(&block_size) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid block size {}", block_size),
+                ));
+            }
+            header = header.block_size(block_size);
+        }
+        if flags & FLAG_SEEKABLE != 0 {
+            // offsets in the coded stream must be offsets in the output
+            if header.block_size.is_none() || header.dedup.is_some() || header.text.is_some() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Seekable streams need blocks and no transforms",
+                ));
+            }
+            header = header.seekable(true);
+        }
+        if ext_flags & EXT_DICTIONARY != 0 {
+            // dictionaries hold byte models, not codeword models
+            if header.huffman.is_some() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Huffman coded streams can't use a dictionary",
+                ));
+            }
+            let mut buf = [0; 8];
+            reader.read_exact(&mut buf)?;
+            header = header.dictionary(u64::from_be_bytes(buf));
+        }
+        if ext_flags & EXT_REFERENCE != 0 {
+            // the match model predicts byte bits, not codeword bits
+            if header.huffman.is_some() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Huffman coded streams can't use a reference",
+                ));
+            }
+            let mut buf = [0; 8];
+            reader.read_exact(&mut buf)?;
+            header = header.reference(u64::from_be_bytes(buf));
+        }
+        if ext_flags & EXT_MODELS != 0 {
+            let mut models = [0];
+            reader.read_exact(&mut models)?;
+            let models = models[0];
+            // dictionaries are trained for the order-N model alone
+            if models == 0
+                || models & !KNOWN_MODELS != 0
+                || models & (MODEL_ADAPTIVE0 | MODEL_ADAPTIVE1) == MODEL_ADAPTIVE0 | MODEL_ADAPTIVE1
+                || header.preset != Preset::Text
+                || header.huffman.is_some()
+                || header.dictionary.is_some()
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid models {:#010b}", models),
+                ));
+            }
+            header = header.models(models_from_byte(models));
+        }
+        Ok(header)
+    }
+}
+
+fn models_to_byte(models: TextModels) -> u8 {
+    let mut byte = 0;
+    if models.states {
+        byte |= MODEL_STATES;
+    }
+    if models.run {
+        byte |= MODEL_RUN;
+    }
+    if models.bytes {
+        byte |= MODEL_BYTES;
+    }
+    match models.hash {
+        HashModel::Stationary => byte,
+        HashModel::Adaptive0 => byte | MODEL_ADAPTIVE0,
+        HashModel::Adaptive1 => byte | MODEL_ADAPTIVE1,
+    }
+}
+
+fn models_from_byte(byte: u8) -> TextModels {
+    TextModels {
+        states: byte & MODEL_STATES != 0,
+        run: byte & MODEL_RUN != 0,
+        bytes: byte & MODEL_BYTES != 0,
+        hash: if byte & MODEL_ADAPTIVE0 != 0 {
+            HashModel::Adaptive0
+        } else if byte & MODEL_ADAPTIVE1 != 0 {
+            HashModel::Adaptive1
+        } else {
+            HashModel::Stationary
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::TextOptions;
+
+    fn assert_round_trip(header: &Header) {
+        let mut buf = Vec::new();
+        header.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), header.size());
+        assert_eq!(&Header::read(&mut buf.as_slice()).unwrap(), header);
+    }
+
+    #[test]
+    fn round_trip() {
+        let header = Header::new(0xdead_beef_cafe, 256 << 20);
+        assert_round_trip(&header);
+
+        let code_lens = CanonicalCode::from_counts(&[1; 256]).code_lens().to_vec();
+        let header = header.huffman(code_lens);
+        assert_round_trip(&header);
+
+        let (transform, _) = TextTransform::build(b"The end", &TextOptions::default());
+        let header = header.text(transform);
+        assert_round_trip(&header);
+
+        let (dedup, literals) = Dedup::build(&[0; 100]);
+        let (transform, _) = TextTransform::build(&literals, &TextOptions::default());
+        let header = Header { len: 100, ..header }.dedup(dedup).text(transform);
+        assert_round_trip(&header);
+
+        assert_round_trip(&header.block_size(MAX_BLOCK_SIZE));
+        let header = Header::new(1, 1).block_size(1).seekable(true);
+        assert_round_trip(&header);
+        let header = header.dictionary(u64::MAX);
+        assert_round_trip(&header);
+        assert_round_trip(&header.reference(7));
+        assert_round_trip(&Header::new(1, 1).reference(u64::MAX));
+        let models = TextModels { states: true, ..TextModels::default() };
+        assert_round_trip(&Header::new(1, 1).reference(1).models(models));
+        let models = TextModels { run: true, bytes: true, ..TextModels::default() };
+        assert_round_trip(&Header::new(1, 1).models(models));
+        for hash in [HashModel::Adaptive0, HashModel::Adaptive1] {
+            let models = TextModels { hash, ..models };
+            assert_round_trip(&Header::new(1, 1).models(models));
+            let models = TextModels { hash, ..TextModels::default() };
+            assert_round_trip(&Header::new(1, 1).models(models));
+        }
+    }
+
+    #[test]
+    fn presets() {
+        for preset in Preset::ALL {
+            assert_round_trip(&Header::new(1, 1).preset(preset));
+        }
+        // nothing is modeled when storing
+        let mut buf = Vec::new();
+        Header::new(1, 1)
+            .preset(Preset::Binary)
+            .huffman(vec![8; 256])
+            .write(&mut buf)
+            .unwrap();
+        buf[20] = FLAG_STORE | FLAG_HUFFMAN;
+        let err = Header::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        buf[20] = FLAG_STORE | FLAG_BINARY;
+        let err = Header::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        buf[20] = FLAG_STORE | FLAG_EXTENDED;
+        let err = Header::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn huffman_dictionary() {
+        let header = Header::new(1, 1).huffman(vec![8; 256]);
+        for header in [header.clone().dictionary(1), header.reference(1)] {
+            let mut buf = Vec::new();
+            header.write(&mut buf).unwrap();
+            let err = Header::read(&mut buf.as_slice()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn bad_models() {
+        let models = TextModels { run: true, ..TextModels::default() };
+        for header in [
+            Header::new(1, 1).preset(Preset::Binary).models(models),
+            Header::new(1, 1).huffman(vec![8; 256]).models(models),
+            Header::new(1, 1).dictionary(1).models(models),
+        ] {
+            let mut buf = Vec::new();
+            header.write(&mut buf).unwrap();
+            let err = Header::read(&mut buf.as_slice()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+        let mut buf = Vec::new();
+        Header::new(1, 1).models(models).write(&mut buf).unwrap();
+        for byte in [0, 0x80, MODEL_ADAPTIVE0 | MODEL_ADAPTIVE1] {
+            *buf.last_mut().unwrap() = byte;
+            let err = Header::read(&mut buf.as_slice()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn bad_memory() {
+        let mut buf = Vec::new();
+        Header::new(1, MAX_MEMORY).write(&mut buf).unwrap();
+        Header::read(&mut buf.as_slice()).unwrap();
+        for memory in [MAX_MEMORY + 1, u64::MAX] {
+            let mut buf = Vec::new();
+            Header::new(1, memory).write(&mut buf).unwrap();
+            let err = Header::read(&mut buf.as_slice()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn bad_text_len() {
+        let (mut transform, _) = TextTransform::build(b"The end", &TextOptions::default());
+        transform.len = TextTransform::max_len(7) + 1;
+        let mut buf = Vec::new();
+        Header::new(7, 1).text(transform).write(&mut buf).unwrap();
+        let err = Header::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bad_block_size() {
+        for block_size in [0, MAX_BLOCK_SIZE + 1] {
+            let mut buf = Vec::new();
+            Header::new(1, 1)
+                .block_size(block_size)
+                .write(&mut buf)
+                .unwrap();
+            let err = Header::read(&mut buf.as_slice()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn bad_seekable() {
+        let (dedup, _) = Dedup::build(&[0; 100]);
+        for header in [
+            Header::new(100, 1).seekable(true),
+            Header::new(100, 1)
+                .block_size(10)
+                .dedup(dedup)
+                .seekable(true),
+        ] {
+            let mut buf = Vec::new();
+            header.write(&mut buf).unwrap();
+            let err = Header::read(&mut buf.as_slice()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn bad_dedup_len() {
+        let (dedup, _) = Dedup::build(&[0; 100]);
+        let mut buf = Vec::new();
+        Header::new(99, 1).dedup(dedup).write(&mut buf).unwrap();
+        let err = Header::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bad_flags() {
+        let mut buf = Vec::new();
+        Header::new(1, 1).write(&mut buf).unwrap();
+        buf[20] = 0x80;
+        let err = Header::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        for ext_flags in [0, 0x80] {
+            let mut buf = buf.clone();
+            buf.push(ext_flags);
+            let err = Header::read(&mut buf.as_slice()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn bad_code_lens() {
+        let mut buf = Vec::new();
+        Header::new(1, 1)
+            .huffman(vec![8; 256])
+            .write(&mut buf)
+            .unwrap();
+        buf[Header::FIXED_SIZE] = 7;
+        let err = Header::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let buf = b"w31i\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x20\x00\x00";
+        let err = Header::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated() {
+        let buf = b"w30i\x00\x00";
+        let err = Header::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,90 @@
+use super::Header;
+use std::{
+    io::{self, Error, ErrorKind},
+    time::{Duration, Instant},
+};
+
+/// Resource limits for decoding untrusted streams, `None` means unlimited
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    /// Maximum number of decompressed bytes
+    pub max_output: Option<u64>,
+    /// Maximum bytes of model tables and decoding buffers to allocate
+    pub max_memory: Option<usize>,
+    /// Maximum number of binary decisions to decode
+    pub max_bits: Option<u64>,
+    /// Maximum wall time spent decoding
+    pub max_time: Option<Duration>,
+}
+
+impl Limits {
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub fn max_output(mut self, bytes: u64) -> Self {
+        self.max_output = Some(bytes);
+        self
+    }
+
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    pub fn max_bits(mut self, bits: u64) -> Self {
+        self.max_bits = Some(bits);
+        self
+    }
+
+    pub fn max_time(mut self, time: Duration) -> Self {
+        self.max_time = Some(time);
+        self
+    }
+
+    /// Checks what the header declares, before anything is allocated
+    pub(crate) fn check_header(
+        &self,
+        header: &Header,
+        bits_per_byte: u8,
+        memory: usize,
+    ) -> io::Result<()> {
+        let len = header.len;
+        if let Some(max_output) = self.max_output.filter(|&max| len > max) {
+            let msg = format!(
+                "Declared length {} exceeds the limit of {} bytes",
+                len, max_output
+            );
+            return Err(Error::new(ErrorKind::FileTooLarge, msg));
+        }
+        let coded_len = header.coded_len();
+        if let Some(max_bits) = self
+            .max_bits
+            .filter(|&max| coded_len.saturating_mul(u64::from(bits_per_byte)) > max)
+        {
+            let msg = format!(
+                "Declared length {} exceeds the limit of {} bits",
+                coded_len, max_bits
+            );
+            return Err(Error::new(ErrorKind::FileTooLarge, msg));
+        }
+        if let Some(max_memory) = self.max_memory.filter(|&max| memory > max) {
+            let msg = format!(
+                "Decoding needs {} bytes, exceeding the limit of {} bytes",
+                memory, max_memory
+            );
+            return Err(Error::new(ErrorKind::OutOfMemory, msg));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_time(&self, start: Instant) -> io::Result<()> {
+        match self.max_time {
+            Some(max_time) if start.elapsed() > max_time => Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("Decoding took longer than {:?}", max_time),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,1231 @@
+pub mod checkpoint;
+pub mod detect;
+pub mod dictionary;
+pub mod header;
+pub mod limits;
+pub mod options;
+pub mod reference;
+pub mod streaming;
+
+mod blocks;
+mod seekable;
+
+use std::io::{self, BufRead, Error, ErrorKind, Read, Write};
+use std::time::Instant;
+
+use crate::{
+    entropy_coding::{
+        arithmetic_coder::{ACRead, ACWrite, ArithmeticCoder},
+        huffman::{CanonicalCode, TreePos},
+        io::{ACReader, ACWriter},
+    },
+    helpers::{histogram, table_bits},
+    history::{ACHistory, RawHistory},
+    models::{
+        ac_hash::{AdaptiveOrder0, AdaptiveOrder1, AnyHashModel, StationaryModel},
+        Counter, Model, NaiveStateTable, Order1, OrderNBytes, OrderNCodeword, OrderNStates,
+        RunModel, Tables, CODEWORD_ALIGNMENT_BITS,
+    },
+    state::State,
+    transform::{Dedup, TextTransform},
+};
+
+pub use self::blocks::MAX_BLOCK_SIZE;
+use self::blocks::{read_block, read_index, write_block, write_index, Block, Index};
+pub use self::{
+    checkpoint::*, detect::*, dictionary::*, header::*, limits::*, options::*, reference::*,
+    seekable::*, streaming::*,
+};
+
+/// Bytes the decoder may read past the end of the stream - the arithmetic coder
+/// keeps 32 bits of lookahead, anything more means the stream is truncated or
+/// the header lies about the length
+const MAX_OVERRUN: u64 = 8;
+// bytes between checks of the time limit
+const TIME_CHECK_INTERVAL: u64 = 1 << 12;
+
+/// Compresses `len` bytes from `reader` into `writer`
+pub fn compress(reader: impl BufRead, len: u64, writer: impl Write) -> io::Result<()> {
+    compress_with(reader, len, writer, &Options::default()).map(|_| ())
+}
+
+/// Compresses `len` bytes from `reader` into `writer` with the given `options`
+///
+/// Without blocks, the input and its coded form are held in memory: a stream
+/// the models would expand is stored instead, as blocks are.
+pub fn compress_with(
+    reader: impl BufRead,
+    len: u64,
+    writer: impl Write,
+    options: &Options,
+) -> io::Result<Summary> {
+    compress_primed(reader, len, writer, options, None, None)
+}
+
+/// Compresses like `compress_with`, starting from the models trained into
+/// `dictionary` - its memory and preset replace the ones in `options`
+pub fn compress_with_dictionary(
+    reader: impl BufRead,
+    len: u64,
+    writer: impl Write,
+    options: &Options,
+    dictionary: &Dictionary,
+) -> io::Result<Summary> {
+    if options.huffman
+        || options
+            .preset
+            .is_some_and(|preset| preset != dictionary.preset)
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Dictionaries only apply to the preset they were trained for, without Huffman coding",
+        ));
+    }
+    compress_primed(reader, len, writer, options, Some(dictionary), None)
+}
+
+/// Compresses like `compress_with`, as a delta against `reference` - the
+/// models start out having seen it
+pub fn compress_with_reference(
+    reader: impl BufRead,
+    len: u64,
+    writer: impl Write,
+    options: &Options,
+    reference: &Reference,
+) -> io::Result<Summary> {
+    // every block of a seekable stream would start over with the reference
+    if options.huffman || options.seekable {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "References don't apply with Huffman coding or seekable streams",
+        ));
+    }
+    compress_primed(reader, len, writer, options, None, Some(reference))
+}
+
+fn compress_primed(
+    reader: impl BufRead,
+    len: u64,
+    writer: impl Write,
+    options: &Options,
+    dictionary: Option<&Dictionary>,
+    reference: Option<&Reference>,
+) -> io::Result<Summary> {
+    options.check()?;
+    if dictionary.is_some() && options.models != TextModels::default() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Dictionaries are trained for the text preset without other models",
+        ));
+    }
+    let mut reader = reader;
+    let mut sample = Vec::new();
+    let preset = match (dictionary, options.preset) {
+        (Some(dictionary), _) => dictionary.preset,
+        (None, Some(preset)) => preset,
+        (None, None) => {
+            sample = read_sample(&mut reader)?;
+            match Preset::detect(&sample) {
+                // noise may well be in the reference, only models can find it
+                Preset::Store if reference.is_some() => Preset::Binary,
+                preset => preset,
+            }
+        }
+    };
+    // the sample is still part of the input
+    let mut reader = sample.as_slice().chain(reader);
+    let mut header = match dictionary {
+        Some(dictionary) => Header::new(len, dictionary.memory).dictionary(dictionary.id),
+        None => Header::new(len, options.memory),
+    };
+    header = header.preset(preset);
+    if preset == Preset::Text {
+        header = header.models(options.models);
+    }
+    if preset == Preset::Store {
+        // nothing is modeled, nothing needs the reference
+        return store(reader, header, writer);
+    }
+    if options.seekable {
+        if options.dedup || options.text.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Seekable streams can't use the dedup or text transforms",
+            ));
+        }
+        header = header.seekable(true);
+    }
+    let default_block_size = options.seekable.then_some(DEFAULT_SEEK_BLOCK_SIZE);
+    if let Some(block_size) = options.block_size.or(default_block_size) {
+        header = header.block_size(block_size);
+    }
+    if let Some(reference) = reference {
+        header = header.reference(reference.hash);
+    }
+    // blocks the models would expand are stored one by one
+    if header.block_size.is_some() && !options.dedup && options.text.is_none() {
+        return encode(reader, header, writer, options, dictionary, reference);
+    }
+
+    let mut input = Vec::new();
+    reader.read_to_end(&mut input)?;
+    check_len(input.len() as u64, len)?;
+    if header.block_size.is_some() {
+        return transform_and_encode(&input, header, writer, options, dictionary, reference);
+    }
+    let mut coded = Vec::new();
+    let summary = transform_and_encode(&input, header, &mut coded, options, dictionary, reference)?;
+    let stored = Header::new(len, options.memory).preset(Preset::Store);
+    if coded.len() > stored.size() + input.len() {
+        return store(input.as_slice(), stored, writer);
+    }
+    let mut writer = writer;
+    writer.write_all(&coded)?;
+    writer.flush()?;
+    Ok(summary)
+}
+
+/// Codes `input` behind `header`, after the transforms of `options`
+fn transform_and_encode(
+    input: &[u8],
+    mut header: Header,
+    writer: impl Write,
+    options: &Options,
+    dictionary: Option<&Dictionary>,
+    reference: Option<&Reference>,
+) -> io::Result<Summary> {
+    let mut buf = None;
+    if options.dedup {
+        let (dedup, literals) = Dedup::build(input);
+        header = header.dedup(dedup);
+        buf = Some(literals);
+    }
+    if let Some(text) = &options.text {
+        let (transform, transformed) = TextTransform::build(buf.as_deref().unwrap_or(input), text);
+        header = header.text(transform);
+        buf = Some(transformed);
+    }
+    let input = buf.as_deref().unwrap_or(input);
+    encode(input, header, writer, options, dictionary, reference)
+}
+
+/// Copies the input behind a `Preset::Store` header
+fn store(mut reader: impl BufRead, header: Header, mut writer: impl Write) -> io::Result<Summary> {
+    header.write(&mut writer)?;
+    let copied = io::copy(&mut reader, &mut writer)?;
+    check_len(copied, header.len)?;
+    writer.flush()?;
+    Ok(Summary { preset: Preset::Store, ..Summary::default() })
+}
+
+/// Fails with `InvalidInput` unless all `len` bytes of input were read
+fn check_len(pos: u64, len: u64) -> io::Result<()> {
+    match pos == len {
+        true => Ok(()),
+        false => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Expected {} bytes of input, got {}", len, pos),
+        )),
+    }
+}
+
+/// Codes the bytes from `reader` (what the header calls the coded stream)
+fn encode(
+    reader: impl BufRead,
+    header: Header,
+    writer: impl Write,
+    options: &Options,
+    dictionary: Option<&Dictionary>,
+    reference: Option<&Reference>,
+) -> io::Result<Summary> {
+    let config = ModelConfig::for_header(&header);
+    if options.huffman {
+        return compress_huffman(reader, header, writer, &config);
+    }
+
+    match (config.preset, reference) {
+        (Preset::Binary, None) => {
+            let init = || primed(config.init_binary_model(), dictionary);
+            encode_stream(reader, header, init, &ByteBits, writer)
+        }
+        (Preset::Binary, Some(reference)) => {
+            let init = || reference.prime(primed(config.init_binary_model(), dictionary));
+            encode_stream(reader, header, init, &ByteBits, writer)
+        }
+        (_, None) => {
+            let init = || primed(config.init_model(), dictionary);
+            encode_stream(reader, header, init, &ByteBits, writer)
+        }
+        (_, Some(reference)) => {
+            let init = || reference.prime(primed(config.init_model(), dictionary));
+            encode_stream(reader, header, init, &ByteBits, writer)
+        }
+    }
+}
+
+/// `model` with the tables of `dictionary`, if any
+fn primed<M: Tables>(mut model: M, dictionary: Option<&Dictionary>) -> M {
+    if let Some(dictionary) = dictionary {
+        dictionary.prime(&mut model);
+    }
+    model
+}
+
+/// Codes every byte as its codeword of a Huffman code built over the whole input
+fn compress_huffman(
+    mut reader: impl BufRead,
+    header: Header,
+    writer: impl Write,
+    config: &ModelConfig,
+) -> io::Result<Summary> {
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    let code = CanonicalCode::from_counts(&histogram(&buf));
+    let header = header.huffman(code.code_lens().to_vec());
+    let init = || config.init_codeword_model(code.clone());
+    encode_stream(buf.as_slice(), header, init, &code, writer)
+}
+
+/// Writes `header` and codes the bytes from `reader` behind it, as blocks when
+/// the header has a block size - `init` builds the models, once or for every
+/// block of a seekable stream
+fn encode_stream<M: Model>(
+    mut reader: impl BufRead,
+    header: Header,
+    init: impl Fn() -> M,
+    coding: &impl ByteCoding,
+    mut writer: impl Write,
+) -> io::Result<Summary> {
+    header.write(&mut writer)?;
+    let mut model = init();
+    match header.block_size {
+        None => {
+            let read = encode_block(reader, &mut model, coding, &mut ACWriter::new(writer))?;
+            check_len(read, header.coded_len())?;
+        }
+        Some(block_size) => {
+            let mut block = Vec::new();
+            let mut index = Index::new();
+            let mut offset = header.size() as u64;
+            let mut read = 0;
+            loop {
+                block.clear();
+                (&mut reader).take(block_size).read_to_end(&mut block)?;
+                if block.is_empty() {
+                    break;
+                }
+                read += block.len() as u64;
+                if header.seekable && !index.is_empty() {
+                    model = init();
+                }
+                // the model learns the block either way, stored or not
+                let mut coded = ACWriter::new(Vec::new());
+                encode_block(block.as_slice(), &mut model, coding, &mut coded)?;
+                index.push((index.len() as u64 * block_size, offset));
+                offset += write_block(&mut writer, &block, &coded.into_inner())?;
+            }
+            // the index and the decoder count on `coded_len` bytes in blocks
+            check_len(read, header.coded_len())?;
+            if header.seekable {
+                write_index(&mut writer, &index)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(Summary {
+        preset: header.preset,
+        memory_usage: model.memory_usage(),
+    })
+}
+
+/// Codes all bytes of `reader` as one arithmetic coded stream, returns how
+/// many there were
+fn encode_block<W: ACWrite>(
+    reader: impl BufRead,
+    model: &mut impl Model,
+    coding: &impl ByteCoding,
+    writer: &mut W,
+) -> io::Result<u64> {
+    let mut ac = ArithmeticCoder::new_coder();
+    let mut read = 0;
+    for byte in reader.bytes() {
+        encode_bits(coding.bits(byte?), model, &mut ac, writer)?;
+        read += 1;
+    }
+    ac.flush(writer)?;
+    Ok(read)
+}
+
+fn encode_bits<W: ACWrite>(
+    bits: impl Iterator<Item = u8>,
+    model: &mut (impl Model + ?Sized),
+    ac: &mut ArithmeticCoder<W>,
+    writer: &mut W,
+) -> io::Result<()> {
+    for bit in bits {
+        let p = model.predict();
+        model.update(bit);
+        ac.encode(bit, p, writer)?;
+    }
+    Ok(())
+}
+
+/// Decompresses a stream produced by `compress` from `reader` into `writer`
+pub fn decompress(reader: impl Read, writer: impl Write) -> io::Result<()> {
+    decompress_with_limits(reader, writer, &Limits::unlimited()).map(|_| ())
+}
+
+/// Decompresses like `decompress`, but fails as soon as the stream would exceed
+/// any of the `limits` - use this for streams from untrusted sources
+pub fn decompress_with_limits(
+    reader: impl Read,
+    writer: impl Write,
+    limits: &Limits,
+) -> io::Result<Summary> {
+    decompress_primed(reader, writer, limits, None, None)
+}
+
+/// Decompresses like `decompress_with_limits` a stream compressed with
+/// `dictionary`, fails with `InvalidInput` when it was compressed with another
+pub fn decompress_with_dictionary(
+    reader: impl Read,
+    writer: impl Write,
+    limits: &Limits,
+    dictionary: &Dictionary,
+) -> io::Result<Summary> {
+    decompress_primed(reader, writer, limits, Some(dictionary), None)
+}
+
+/// Decompresses like `decompress_with_limits` a stream compressed against
+/// `reference`, fails with `InvalidInput` when it was compressed against another
+pub fn decompress_with_reference(
+    reader: impl Read,
+    writer: impl Write,
+    limits: &Limits,
+    reference: &Reference,
+) -> io::Result<Summary> {
+    decompress_primed(reader, writer, limits, None, Some(reference))
+}
+
+fn decompress_primed(
+    reader: impl Read,
+    writer: impl Write,
+    limits: &Limits,
+    dictionary: Option<&Dictionary>,
+    reference: Option<&Reference>,
+) -> io::Result<Summary> {
+    let timer = Instant::now();
+    let mut reader = reader;
+    let mut writer = writer;
+
+    let header = Header::read(&mut reader)?;
+    let dictionary = Dictionary::for_header(&header, dictionary)?;
+    let reference = Reference::for_header(&header, reference)?;
+    if header.preset == Preset::Store {
+        limits.check_header(&header, 0, 0)?;
+        let copied = io::copy(&mut reader.take(header.len), &mut writer)?;
+        if copied != header.len {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Stream ended before the length declared in the header",
+            ));
+        }
+        writer.flush()?;
+        return Ok(Summary { preset: Preset::Store, ..Summary::default() });
+    }
+    let code = header.huffman.as_deref().map(|code_lens| {
+        CanonicalCode::from_code_lens(code_lens).expect("Header::read validates the code")
+    });
+    let config = ModelConfig::for_header(&header);
+    let bits_per_byte = code.as_ref().map_or(8, CanonicalCode::max_len);
+    let match_memory = reference.map_or(0, |reference| reference.memory(header.coded_len()));
+    let model_memory = config.memory().saturating_add(match_memory);
+    let memory = model_memory.saturating_add(buffer_memory(&header));
+    limits.check_header(&header, bits_per_byte, memory)?;
+    let stream = Stream::new(&header, limits, timer);
+    if header.seekable {
+        let summary = decode(
+            &stream,
+            code.as_ref(),
+            &config,
+            dictionary,
+            reference,
+            &mut reader,
+            writer,
+        )?;
+        let block_size = header
+            .block_size
+            .expect("Header::read checks seekable has blocks");
+        read_index(&mut reader, header.len.div_ceil(block_size))?;
+        return Ok(summary);
+    }
+    if header.dedup.is_none() && header.text.is_none() {
+        let code = code.as_ref();
+        return decode(
+            &stream, code, &config, dictionary, reference, reader, writer,
+        );
+    }
+
+    let mut buf = Vec::new();
+    let summary = decode(
+        &stream,
+        code.as_ref(),
+        &config,
+        dictionary,
+        reference,
+        reader,
+        &mut buf,
+    )?;
+    if let Some(transform) = &header.text {
+        buf = transform.inverse(&buf, header.deduped_len())?;
+    }
+    if let Some(dedup) = &header.dedup {
+        // `Header::read` checked the matches add up to the header's length
+        buf = dedup.inverse(&buf)?;
+    }
+    writer.write_all(&buf)?;
+    writer.flush()?;
+    Ok(summary)
+}
+
+/// Bytes of the buffers `decompress_primed` decodes into when the stream needs
+/// inverse transforms, each one holds its input and output at once
+fn buffer_memory(header: &Header) -> usize {
+    let text = header.text.as_ref().map_or(0, |_| {
+        header.coded_len().saturating_add(header.deduped_len())
+    });
+    let dedup = header
+        .dedup
+        .as_ref()
+        .map_or(0, |_| header.deduped_len().saturating_add(header.len));
+    usize::try_from(text.max(dedup)).unwrap_or(usize::MAX)
+}
+
+/// Decodes `stream` with the models `config` builds, primed with `dictionary`
+/// and `reference`
+fn decode<R: Read>(
+    stream: &Stream,
+    code: Option<&CanonicalCode>,
+    config: &ModelConfig,
+    dictionary: Option<&Dictionary>,
+    reference: Option<&Reference>,
+    reader: R,
+    writer: impl Write,
+) -> io::Result<Summary> {
+    let summary = match (code, reference) {
+        (None, None) if config.preset == Preset::Binary => {
+            let init = || primed(config.init_binary_model(), dictionary);
+            stream.decode(init, &ByteBits, reader, writer)
+        }
+        (None, Some(reference)) if config.preset == Preset::Binary => {
+            let init = || reference.prime(primed(config.init_binary_model(), dictionary));
+            stream.decode(init, &ByteBits, reader, writer)
+        }
+        (None, None) => {
+            let init = || primed(config.init_model(), dictionary);
+            stream.decode(init, &ByteBits, reader, writer)
+        }
+        (None, Some(reference)) => {
+            let init = || reference.prime(primed(config.init_model(), dictionary));
+            stream.decode(init, &ByteBits, reader, writer)
+        }
+        (Some(code), _) => {
+            let init = || config.init_codeword_model(code.clone());
+            stream.decode(init, code, reader, writer)
+        }
+    };
+    summary.map(|summary| Summary { preset: config.preset, ..summary })
+}
+
+/// The coded stream's layout and what limits decoding it
+struct Stream<'a> {
+    len: u64,
+    block_size: Option<u64>,
+    /// Every block starts with fresh models
+    independent: bool,
+    limits: &'a Limits,
+    timer: Instant,
+}
+
+impl<'a> Stream<'a> {
+    fn new(header: &Header, limits: &'a Limits, timer: Instant) -> Self {
+        Self {
+            len: header.coded_len(),
+            block_size: header.block_size,
+            independent: header.seekable,
+            limits,
+            timer,
+        }
+    }
+
+    fn decode<M: Model>(
+        &self,
+        init: impl Fn() -> M,
+        coding: &impl ByteCoding,
+        mut reader: impl Read,
+        mut writer: impl Write,
+    ) -> io::Result<Summary> {
+        let mut model = init();
+        let Some(block_size) = self.block_size else {
+            let mut decoder = Decoder::new(ACReader::new(reader), model)?;
+            self.decode_bytes(self.len, &mut decoder, coding, &mut writer)?;
+            writer.flush()?;
+            let memory_usage = decoder.model.memory_usage();
+            return Ok(Summary { memory_usage, ..Summary::default() });
+        };
+
+        let mut remaining = self.len;
+        while remaining > 0 {
+            let len = remaining.min(block_size);
+            if self.independent && remaining < self.len {
+                model = init();
+            }
+            match read_block(&mut reader, len)? {
+                Block::Stored(bytes) => {
+                    self.limits.check_time(self.timer)?;
+                    writer.write_all(&bytes)?;
+                    // as the encoder did when it tried to model the block
+                    for &byte in &bytes {
+                        coding.bits(byte).for_each(|bit| model.update(bit));
+                    }
+                }
+                Block::Modeled(coded) => {
+                    let mut decoder = Decoder::new(ACReader::new(coded.as_slice()), model)?;
+                    self.decode_bytes(len, &mut decoder, coding, &mut writer)?;
+                    model = decoder.model;
+                }
+            }
+            remaining -= len;
+        }
+        writer.flush()?;
+        Ok(Summary {
+            memory_usage: model.memory_usage(),
+            ..Summary::default()
+        })
+    }
+
+    /// Decodes `len` bytes, checking the limits along the way
+    fn decode_bytes<R: Read, M: Model>(
+        &self,
+        len: u64,
+        decoder: &mut Decoder<R, M>,
+        coding: &impl ByteCoding,
+        writer: &mut impl Write,
+    ) -> io::Result<()> {
+        for i in 0..len {
+            if i % TIME_CHECK_INTERVAL == 0 {
+                self.limits.check_time(self.timer)?;
+            }
+            if decoder.reader.overrun() > MAX_OVERRUN {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Stream ended before the length declared in the header",
+                ));
+            }
+            writer.write_all(&[coding.decode(decoder)?])?;
+        }
+        Ok(())
+    }
+}
+
+/// The decoding side of `encode_bits`
+struct Decoder<R: Read, M: Model> {
+    ac: ArithmeticCoder<ACReader<R>>,
+    reader: ACReader<R>,
+    model: M,
+}
+
+impl<R: Read, M: Model> Decoder<R, M> {
+    fn new(mut reader: ACReader<R>, model: M) -> io::Result<Self> {
+        let ac = ArithmeticCoder::new_decoder(&mut reader)?;
+        Ok(Self { ac, reader, model })
+    }
+
+    fn bit(&mut self) -> io::Result<u8> {
+        let p = self.model.predict();
+        let bit = self.ac.decode(p, &mut self.reader)?;
+        self.model.update(bit);
+        Ok(bit)
+    }
+}
+
+/// How bytes turn into the binary decisions the models predict
+trait ByteCoding {
+    /// The decisions coding `byte`, in order
+    fn bits(&self, byte: u8) -> impl Iterator<Item = u8>;
+    fn decode<R: Read, M: Model>(&self, decoder: &mut Decoder<R, M>) -> io::Result<u8>;
+}
+
+/// Bytes as their 8 bits, MSB first
+struct ByteBits;
+
+impl ByteCoding for ByteBits {
+    fn bits(&self, byte: u8) -> impl Iterator<Item = u8> {
+        (0..8).rev().map(move |i| (byte >> i) & 1)
+    }
+
+    fn decode<R: Read, M: Model>(&self, decoder: &mut Decoder<R, M>) -> io::Result<u8> {
+        let mut byte = 0;
+        for _ in 0..u8::BITS {
+            byte = (byte << 1) | decoder.bit()?;
+        }
+        Ok(byte)
+    }
+}
+
+/// Bytes as their codewords
+impl ByteCoding for CanonicalCode {
+    fn bits(&self, byte: u8) -> impl Iterator<Item = u8> {
+        CanonicalCode::bits(self, byte)
+    }
+
+    fn decode<R: Read, M: Model>(&self, decoder: &mut Decoder<R, M>) -> io::Result<u8> {
+        let mut pos = TreePos::default();
+        loop {
+            if let Some(byte) = self.step(&mut pos, decoder.bit()?) {
+                return Ok(byte);
+            }
+        }
+    }
+}
+
+/// Model sizes derived from the memory budget in the header, so the decoder
+/// builds exactly the models the encoder used
+struct ModelConfig {
+    preset: Preset,
+    models: TextModels,
+    ctx_bits: u8,
+    alignment_bits: u8,
+    // bytes of the budget for each of the `models`
+    share: usize,
+}
+
+impl ModelConfig {
+    const BYTE_ALIGNMENT_BITS: u8 = 3;
+    // the entropy hash is at most 32 bits
+    const MAX_CTX_BITS: u8 = 32;
+
+    fn new(memory: u64, preset: Preset, huffman: bool) -> Self {
+        Self::with_models(memory, preset, huffman, TextModels::default())
+    }
+
+    fn for_header(header: &Header) -> Self {
+        let huffman = header.huffman.is_some();
+        Self::with_models(header.memory, header.preset, huffman, header.models)
+    }
+
+    fn with_models(memory: u64, preset: Preset, huffman: bool, models: TextModels) -> Self {
+        let alignment_bits = match huffman {
+            true => CODEWORD_ALIGNMENT_BITS,
+            false => Self::BYTE_ALIGNMENT_BITS,
+        };
+        let max_ctx_bits = match (preset, huffman) {
+            (Preset::Binary, false) => Order1::MAX_CTX_BITS,
+            _ => Self::MAX_CTX_BITS,
+        };
+        // the budget is split evenly between the main model and the ones
+        // mixed into it
+        let memory = usize::try_from(memory).unwrap_or(usize::MAX);
+        let share = memory / (1 + models.count());
+        let ctx_bits = table_bits(share, std::mem::size_of::<Counter>())
+            .clamp(alignment_bits + 1, max_ctx_bits);
+        Self { preset, models, ctx_bits, alignment_bits, share }
+    }
+
+    /// Bytes of tables `init_model` allocates, known without allocating them
+    fn memory(&self) -> usize {
+        let mut memory = (1 << self.ctx_bits) * std::mem::size_of::<Counter>();
+        if self.models.states {
+            memory += OrderNStates::memory(self.states_bits(), &NaiveStateTable);
+        }
+        if self.models.run {
+            memory += (1 << self.run_bits()) * RunModel::SLOT_SIZE;
+        }
+        if self.models.bytes {
+            memory += (1 << self.bytes_bits()) * std::mem::size_of::<Counter>();
+        }
+        memory + self.hash_memory()
+    }
+
+    // the hash models' tables don't grow with the budget
+    fn hash_memory(&self) -> usize {
+        match self.models.hash {
+            HashModel::Stationary => 0,
+            HashModel::Adaptive0 => AdaptiveOrder0::MEMORY,
+            HashModel::Adaptive1 => AdaptiveOrder1::MEMORY,
+        }
+    }
+
+    fn states_bits(&self) -> u8 {
+        table_bits(self.share, std::mem::size_of::<u16>()).clamp(8, 32)
+    }
+
+    fn run_bits(&self) -> u8 {
+        table_bits(self.share, RunModel::SLOT_SIZE).clamp(8, 32)
+    }
+
+    fn bytes_bits(&self) -> u8 {
+        table_bits(self.share, std::mem::size_of::<Counter>()).clamp(8, 32)
+    }
+
+    fn init_model(&self) -> impl Model + Tables + State {
+        use crate::models::*;
+        // BestOfTwoModel::new(Order0::new(), Order1::new())
+        // BestOfTwoModel::new(Order0Entropy::new(), Order0::new())
+        // BestOfTwoModel::new(Order1::new(), Order0Entropy::new())
+        let hash = match self.models.hash {
+            HashModel::Stationary => AnyHashModel::Stationary(StationaryModel::for_book1()),
+            HashModel::Adaptive0 => AnyHashModel::Order0(AdaptiveOrder0::new()),
+            HashModel::Adaptive1 => AnyHashModel::Order1(Box::default()),
+        };
+        let history = ACHistory::new(self.ctx_bits - self.alignment_bits, hash);
+        let model = OrderNEntropy::new(self.ctx_bits, self.alignment_bits, history);
+        let states = self
+            .models
+            .states
+            .then(|| OrderNStates::new(2, self.states_bits(), NaiveStateTable));
+        let run = self.models.run.then(|| RunModel::new(3, self.run_bits()));
+        let bytes = self
+            .models
+            .bytes
+            .then(|| OrderNBytes::new(4, self.bytes_bits()));
+        let model = BestOfTwoModel::new(BestOfTwoModel::new(model, states), run);
+        BestOfTwoModel::new(model, bytes)
+    }
+
+    /// The model for `Preset::Binary`, nothing text specific
+    fn init_binary_model(&self) -> impl Model + Tables + State {
+        Order1::with_ctx_bits(self.ctx_bits)
+    }
+
+    /// The model for bytes coded as codewords of `code`
+    fn init_codeword_model(&self, code: CanonicalCode) -> impl Model {
+        // the stationary AC hash models bytes, not codewords - the raw
+        // codeword bits make the better context (as in `ac-over-huffman`)
+        OrderNCodeword::new(self.ctx_bits, code, RawHistory::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::{corpus, generate, Kind};
+    use crate::transform::TextOptions;
+    use std::time::Duration;
+
+    #[test]
+    fn round_trip() {
+        for (kind, input) in corpus(1 << 12, 0) {
+            let mut compressed = Vec::new();
+            compress(input.as_slice(), input.len() as u64, &mut compressed).unwrap();
+            let mut decompressed = Vec::new();
+            decompress(compressed.as_slice(), &mut decompressed).unwrap();
+            assert_eq!(input, decompressed, "{}", kind.name());
+        }
+    }
+
+    #[test]
+    fn models_round_trip() {
+        for (memory, hash) in [
+            (DEFAULT_MEMORY, HashModel::Stationary),
+            (1 << 20, HashModel::Stationary),
+            (1 << 20, HashModel::Adaptive0),
+            (1 << 20, HashModel::Adaptive1),
+        ] {
+            let models = TextModels { states: true, run: true, bytes: true, hash };
+            let options = Options::default().memory(memory).models(models);
+            let config = ModelConfig::with_models(memory, Preset::Text, false, models);
+            for (kind, input) in corpus(1 << 12, 11) {
+                let mut compressed = Vec::new();
+                let len = input.len() as u64;
+                compress_with(input.as_slice(), len, &mut compressed, &options).unwrap();
+                let mut decompressed = Vec::new();
+                let limits = Limits::unlimited();
+                let summary =
+                    decompress_with_limits(compressed.as_slice(), &mut decompressed, &limits)
+                        .unwrap();
+                assert_eq!(input, decompressed, "{}", kind.name());
+
+                let header = Header::read(&mut compressed.as_slice()).unwrap();
+                if header.preset == Preset::Text {
+                    assert_eq!(header.models, models);
+                    assert_eq!(summary.total_memory(), config.memory());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn preset_round_trip() {
+        for preset in Preset::ALL {
+            let options = Options::default().preset(preset);
+            for (kind, input) in corpus(1 << 12, 10) {
+                let mut compressed = Vec::new();
+                let len = input.len() as u64;
+                let summary =
+                    compress_with(input.as_slice(), len, &mut compressed, &options).unwrap();
+                // what the preset would expand is stored
+                let expected = match kind {
+                    Kind::Random | Kind::SingleByte | Kind::Empty => Preset::Store,
+                    _ => preset,
+                };
+                assert_eq!(summary.preset, expected, "{}", kind.name());
+                let mut decompressed = Vec::new();
+                let summary = decompress_with_limits(
+                    compressed.as_slice(),
+                    &mut decompressed,
+                    &Limits::unlimited(),
+                )
+                .unwrap();
+                assert_eq!(input, decompressed, "{}", kind.name());
+                assert_eq!(summary.preset, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn expanding_stream_is_stored() {
+        let input = generate(Kind::Random, 1 << 14, 13);
+        let len = input.len() as u64;
+        for options in [
+            Options::default().preset(Preset::Text),
+            Options::default().preset(Preset::Binary),
+            Options::default().huffman(true).preset(Preset::Text),
+            Options::default().dedup(true).preset(Preset::Binary),
+            Options::default()
+                .text(TextOptions::default())
+                .preset(Preset::Text),
+        ] {
+            let mut compressed = Vec::new();
+            let summary = compress_with(input.as_slice(), len, &mut compressed, &options).unwrap();
+            assert_eq!(summary.preset, Preset::Store, "{:?}", options);
+            assert_eq!(compressed.len(), Header::FIXED_SIZE + input.len());
+            let mut decompressed = Vec::new();
+            decompress(compressed.as_slice(), &mut decompressed).unwrap();
+            assert_eq!(input, decompressed, "{:?}", options);
+        }
+    }
+
+    #[test]
+    fn blocks_round_trip() {
+        let text = TextOptions::default();
+        // text, then noise the models can only expand, then text again
+        let mut input = generate(Kind::Markov, 3 << 10, 12);
+        input.extend(generate(Kind::Random, 2 << 10, 12));
+        input.extend(generate(Kind::Markov, 3 << 10, 13));
+        for options in [
+            Options::default().blocks(1 << 10),
+            Options::default().blocks(1000).preset(Preset::Binary),
+            Options::default().blocks(1 << 10).huffman(true),
+            Options::default().blocks(777).text(text).dedup(true),
+            Options::default().blocks(1),
+        ] {
+            let mut compressed = Vec::new();
+            let len = input.len() as u64;
+            compress_with(input.as_slice(), len, &mut compressed, &options).unwrap();
+            let mut decompressed = Vec::new();
+            decompress(compressed.as_slice(), &mut decompressed).unwrap();
+            assert_eq!(input, decompressed, "{:?}", options);
+
+            // no block grows by more than its framing
+            let block_size = options.block_size.unwrap();
+            let blocks = len.div_ceil(block_size);
+            assert!(compressed.len() as u64 <= 64 + len + 5 * blocks);
+        }
+
+        let options = Options::default().blocks(1 << 10);
+        let mut compressed = Vec::new();
+        let len = input.len() as u64;
+        compress_with(input.as_slice(), len, &mut compressed, &options).unwrap();
+        let stored = compressed.len() as u64 - Header::FIXED_SIZE as u64 - 8;
+        assert!(stored < len);
+        compressed.truncate(compressed.len() - 1);
+        let err = decompress(compressed.as_slice(), io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn random_is_stored() {
+        let input = generate(Kind::Random, 1 << 12, 11);
+        let mut compressed = Vec::new();
+        let summary = compress_with(
+            input.as_slice(),
+            input.len() as u64,
+            &mut compressed,
+            &Options::default().huffman(true),
+        )
+        .unwrap();
+        assert_eq!(summary.preset, Preset::Store);
+        assert_eq!(compressed.len(), Header::FIXED_SIZE + input.len());
+
+        let err = decompress(&compressed[..compressed.len() - 1], io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let limits = Limits::unlimited().max_output(input.len() as u64 - 1);
+        let err = decompress_with_limits(compressed.as_slice(), io::sink(), &limits).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+    }
+
+    #[test]
+    fn huffman_round_trip() {
+        let options = Options::default().huffman(true);
+        for (kind, input) in corpus(1 << 12, 0) {
+            let mut compressed = Vec::new();
+            let len = input.len() as u64;
+            compress_with(input.as_slice(), len, &mut compressed, &options).unwrap();
+            let mut decompressed = Vec::new();
+            decompress(compressed.as_slice(), &mut decompressed).unwrap();
+            assert_eq!(input, decompressed, "{}", kind.name());
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let text = TextOptions::default().eol(true);
+        for options in [
+            Options::default().text(text.clone()),
+            Options::default().text(text).huffman(true),
+        ] {
+            let mut inputs = corpus(1 << 12, 6);
+            inputs.push((Kind::Markov, b"The END\r\nthe end\r\n".repeat(64)));
+            for (kind, input) in inputs {
+                let mut compressed = Vec::new();
+                let len = input.len() as u64;
+                compress_with(input.as_slice(), len, &mut compressed, &options).unwrap();
+                let mut decompressed = Vec::new();
+                decompress(compressed.as_slice(), &mut decompressed).unwrap();
+                assert_eq!(input, decompressed, "{}", kind.name());
+            }
+        }
+    }
+
+    #[test]
+    fn dedup_round_trip() {
+        let text = TextOptions::default();
+        for options in [
+            Options::default().dedup(true),
+            Options::default().dedup(true).text(text).huffman(true),
+        ] {
+            let mut inputs = corpus(1 << 12, 9);
+            let random = generate(Kind::Random, 1 << 10, 9);
+            inputs.push((Kind::Repeated, [&random[..], b"The end", &random].concat()));
+            for (kind, input) in inputs {
+                let mut compressed = Vec::new();
+                let len = input.len() as u64;
+                compress_with(input.as_slice(), len, &mut compressed, &options).unwrap();
+                let mut decompressed = Vec::new();
+                decompress(compressed.as_slice(), &mut decompressed).unwrap();
+                assert_eq!(input, decompressed, "{}", kind.name());
+            }
+        }
+    }
+
+    #[test]
+    fn detects_on_whole_sample() {
+        // a text start, the rest of the sample random
+        let mut input = b"Once upon a time".to_vec();
+        input.extend(generate(Kind::Random, SAMPLE_SIZE, 65));
+        let len = input.len() as u64;
+        let options = Options::default();
+        let mut expected = Vec::new();
+        let summary = compress_with(input.as_slice(), len, &mut expected, &options).unwrap();
+        assert_eq!(summary.preset, Preset::Store);
+        for capacity in [1, 16, 1 << 10] {
+            let reader = io::BufReader::with_capacity(capacity, input.as_slice());
+            let mut compressed = Vec::new();
+            compress_with(reader, len, &mut compressed, &options).unwrap();
+            assert_eq!(compressed, expected, "capacity {}", capacity);
+        }
+    }
+
+    #[test]
+    fn wrong_length() {
+        let input = generate(Kind::Markov, 1000, 64);
+        for options in [
+            Options::default().preset(Preset::Text),
+            Options::default().preset(Preset::Binary),
+            Options::default().huffman(true),
+            Options::default().blocks(300),
+            Options::default().blocks(300).seekable(true),
+        ] {
+            for len in [999, 1001, 1200] {
+                let err = compress_with(input.as_slice(), len, io::sink(), &options).unwrap_err();
+                assert_eq!(
+                    err.kind(),
+                    ErrorKind::InvalidInput,
+                    "{:?}, {}",
+                    options,
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn buffer_limits() {
+        let input = corpus(1 << 12, 10).swap_remove(2).1;
+        let options = Options::default().dedup(true).text(TextOptions::default());
+        let mut compressed = Vec::new();
+        let len = input.len() as u64;
+        compress_with(input.as_slice(), len, &mut compressed, &options).unwrap();
+        let header = Header::read(&mut compressed.as_slice()).unwrap();
+
+        let model_memory = ModelConfig::new(DEFAULT_MEMORY, Preset::Text, false).memory();
+        let memory = model_memory + buffer_memory(&header);
+        let limits = Limits::unlimited().max_memory(memory);
+        let mut decompressed = Vec::new();
+        decompress_with_limits(compressed.as_slice(), &mut decompressed, &limits).unwrap();
+        assert_eq!(decompressed, input);
+
+        let limits = Limits::unlimited().max_memory(memory - 1);
+        let err = decompress_with_limits(compressed.as_slice(), io::sink(), &limits).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+    }
+
+    #[test]
+    fn invalid_options() {
+        let options = Options { memory: MAX_MEMORY + 1, ..Options::default() };
+        let err = compress_with(&b"hello"[..], 5, io::sink(), &options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let options = Options { block_size: Some(0), ..Options::default() };
+        let err = compress_with(&b"hello"[..], 5, io::sink(), &options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn text_wrong_length() {
+        let options = Options::default().text(TextOptions::default());
+        let err = compress_with(&b"hello"[..], 6, io::sink(), &options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        // long enough not to be stored
+        let input = b"hello".repeat(64);
+        let mut compressed = Vec::new();
+        compress_with(input.as_slice(), 320, &mut compressed, &options).unwrap();
+        compressed[4..12].copy_from_slice(&319_u64.to_be_bytes());
+        let err = decompress(compressed.as_slice(), io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn huffman_bits_limit() {
+        // 2 symbols code as 1 bit each
+        let input = b"abba".repeat(256);
+        let options = Options::default().huffman(true);
+        let mut compressed = Vec::new();
+        compress_with(input.as_slice(), 1024, &mut compressed, &options).unwrap();
+        let limits = Limits::unlimited().max_bits(1024);
+        let mut decompressed = Vec::new();
+        decompress_with_limits(compressed.as_slice(), &mut decompressed, &limits).unwrap();
+        assert_eq!(input, decompressed);
+
+        let limits = Limits::unlimited().max_bits(1023);
+        let err = decompress_with_limits(compressed.as_slice(), io::sink(), &limits).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+    }
+
+    #[test]
+    fn memory_budget() {
+        let input = corpus(1 << 12, 4).swap_remove(2).1;
+        for memory in [0, 1 << 10, DEFAULT_MEMORY, 1 << 20] {
+            let options = Options::default().memory(memory);
+            let mut compressed = Vec::new();
+            let summary = compress_with(
+                input.as_slice(),
+                input.len() as u64,
+                &mut compressed,
+                &options,
+            )
+            .unwrap();
+            assert!(summary.total_memory() as u64 <= memory.max(64));
+
+            let mut decompressed = Vec::new();
+            let limits = Limits::unlimited();
+            let summary =
+                decompress_with_limits(compressed.as_slice(), &mut decompressed, &limits).unwrap();
+            assert_eq!(input, decompressed);
+            assert_eq!(
+                summary.total_memory(),
+                ModelConfig::new(memory, Preset::Text, false).memory()
+            );
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let input = corpus(1 << 12, 3).swap_remove(2).1;
+        let mut compressed = Vec::new();
+        compress(input.as_slice(), input.len() as u64, &mut compressed).unwrap();
+        let decompress = |limits: Limits| {
+            let mut decompressed = Vec::new();
+            decompress_with_limits(compressed.as_slice(), &mut decompressed, &limits)
+                .map(|_| decompressed)
+        };
+
+        let model_memory = ModelConfig::new(DEFAULT_MEMORY, Preset::Text, false).memory();
+        let exact = Limits::unlimited()
+            .max_output(1 << 12)
+            .max_bits(1 << 15)
+            .max_memory(model_memory);
+        assert_eq!(decompress(exact).unwrap(), input);
+
+        let err = decompress(Limits::unlimited().max_output((1 << 12) - 1)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+        let err = decompress(Limits::unlimited().max_bits((1 << 15) - 1)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+        let err = decompress(Limits::unlimited().max_memory(model_memory - 1)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+        let err = decompress(Limits::unlimited().max_time(Duration::ZERO)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn lying_length_with_limit() {
+        let mut compressed = Vec::new();
+        compress(&b"hello"[..], 5, &mut compressed).unwrap();
+        compressed[4..12].copy_from_slice(&u64::MAX.to_be_bytes());
+        let limits = Limits::unlimited().max_output(1 << 30);
+        let err = decompress_with_limits(compressed.as_slice(), io::sink(), &limits).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+    }
+
+    #[test]
+    fn lying_memory() {
+        // unlimited decoding must not try to allocate what the header asks for
+        let mut compressed = Vec::new();
+        compress(&b"hello"[..], 5, &mut compressed).unwrap();
+        compressed[12..20].copy_from_slice(&u64::MAX.to_be_bytes());
+        let err = decompress(compressed.as_slice(), io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut compressed = Vec::new();
+        compress(&b"hello"[..], 5, &mut compressed).unwrap();
+        compressed[0] = b'x';
+        let err = decompress(compressed.as_slice(), io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn lying_length() {
+        let input = corpus(1 << 12, 1).swap_remove(0).1;
+        let mut compressed = Vec::new();
+        compress(input.as_slice(), input.len() as u64, &mut compressed).unwrap();
+        // claim the input was much longer than it is
+        compressed[4..12].copy_from_slice(&u64::MAX.to_be_bytes());
+        let err = decompress(compressed.as_slice(), io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let input = corpus(1 << 12, 2).swap_remove(0).1;
+        let mut compressed = Vec::new();
+        compress(input.as_slice(), input.len() as u64, &mut compressed).unwrap();
+        compressed.truncate(compressed.len() / 2);
+        let err = decompress(compressed.as_slice(), io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,271 @@
+use super::{Preset, MAX_BLOCK_SIZE};
+use crate::transform::TextOptions;
+use std::io::{self, Error, ErrorKind};
+
+/// Model memory used when no budget is given, the historic fixed model size
+pub const DEFAULT_MEMORY: u64 = 8 << 10;
+/// Largest memory budget, headers asking for more are rejected before
+/// anything is allocated
+pub const MAX_MEMORY: u64 = 1 << 30;
+/// Block size of seekable streams when no block size is given
+pub const DEFAULT_SEEK_BLOCK_SIZE: u64 = 1 << 20;
+
+/// Settings for `compress_with`, everything the decoder needs ends up in the header
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Memory budget in bytes, distributed across all models
+    pub memory: u64,
+    /// The models to use, `None` picks them with `Preset::detect` on the
+    /// start of the input. `Preset::Store` ignores all the other options.
+    pub preset: Option<Preset>,
+    /// Code every byte as its Huffman codeword instead of its 8 bits, fewer
+    /// binary decisions on skewed inputs like text (reads the whole input first)
+    pub huffman: bool,
+    /// Cut long repeats out of the input before modeling (reads the whole input)
+    pub dedup: bool,
+    /// Code the stream in blocks of this many bytes, blocks the models would
+    /// expand are stored instead
+    pub block_size: Option<u64>,
+    /// Code every block with fresh models and end the stream in an index, so
+    /// `SeekableReader` can decode any byte range (`DEFAULT_SEEK_BLOCK_SIZE`
+    /// unless `block_size` is set, incompatible with the transforms)
+    pub seekable: bool,
+    /// Run the reversible text transform before modeling (reads the whole input)
+    pub text: Option<TextOptions>,
+    /// Models mixed into `Preset::Text`, if that's the preset used
+    pub models: TextModels,
+}
+
+impl Options {
+    pub fn memory(mut self, bytes: u64) -> Self {
+        self.memory = bytes;
+        self
+    }
+
+    pub fn preset(mut self, preset: Preset) -> Self {
+        self.preset = Some(preset);
+        self
+    }
+
+    pub fn huffman(mut self, huffman: bool) -> Self {
+        self.huffman = huffman;
+        self
+    }
+
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    pub fn blocks(mut self, block_size: u64) -> Self {
+        self.block_size = Some(block_size);
+        self
+    }
+
+    pub fn seekable(mut self, seekable: bool) -> Self {
+        self.seekable = seekable;
+        self
+    }
+
+    pub fn text(mut self, text: TextOptions) -> Self {
+        self.text = Some(text);
+        self
+    }
+
+    pub fn models(mut self, models: TextModels) -> Self {
+        self.models = models;
+        self
+    }
+
+    /// Fails with `InvalidInput` if the memory budget or block size is out of
+    /// range, what every compressor checks before writing anything
+    pub fn check(&self) -> io::Result<()> {
+        if self.memory > MAX_MEMORY {
+            let msg = format!(
+                "Memory must be at most {} bytes, got {}",
+                MAX_MEMORY, self.memory
+            );
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+        if let Some(block_size) = self
+            .block_size
+            .filter(|size| !(1..=MAX_BLOCK_SIZE).contains(size))
+        {
+            let msg = format!(
+                "Block size must be in 1..={}, got {}",
+                MAX_BLOCK_SIZE, block_size
+            );
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+        let text_preset = matches!(self.preset, None | Some(Preset::Text));
+        if self.models != TextModels::default() && (self.huffman || !text_preset) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Models only mix into the text preset, without Huffman coding",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            memory: DEFAULT_MEMORY,
+            preset: None,
+            huffman: false,
+            dedup: false,
+            block_size: None,
+            seekable: false,
+            text: None,
+            models: TextModels::default(),
+        }
+    }
+}
+
+/// Models mixed with the entropy hashed order-N model of `Preset::Text`,
+/// recorded in the header - the budget is split evenly between all of them.
+/// By default the order-N model codes alone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextModels {
+    /// Order-2 bit-history states, predicted through a learned `StateMap`
+    pub states: bool,
+    /// The byte that followed the last 3 bytes last time (`RunModel`)
+    pub run: bool,
+    /// Order-4 counters over whole bytes (`OrderNBytes`)
+    pub bytes: bool,
+    /// The model the order-N history is hashed with
+    pub hash: HashModel,
+}
+
+/// Probabilities the order-N model's history is coded with into a hash.
+/// Adaptive ones learn the stream's bits, with fixed size tables on top of
+/// the memory budget.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashModel {
+    /// Fixed per-position probabilities of English text
+    #[default]
+    Stationary,
+    /// Learned order-0 probabilities (`AdaptiveOrder0`)
+    Adaptive0,
+    /// Learned order-1 probabilities (`AdaptiveOrder1`)
+    Adaptive1,
+}
+
+impl TextModels {
+    /// Parses a comma separated list of model names: `states`, `run`, `bytes`
+    /// and at most one hash model, `adaptive0` or `adaptive1`
+    pub fn from_names(names: &str) -> Option<Self> {
+        let mut models = Self::default();
+        for name in names.split(',') {
+            match name.trim() {
+                "states" => models.states = true,
+                "run" => models.run = true,
+                "bytes" => models.bytes = true,
+                "adaptive0" | "adaptive1" if models.hash != HashModel::Stationary => return None,
+                "adaptive0" => models.hash = HashModel::Adaptive0,
+                "adaptive1" => models.hash = HashModel::Adaptive1,
+                _ => return None,
+            }
+        }
+        Some(models)
+    }
+
+    /// Number of models mixed in, the hash model isn't one of them
+    pub fn count(&self) -> usize {
+        usize::from(self.states) + usize::from(self.run) + usize::from(self.bytes)
+    }
+}
+
+/// What `compress_with`/`decompress_with_limits` allocated
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    /// The models used
+    pub preset: Preset,
+    /// Bytes allocated by each model, by name
+    pub memory_usage: Vec<(&'static str, usize)>,
+}
+
+impl Summary {
+    pub fn total_memory(&self) -> usize {
+        self.memory_usage.iter().map(|&(_, bytes)| bytes).sum()
+    }
+}
+
+/// Parses sizes like `256MB`, `64k` or `1048576` (binary multiples)
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim().to_ascii_uppercase();
+    let size = size.strip_suffix('B').unwrap_or(&size);
+    let (num, shift) = match size.chars().last()? {
+        'K' => (&size[..size.len() - 1], 10),
+        'M' => (&size[..size.len() - 1], 20),
+        'G' => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+    num.trim().parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("256MB"), Some(256 << 20));
+        assert_eq!(parse_size("64k"), Some(64 << 10));
+        assert_eq!(parse_size("1 GB"), Some(1 << 30));
+        assert_eq!(parse_size("100B"), Some(100));
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("MB"), None);
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size("99999999999G"), None);
+    }
+
+    #[test]
+    fn check() {
+        assert!(Options::default().memory(MAX_MEMORY).check().is_ok());
+        assert!(Options::default().blocks(MAX_BLOCK_SIZE).check().is_ok());
+        let invalid = [
+            Options::default().memory(MAX_MEMORY + 1),
+            Options::default().blocks(0),
+            Options::default().blocks(MAX_BLOCK_SIZE + 1),
+        ];
+        for options in invalid {
+            let err = options.check().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn models() {
+        let states = TextModels { states: true, ..TextModels::default() };
+        assert_eq!(TextModels::from_names("states"), Some(states));
+        let all = TextModels {
+            states: true,
+            run: true,
+            bytes: true,
+            ..TextModels::default()
+        };
+        assert_eq!(TextModels::from_names("run, bytes,states"), Some(all));
+        assert_eq!(all.count(), 3);
+        let adaptive = TextModels { hash: HashModel::Adaptive1, ..all };
+        assert_eq!(
+            TextModels::from_names("run,adaptive1,bytes,states"),
+            Some(adaptive)
+        );
+        assert_eq!(adaptive.count(), 3);
+        assert_eq!(TextModels::from_names("adaptive0,adaptive1"), None);
+        assert_eq!(TextModels::from_names("states,unknown"), None);
+        assert_eq!(TextModels::from_names(""), None);
+
+        assert!(Options::default().models(states).check().is_ok());
+        for options in [
+            Options::default().models(states).huffman(true),
+            Options::default().models(states).preset(Preset::Binary),
+        ] {
+            let err = options.check().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+}
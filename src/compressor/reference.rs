@@ -0,0 +1,220 @@
+use std::fmt;
+use std::io::{self, Error, ErrorKind};
+use std::mem::size_of;
+
+use super::Header;
+use crate::helpers::{hash_bytes, hash_u64, table_bits};
+use crate::models::{train, BestOfTwoModel, MatchModel, Model};
+
+// match table sizes, about one entry per byte of the reference
+const MIN_MATCH_TABLE_BITS: u8 = 16;
+const MAX_MATCH_TABLE_BITS: u8 = 24;
+
+/// A file the decompressing side already has, e.g. the previous version of
+/// the input, to compress the input as a delta against
+///
+/// Both sides run the models and a match model over the reference before
+/// coding, so whatever the input shares with it costs next to nothing. Streams
+/// record the reference's `hash` and only decode with the same reference.
+#[derive(Clone, Copy)]
+pub struct Reference<'a> {
+    bytes: &'a [u8],
+    /// Hash of the bytes, identifies the reference in headers
+    pub hash: u64,
+}
+
+impl<'a> Reference<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        let hash = hash_bytes(hash_u64(bytes.len() as u64), bytes);
+        Self { bytes, hash }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// `model` next to a match model, both trained on the reference
+    pub(super) fn prime<M: Model>(&self, model: M) -> BestOfTwoModel<M, MatchModel> {
+        let mut model = BestOfTwoModel::new(model, MatchModel::new(self.table_bits()));
+        train(&mut model, self.bytes);
+        model
+    }
+
+    /// Bytes the match model of `prime` needs to code `len` more bytes: its
+    /// table and its history of the reference and those bytes, counted twice
+    /// as the history grows by doubling
+    pub(super) fn memory(&self, len: u64) -> usize {
+        let history = usize::try_from(len)
+            .unwrap_or(usize::MAX)
+            .saturating_add(self.bytes.len());
+        (size_of::<u32>() << self.table_bits()).saturating_add(history.saturating_mul(2))
+    }
+
+    fn table_bits(&self) -> u8 {
+        table_bits(self.bytes.len(), 1).clamp(MIN_MATCH_TABLE_BITS, MAX_MATCH_TABLE_BITS)
+    }
+
+    /// The reference to decode the stream behind `header` against, fails with
+    /// `InvalidInput` unless `reference` is the one it was compressed against
+    pub(super) fn for_header<'b>(
+        header: &Header,
+        reference: Option<&'b Self>,
+    ) -> io::Result<Option<&'b Self>> {
+        let Some(hash) = header.reference else {
+            return Ok(None);
+        };
+        match reference {
+            Some(reference) if reference.hash == hash => Ok(Some(reference)),
+            Some(reference) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Stream needs reference {:016x}, got {:016x}",
+                    hash, reference.hash
+                ),
+            )),
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Stream needs reference {:016x}", hash),
+            )),
+        }
+    }
+}
+
+// the bytes can be as large as any input, leave them out
+impl fmt::Debug for Reference<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reference")
+            .field("hash", &format_args!("{:016x}", self.hash))
+            .field("len", &self.bytes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::{
+        compress_with, compress_with_reference, decompress, decompress_with_reference, Limits,
+        ModelConfig, Options, Preset,
+    };
+    use crate::corpus::{generate, Kind};
+
+    /// The next version of `reference`: a bit cut, a bit replaced, a bit added
+    fn edited(reference: &[u8]) -> Vec<u8> {
+        let len = reference.len();
+        let mut input = reference[..len / 3].to_vec();
+        input.extend(generate(Kind::Markov, 200, 60));
+        input.extend(&reference[len / 3 + 500..len * 2 / 3]);
+        input.extend(&reference[len * 2 / 3 + 10..]);
+        input.extend(b"appendix");
+        input
+    }
+
+    fn compress(input: &[u8], options: &Options, reference: Option<&Reference>) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        let len = input.len() as u64;
+        match reference {
+            Some(reference) => {
+                compress_with_reference(input, len, &mut compressed, options, reference)
+            }
+            None => compress_with(input, len, &mut compressed, options),
+        }
+        .unwrap();
+        compressed
+    }
+
+    #[test]
+    fn delta() {
+        let limits = Limits::unlimited();
+        for (kind, options) in [
+            (Kind::Markov, Options::default()),
+            (Kind::Random, Options::default().preset(Preset::Binary)),
+            // detection alone would store it
+            (Kind::Random, Options::default()),
+            (Kind::Markov, Options::default().blocks(1 << 12)),
+            (Kind::Markov, Options::default().dedup(true)),
+        ] {
+            let bytes = generate(kind, 32 << 10, 61);
+            let reference = Reference::new(&bytes);
+            let input = edited(&bytes);
+            let cold = compress(&input, &options, None);
+            let delta = compress(&input, &options, Some(&reference));
+            assert!(
+                delta.len() < cold.len() / 4,
+                "{:?}: {} vs {} bytes",
+                options,
+                delta.len(),
+                cold.len()
+            );
+
+            let mut decompressed = Vec::new();
+            decompress_with_reference(delta.as_slice(), &mut decompressed, &limits, &reference)
+                .unwrap();
+            assert_eq!(input, decompressed, "{:?}", options);
+        }
+    }
+
+    #[test]
+    fn reference_memory() {
+        let bytes = generate(Kind::Markov, 8 << 10, 63);
+        let reference = Reference::new(&bytes);
+        let input = edited(&bytes);
+        let delta = compress(&input, &Options::default(), Some(&reference));
+
+        let header = Header::read(&mut delta.as_slice()).unwrap();
+        let config = ModelConfig::new(header.memory, header.preset, false);
+        let memory = config.memory() + reference.memory(header.coded_len());
+        let summary = decompress_with_reference(
+            delta.as_slice(),
+            io::sink(),
+            &Limits::unlimited().max_memory(memory),
+            &reference,
+        )
+        .unwrap();
+        assert!(summary.total_memory() <= memory);
+        // the match model counts too
+        let limits = Limits::unlimited().max_memory(config.memory());
+        let err = decompress_with_reference(delta.as_slice(), io::sink(), &limits, &reference)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+    }
+
+    #[test]
+    fn wrong_reference() {
+        let bytes = generate(Kind::Markov, 8 << 10, 62);
+        let reference = Reference::new(&bytes);
+        let other_bytes = edited(&bytes);
+        let other = Reference::new(&other_bytes);
+        assert_ne!(reference.hash, other.hash);
+        assert_ne!(reference.hash, Reference::new(&bytes[1..]).hash);
+        let input = edited(&bytes);
+        let delta = compress(&input, &Options::default(), Some(&reference));
+
+        let err = decompress(delta.as_slice(), io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let limits = Limits::unlimited();
+        let err =
+            decompress_with_reference(delta.as_slice(), io::sink(), &limits, &other).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        // a reference a stream doesn't need is ignored
+        let plain = compress(&input, &Options::default(), None);
+        let mut decompressed = Vec::new();
+        decompress_with_reference(plain.as_slice(), &mut decompressed, &limits, &other).unwrap();
+        assert_eq!(input, decompressed);
+
+        let len = input.len() as u64;
+        for options in [
+            Options::default().huffman(true),
+            Options::default().seekable(true),
+        ] {
+            let err = compress_with_reference(&input[..], len, io::sink(), &options, &reference)
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::io::{self, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::time::Instant;
+
+use super::blocks::{seek_index, Index};
+use super::{decode, Dictionary, Header, Limits, ModelConfig, Preset, Reference, Stream};
+use crate::entropy_coding::huffman::CanonicalCode;
+use crate::usize;
+
+/// Reads the decompressed bytes of a seekable stream (or a stored one) at any
+/// position, decoding only the blocks the reads touch
+pub struct SeekableReader<R: Read + Seek> {
+    inner: R,
+    header: Header,
+    config: ModelConfig,
+    code: Option<CanonicalCode>,
+    dictionary: Option<Dictionary>,
+    index: Index,
+    /// Where the last block ends and the index starts
+    blocks_end: u64,
+    limits: Limits,
+    pos: u64,
+    /// The last decoded block and its number
+    block: Option<(usize, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    /// Reads the header and the index of the stream in `inner`, fails with
+    /// `InvalidInput` for streams compressed without `Options::seekable`
+    pub fn new(inner: R) -> io::Result<Self> {
+        Self::open(inner, None)
+    }
+
+    /// `new` for a stream compressed with `dictionary`
+    pub fn with_dictionary(inner: R, dictionary: Dictionary) -> io::Result<Self> {
+        Self::open(inner, Some(dictionary))
+    }
+
+    fn open(mut inner: R, dictionary: Option<Dictionary>) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(0))?;
+        let header = Header::read(&mut inner)?;
+        Dictionary::for_header(&header, dictionary.as_ref())?;
+        // a reference would be run over again for every block
+        Reference::for_header(&header, None)?;
+        // streams without one decode without it
+        let dictionary = dictionary.filter(|_| header.dictionary.is_some());
+        let code = header.huffman.as_deref().map(|code_lens| {
+            CanonicalCode::from_code_lens(code_lens).expect("Header::read validates the code")
+        });
+        let config = ModelConfig::for_header(&header);
+        let (index, blocks_end) = match header.block_size {
+            _ if header.preset == Preset::Store => (Index::new(), 0),
+            Some(block_size) if header.seekable => {
+                seek_index(&mut inner, header.len, block_size, header.size() as u64)?
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Stream wasn't compressed as seekable",
+                ))
+            }
+        };
+        Ok(Self {
+            inner,
+            header,
+            config,
+            code,
+            dictionary,
+            index,
+            blocks_end,
+            limits: Limits::unlimited(),
+            pos: 0,
+            block: None,
+        })
+    }
+
+    /// Length of the decompressed stream
+    pub fn len(&self) -> u64 {
+        self.header.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.len == 0
+    }
+
+    /// Reads from the stored input behind the header
+    fn read_stored(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = (self.header.len - self.pos).min(buf.len() as u64);
+        let start = self.header.size() as u64 + self.pos;
+        self.inner.seek(SeekFrom::Start(start))?;
+        self.inner.read_exact(&mut buf[..usize!(len)])?;
+        Ok(usize!(len))
+    }
+
+    /// Decodes block `i`, unless it's the cached one
+    fn load_block(&mut self, i: usize) -> io::Result<&[u8]> {
+        if self.block.as_ref().is_none_or(|(cached, _)| *cached != i) {
+            let block_size = self
+                .header
+                .block_size
+                .expect("seekable streams have blocks");
+            let (start, offset) = self.index[i];
+            let stream = Stream {
+                len: (self.header.len - start).min(block_size),
+                block_size: Some(block_size),
+                independent: true,
+                limits: &self.limits,
+                timer: Instant::now(),
+            };
+            self.inner.seek(SeekFrom::Start(offset))?;
+            let mut bytes = Vec::new();
+            let (code, dictionary) = (self.code.as_ref(), self.dictionary.as_ref());
+            decode(
+                &stream,
+                code,
+                &self.config,
+                dictionary,
+                None,
+                &mut self.inner,
+                &mut bytes,
+            )?;
+            // the index only points at the start of blocks, where the next
+            // one starts is the check it points at the right bytes
+            let end = self
+                .index
+                .get(i + 1)
+                .map_or(self.blocks_end, |&(_, next)| next);
+            if self.inner.stream_position()? != end {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Seek index doesn't match the blocks",
+                ));
+            }
+            self.block = Some((i, bytes));
+        }
+        Ok(&self.block.as_ref().unwrap().1)
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.header.len || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.header.preset == Preset::Store {
+            let read = self.read_stored(buf)?;
+            self.pos += read as u64;
+            return Ok(read);
+        }
+
+        let block_size = self
+            .header
+            .block_size
+            .expect("seekable streams have blocks");
+        let pos = self.pos;
+        let block = self.load_block(usize!(pos / block_size))?;
+        let bytes = &block[usize!(pos % block_size)..];
+        let read = bytes.len().min(buf.len());
+        buf[..read].copy_from_slice(&bytes[..read]);
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.header.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        // past the end is fine, reads there return nothing
+        self.pos = pos.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "Seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::{compress_with, decompress, Options};
+    use crate::corpus::{generate, Kind, Rng};
+    use std::io::Cursor;
+
+    fn compressed(input: &[u8], options: &Options) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        compress_with(input, input.len() as u64, &mut compressed, options).unwrap();
+        compressed
+    }
+
+    #[test]
+    fn random_ranges() {
+        let mut input = generate(Kind::Markov, 5 << 10, 14);
+        input.extend(generate(Kind::Random, 2 << 10, 14));
+        let mut rng = Rng::new(14);
+        for options in [
+            Options::default().seekable(true).blocks(1 << 10),
+            Options::default().seekable(true).blocks(1000).huffman(true),
+            Options::default()
+                .seekable(true)
+                .blocks(999)
+                .preset(Preset::Binary),
+            Options::default().seekable(true).preset(Preset::Store),
+            Options::default().seekable(true),
+        ] {
+            let compressed = compressed(&input, &options);
+            let mut decompressed = Vec::new();
+            decompress(compressed.as_slice(), &mut decompressed).unwrap();
+            assert_eq!(input, decompressed, "{:?}", options);
+
+            let mut reader = SeekableReader::new(Cursor::new(compressed)).unwrap();
+            assert_eq!(reader.len(), input.len() as u64);
+            for _ in 0..20 {
+                let start = rng.below(input.len());
+                let len = rng.below(3000);
+                let end = (start + len).min(input.len());
+                reader.seek(SeekFrom::Start(start as u64)).unwrap();
+                let mut range = Vec::new();
+                reader
+                    .by_ref()
+                    .take(len as u64)
+                    .read_to_end(&mut range)
+                    .unwrap();
+                assert_eq!(range, input[start..end], "{:?}", options);
+            }
+        }
+    }
+
+    #[test]
+    fn seeks() {
+        let input = generate(Kind::Markov, 3 << 10, 15);
+        let options = Options::default().seekable(true).blocks(1 << 10);
+        let mut reader = SeekableReader::new(Cursor::new(compressed(&input, &options))).unwrap();
+        let len = input.len() as u64;
+        assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), len - 10);
+        assert_eq!(reader.seek(SeekFrom::Current(5)).unwrap(), len - 5);
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, input[input.len() - 5..]);
+
+        assert_eq!(reader.seek(SeekFrom::End(10)).unwrap(), len + 10);
+        assert_eq!(reader.read(&mut [0; 4]).unwrap(), 0);
+        let err = reader
+            .seek(SeekFrom::Current(-(len as i64) - 11))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn rejects_bad_streams() {
+        let input = generate(Kind::Markov, 3 << 10, 16);
+        let err = SeekableReader::new(Cursor::new(compressed(&input, &Options::default())))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let options = Options::default().seekable(true).blocks(1 << 10);
+        let mut compressed = compressed(&input, &options);
+        // the second block's offset
+        let entry = compressed.len() - 12 - 2 * 16 + 8;
+        compressed[entry + 7] ^= 1;
+        let mut reader = SeekableReader::new(Cursor::new(compressed.as_slice())).unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        let err = SeekableReader::new(Cursor::new(compressed.as_slice()))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = decompress(compressed.as_slice(), io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let err = compress_with(
+            input.as_slice(),
+            input.len() as u64,
+            io::sink(),
+            &options.dedup(true),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
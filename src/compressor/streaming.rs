@@ -0,0 +1,520 @@
+use std::io::{self, Error, ErrorKind, Write};
+use std::time::Instant;
+
+use super::{
+    check_len, encode_bits, ByteBits, ByteCoding, Header, Limits, ModelConfig, Options, Preset,
+    Summary, TextModels, MAX_OVERRUN, TIME_CHECK_INTERVAL,
+};
+use crate::entropy_coding::{
+    arithmetic_coder::{ACRead, ArithmeticCoder},
+    io::ACWriter,
+};
+use crate::models::Model;
+use crate::u8;
+
+/// Compresses input handed over piece by piece, into the same stream
+/// `compress_with` writes for the options, unless that one is stored because
+/// the models would expand it
+///
+/// Only single streams are coded this way: no Huffman coding, transforms or
+/// blocks. Without a preset in the options, the first piece picks it - the
+/// same one `compress_with` picks if the piece holds the first `SAMPLE_SIZE`
+/// bytes, or all of a shorter input.
+pub struct StreamEncoder<W: Write> {
+    writer: ACWriter<W>,
+    len: u64,
+    memory: u64,
+    /// Known once the header is written, unless the options had one
+    preset: Option<Preset>,
+    models: TextModels,
+    header_written: bool,
+    /// Bytes of input coded so far
+    pos: u64,
+    /// `None` for stored streams
+    model: Option<Box<dyn Model>>,
+    ac: ArithmeticCoder<ACWriter<W>>,
+}
+
+impl<W: Write> StreamEncoder<W> {
+    /// An encoder for `len` bytes of input, fails with `InvalidInput` for
+    /// options that need the whole input up front
+    pub fn new(writer: W, len: u64, options: &Options) -> io::Result<Self> {
+        options.check()?;
+        if options.huffman
+            || options.dedup
+            || options.text.is_some()
+            || options.block_size.is_some()
+            || options.seekable
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Streams are coded without Huffman coding, transforms or blocks",
+            ));
+        }
+        Ok(Self {
+            writer: ACWriter::new(writer),
+            len,
+            memory: options.memory,
+            preset: options.preset,
+            models: options.models,
+            header_written: false,
+            pos: 0,
+            model: None,
+            ac: ArithmeticCoder::new_coder(),
+        })
+    }
+
+    /// The inner writer, it has all bytes completed so far
+    pub fn get_mut(&mut self) -> &mut W {
+        self.writer.get_mut()
+    }
+
+    /// Codes the end of the stream, fails with `InvalidInput` unless all of
+    /// the input was written
+    pub fn finish(mut self) -> io::Result<(W, Summary)> {
+        check_len(self.pos, self.len)?;
+        if !self.header_written {
+            self.write_header(&[])?;
+        }
+        let memory_usage = match &self.model {
+            Some(model) => {
+                self.ac.flush(&mut self.writer)?;
+                model.memory_usage()
+            }
+            None => {
+                self.writer.get_mut().flush()?;
+                Vec::new()
+            }
+        };
+        let preset = self.preset.expect("the header is written");
+        Ok((self.writer.into_inner(), Summary { preset, memory_usage }))
+    }
+
+    /// Writes the header and builds the models, `sample` picks the preset
+    /// unless the options had one
+    fn write_header(&mut self, sample: &[u8]) -> io::Result<()> {
+        let preset = self.preset.unwrap_or_else(|| Preset::detect(sample));
+        let mut header = Header::new(self.len, self.memory).preset(preset);
+        if preset == Preset::Text {
+            header = header.models(self.models);
+        }
+        header.write(self.writer.get_mut())?;
+        let config = ModelConfig::for_header(&header);
+        self.model = match preset {
+            Preset::Store => None,
+            Preset::Binary => Some(Box::new(config.init_binary_model())),
+            Preset::Text => Some(Box::new(config.init_model())),
+        };
+        self.preset = Some(preset);
+        self.header_written = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for StreamEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.len - self.pos {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Expected {} bytes of input, got more", self.len),
+            ));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.header_written {
+            self.write_header(buf)?;
+        }
+        match &mut self.model {
+            Some(model) => {
+                for &byte in buf {
+                    let bits = ByteBits.bits(byte);
+                    encode_bits(bits, model.as_mut(), &mut self.ac, &mut self.writer)?;
+                }
+            }
+            None => self.writer.get_mut().write_all(buf)?,
+        }
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.get_mut().flush()
+    }
+}
+
+/// Decompresses a stream handed over piece by piece, writing out as much as
+/// the pieces so far decode to
+///
+/// Only streams `StreamEncoder` could have written decode this way: no
+/// Huffman coding, transforms, blocks, dictionary or reference.
+pub struct StreamDecoder<W: Write> {
+    writer: W,
+    limits: Limits,
+    timer: Instant,
+    input: Pieces,
+    header: Option<Header>,
+    model: Option<Box<dyn Model>>,
+    /// Starts once the first 4 bytes of the coded stream are in
+    ac: Option<ArithmeticCoder<Pieces>>,
+    /// Bytes decoded so far
+    pos: u64,
+    // bits of the current byte behind a leading 1
+    partial: u32,
+}
+
+impl<W: Write> StreamDecoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_limits(writer, Limits::unlimited())
+    }
+
+    /// A decoder that fails as soon as the stream would exceed any of the
+    /// `limits`
+    pub fn with_limits(writer: W, limits: Limits) -> Self {
+        Self {
+            writer,
+            limits,
+            timer: Instant::now(),
+            input: Pieces::default(),
+            header: None,
+            model: None,
+            ac: None,
+            pos: 0,
+            partial: 1,
+        }
+    }
+
+    /// The inner writer, it has all bytes decoded so far
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Decodes the rest of the stream, fails with `UnexpectedEof` when it
+    /// ends before the length declared in its header
+    pub fn finish(mut self) -> io::Result<(W, Summary)> {
+        self.input.finished = true;
+        self.decode()?;
+        // with the input finished, `decode` fails without a header
+        let preset = self.header.as_ref().expect("decoded the header").preset;
+        self.writer.flush()?;
+        let memory_usage = self
+            .model
+            .as_ref()
+            .map_or_else(Vec::new, |model| model.memory_usage());
+        Ok((self.writer, Summary { preset, memory_usage }))
+    }
+
+    /// Decodes as much of the input as there is
+    fn decode(&mut self) -> io::Result<()> {
+        if self.header.is_none() && !self.read_header()? {
+            return Ok(());
+        }
+        let len = self.header.as_ref().map_or(0, |header| header.len);
+        if self.model.is_none() {
+            // stored, the input is the output
+            let stored = self.input.take(len - self.pos);
+            self.limits.check_time(self.timer)?;
+            self.writer.write_all(&stored)?;
+            self.pos += stored.len() as u64;
+        } else {
+            self.decode_bits(len)?;
+            self.input.consume();
+        }
+        if self.input.finished && self.pos < len {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Stream ended before the length declared in the header",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Parses the header once all of it is in, returns whether it was
+    fn read_header(&mut self) -> io::Result<bool> {
+        let mut unread = self.input.unread();
+        let header = match Header::read(&mut unread) {
+            Ok(header) => header,
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof && !self.input.finished => {
+                return Ok(false)
+            }
+            Err(err) => return Err(err),
+        };
+        let header_len = self.input.unread().len() - unread.len();
+        self.input.take(header_len as u64);
+
+        if header.huffman.is_some()
+            || header.dedup.is_some()
+            || header.text.is_some()
+            || header.block_size.is_some()
+            || header.dictionary.is_some()
+            || header.reference.is_some()
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Only single streams decode piece by piece, use decompress",
+            ));
+        }
+        let config = ModelConfig::for_header(&header);
+        if header.preset == Preset::Store {
+            self.limits.check_header(&header, 0, 0)?;
+        } else {
+            self.limits.check_header(&header, 8, config.memory())?;
+            self.model = Some(match header.preset {
+                Preset::Binary => Box::new(config.init_binary_model()),
+                _ => Box::new(config.init_model()),
+            });
+        }
+        self.header = Some(header);
+        Ok(true)
+    }
+
+    /// Decodes bits until the output is complete or the input runs out - a
+    /// bit the input doesn't cover yet is decoded again with more of it
+    fn decode_bits(&mut self, len: u64) -> io::Result<()> {
+        let model = self.model.as_mut().expect("coded streams have models");
+        if self.ac.is_none() && self.pos < len {
+            match ArithmeticCoder::new_decoder(&mut self.input) {
+                Ok(ac) => {
+                    self.input.commit();
+                    self.ac = Some(ac);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    self.input.rewind();
+                    return Ok(());
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        while self.pos < len {
+            if self.input.overrun > MAX_OVERRUN {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Stream ended before the length declared in the header",
+                ));
+            }
+            let ac = self.ac.as_mut().expect("started above");
+            let last = ac.clone();
+            let bit = match ac.decode(model.predict(), &mut self.input) {
+                Ok(bit) => bit,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    *ac = last;
+                    self.input.rewind();
+                    return Ok(());
+                }
+                Err(err) => return Err(err),
+            };
+            self.input.commit();
+            model.update(bit);
+            self.partial = (self.partial << 1) | u32::from(bit);
+            if self.partial >= 0x100 {
+                if self.pos.is_multiple_of(TIME_CHECK_INTERVAL) {
+                    self.limits.check_time(self.timer)?;
+                }
+                self.writer.write_all(&[u8!(self.partial & 0xff)])?;
+                self.partial = 1;
+                self.pos += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for StreamDecoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.input.bytes.extend_from_slice(buf);
+        self.decode()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// The input handed over so far - reading past it fails with `WouldBlock`
+/// until it's finished, then it pads with 0s like `ACReader`
+#[derive(Clone, Default)]
+struct Pieces {
+    bytes: Vec<u8>,
+    /// Bits read, the position `rewind` goes back to
+    committed: usize,
+    /// Bits read, including the ones of the bit being decoded
+    read: usize,
+    finished: bool,
+    /// Number of bytes padded with 0s
+    overrun: u64,
+}
+
+impl Pieces {
+    /// Bytes not read yet, at a byte boundary
+    fn unread(&self) -> &[u8] {
+        debug_assert!(self.committed.is_multiple_of(8));
+        &self.bytes[self.committed / 8..]
+    }
+
+    /// Reads up to `len` whole bytes
+    fn take(&mut self, len: u64) -> Vec<u8> {
+        let unread = self.unread();
+        let len = unread.len().min(usize::try_from(len).unwrap_or(usize::MAX));
+        let taken = unread[..len].to_vec();
+        self.committed += len * 8;
+        self.read = self.committed;
+        self.consume();
+        taken
+    }
+
+    fn commit(&mut self) {
+        self.committed = self.read;
+    }
+
+    fn rewind(&mut self) {
+        self.read = self.committed;
+    }
+
+    /// Drops the bytes read completely
+    fn consume(&mut self) {
+        let bytes = (self.committed / 8).min(self.bytes.len());
+        self.bytes.drain(..bytes);
+        self.committed -= bytes * 8;
+        self.read = self.committed;
+    }
+}
+
+impl ACRead for Pieces {
+    fn read_bit(&mut self) -> io::Result<u8> {
+        let bit = match self.bytes.get(self.read / 8) {
+            Some(byte) => (byte >> (7 - self.read % 8)) & 1,
+            None if self.finished => {
+                if self.read.is_multiple_of(8) {
+                    self.overrun += 1;
+                }
+                0
+            }
+            None => return Err(ErrorKind::WouldBlock.into()),
+        };
+        self.read += 1;
+        Ok(bit)
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        (0..u32::BITS).try_fold(0, |x, _| Ok((x << 1) | u32::from(self.read_bit()?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::{compress_with, decompress, SAMPLE_SIZE};
+    use crate::corpus::{generate, Kind, Rng};
+
+    /// Writes `input` in pieces of random sizes up to `max_piece`
+    fn write_pieces(writer: &mut impl Write, input: &[u8], max_piece: usize, rng: &mut Rng) {
+        let mut rest = input;
+        while !rest.is_empty() {
+            let len = (1 + rng.below(max_piece)).min(rest.len());
+            writer.write_all(&rest[..len]).unwrap();
+            rest = &rest[len..];
+        }
+    }
+
+    #[test]
+    fn pieces_round_trip() {
+        let mut input = generate(Kind::Markov, 6 << 10, 70);
+        input.extend(generate(Kind::Random, 2 << 10, 70));
+        let len = input.len() as u64;
+        let mut rng = Rng::new(70);
+        for options in [
+            Options::default(),
+            Options::default().preset(Preset::Text).memory(1 << 16),
+            Options::default().preset(Preset::Binary),
+            Options::default().preset(Preset::Store),
+        ] {
+            let mut plain = Vec::new();
+            compress_with(input.as_slice(), len, &mut plain, &options).unwrap();
+
+            // without a preset, the first piece must be the sample
+            // `compress_with` detects it on
+            let first = match options.preset {
+                Some(_) => 1000,
+                None => input.len().min(SAMPLE_SIZE),
+            };
+            let mut encoder = StreamEncoder::new(Vec::new(), len, &options).unwrap();
+            encoder.write_all(&input[..first]).unwrap();
+            write_pieces(&mut encoder, &input[first..], 500, &mut rng);
+            let (compressed, encoded) = encoder.finish().unwrap();
+            assert_eq!(compressed, plain, "{:?}", options);
+
+            for max_piece in [1, 7, 1000] {
+                let mut decoder = StreamDecoder::new(Vec::new());
+                write_pieces(&mut decoder, &compressed, max_piece, &mut rng);
+                let (decompressed, summary) = decoder.finish().unwrap();
+                assert_eq!(decompressed, input, "{:?}", options);
+                assert_eq!(summary.preset, encoded.preset);
+            }
+        }
+    }
+
+    #[test]
+    fn output_as_it_goes() {
+        let input = generate(Kind::Markov, 4 << 10, 71);
+        let mut encoder =
+            StreamEncoder::new(Vec::new(), input.len() as u64, &Options::default()).unwrap();
+        encoder.write_all(&input).unwrap();
+        let (compressed, _) = encoder.finish().unwrap();
+
+        let mut decoder = StreamDecoder::new(Vec::new());
+        decoder
+            .write_all(&compressed[..compressed.len() / 2])
+            .unwrap();
+        let decoded = decoder.get_mut().len();
+        assert!(decoded > 0 && decoded < input.len(), "{} bytes", decoded);
+        assert_eq!(decoder.get_mut()[..], input[..decoded]);
+        decoder
+            .write_all(&compressed[compressed.len() / 2..])
+            .unwrap();
+        assert_eq!(decoder.finish().unwrap().0, input);
+
+        // empty inputs still have a header
+        let (empty, _) = StreamEncoder::new(Vec::new(), 0, &Options::default())
+            .unwrap()
+            .finish()
+            .unwrap();
+        let mut decompressed = Vec::new();
+        decompress(empty.as_slice(), &mut decompressed).unwrap();
+        assert!(decompressed.is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_streams() {
+        let input = generate(Kind::Markov, 2 << 10, 72);
+        let len = input.len() as u64;
+        let options = Options::default();
+        let mut encoder = StreamEncoder::new(Vec::new(), len, &options).unwrap();
+        encoder.write_all(&input[1..]).unwrap();
+        let err = encoder.write_all(&input[..2]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = encoder.finish().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = StreamEncoder::new(Vec::new(), len, &options.clone().huffman(true))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let mut compressed = Vec::new();
+        compress_with(input.as_slice(), len, &mut compressed, &options).unwrap();
+        for truncated in [&compressed[..10], &compressed[..compressed.len() / 2]] {
+            let mut decoder = StreamDecoder::new(io::sink());
+            decoder.write_all(truncated).unwrap();
+            let err = decoder.finish().err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        }
+
+        // needs the whole input up front
+        let mut compressed = Vec::new();
+        compress_with(&input[..], len, &mut compressed, &options.blocks(1 << 10)).unwrap();
+        let err = StreamDecoder::new(io::sink())
+            .write_all(&compressed)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,183 @@
+//! Deterministic synthetic inputs for tests and for benchmarking on machines
+//! without the usual text corpora. The same `(kind, len, seed)` always yields
+//! the same bytes, on every platform.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Random,
+    Runs,
+    Markov,
+    Repeated,
+    Zeros,
+    SingleByte,
+    Empty,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 7] = [
+        Kind::Random,
+        Kind::Runs,
+        Kind::Markov,
+        Kind::Repeated,
+        Kind::Zeros,
+        Kind::SingleByte,
+        Kind::Empty,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Random => "random",
+            Kind::Runs => "runs",
+            Kind::Markov => "markov",
+            Kind::Repeated => "repeated",
+            Kind::Zeros => "zeros",
+            Kind::SingleByte => "single-byte",
+            Kind::Empty => "empty",
+        }
+    }
+}
+
+/// Generates `len` bytes of the given kind (`SingleByte` and `Empty` ignore `len`)
+pub fn generate(kind: Kind, len: usize, seed: u64) -> Vec<u8> {
+    let mut rng = Rng::new(seed);
+    match kind {
+        Kind::Random => (0..len).map(|_| rng.next_u8()).collect(),
+        Kind::Runs => runs(&mut rng, len),
+        Kind::Markov => markov(&mut rng, len),
+        Kind::Repeated => repeated(&mut rng, len),
+        Kind::Zeros => vec![0; len],
+        Kind::SingleByte => vec![rng.next_u8()],
+        Kind::Empty => Vec::new(),
+    }
+}
+
+/// One input of every kind, with `len` bytes where applicable
+pub fn corpus(len: usize, seed: u64) -> Vec<(Kind, Vec<u8>)> {
+    Kind::ALL
+        .iter()
+        .map(|&kind| (kind, generate(kind, len, seed)))
+        .collect()
+}
+
+// low entropy: long runs of a few symbols
+fn runs(rng: &mut Rng, len: usize) -> Vec<u8> {
+    let alphabet: Vec<u8> = (0..4).map(|_| rng.next_u8()).collect();
+    let mut buf = Vec::with_capacity(len);
+    while buf.len() < len {
+        let byte = alphabet[rng.below(alphabet.len())];
+        let run = 1 + rng.below(64);
+        buf.extend(std::iter::repeat_n(byte, run.min(len - buf.len())));
+    }
+    buf
+}
+
+// order-2 markov chain over a text-like alphabet, every context has a few
+// likely successors so the output looks like (very repetitive) words
+fn markov(rng: &mut Rng, len: usize) -> Vec<u8> {
+    const ALPHABET: &[u8] = b"etaoinshrdlcumwfgypbvkjxqz    ..,,\n";
+    const SUCCESSORS: usize = 4;
+    let n = ALPHABET.len();
+
+    let table: Vec<[u8; SUCCESSORS]> = (0..n * n)
+        .map(|_| [(); SUCCESSORS].map(|_| ALPHABET[rng.below(n)]))
+        .collect();
+    let index = |byte: u8| ALPHABET.iter().position(|&x| x == byte).unwrap();
+
+    let mut buf = Vec::with_capacity(len);
+    let (mut c1, mut c2) = (b' ', b' ');
+    for _ in 0..len {
+        let successors = &table[index(c1) * n + index(c2)];
+        // skewed towards the first successor
+        let pick = rng.below(SUCCESSORS * SUCCESSORS).isqrt();
+        let byte = successors[SUCCESSORS - 1 - pick];
+        buf.push(byte);
+        (c1, c2) = (c2, byte);
+    }
+    buf
+}
+
+// random blocks that repeat at random distances, possibly with a mutation
+fn repeated(rng: &mut Rng, len: usize) -> Vec<u8> {
+    let block_count = 8;
+    let blocks: Vec<Vec<u8>> = (0..block_count)
+        .map(|_| {
+            let block_len = 16 + rng.below(256);
+            (0..block_len).map(|_| rng.next_u8()).collect()
+        })
+        .collect();
+
+    let mut buf = Vec::with_capacity(len);
+    while buf.len() < len {
+        let block = &blocks[rng.below(block_count)];
+        let start = buf.len();
+        buf.extend_from_slice(&block[..block.len().min(len - start)]);
+        if rng.below(4) == 0 {
+            let pos = start + rng.below(buf.len() - start);
+            buf[pos] = rng.next_u8();
+        }
+    }
+    buf
+}
+
+/// SplitMix64, small and good enough for test data
+#[derive(Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        crate::helpers::hash_u64(self.state)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        self.next_u64().to_be_bytes()[0]
+    }
+
+    /// Uniform-ish integer in `0..n`
+    pub fn below(&mut self, n: usize) -> usize {
+        debug_assert!(n > 0);
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic() {
+        for kind in Kind::ALL {
+            assert_eq!(generate(kind, 1000, 42), generate(kind, 1000, 42));
+        }
+        assert_ne!(
+            generate(Kind::Random, 1000, 1),
+            generate(Kind::Random, 1000, 2)
+        );
+    }
+
+    #[test]
+    fn lengths() {
+        for (kind, buf) in corpus(1000, 7) {
+            let expected = match kind {
+                Kind::SingleByte => 1,
+                Kind::Empty => 0,
+                _ => 1000,
+            };
+            assert_eq!(buf.len(), expected, "{}", kind.name());
+        }
+    }
+
+    #[test]
+    fn markov_is_text() {
+        let buf = generate(Kind::Markov, 1000, 3);
+        assert!(buf
+            .iter()
+            .all(|b| b.is_ascii_lowercase() || b" .,\n".contains(b)));
+    }
+}
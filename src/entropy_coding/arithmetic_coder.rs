@@ -1,4 +1,6 @@
+use crate::state::{invalid_state, read_bytes, State};
 use crate::u32;
+use std::io::{Read, Write};
 use std::{io, marker::PhantomData};
 
 const PREC_SHIFT: u32 = u32::BITS - 1; // 31
@@ -105,6 +107,26 @@ impl<R: ACRead> ArithmeticCoder<R> {
     }
 }
 
+impl<T> State for ArithmeticCoder<T> {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        for x in [self.x1, self.x2, self.x] {
+            writer.write_all(&x.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        let x1 = u32::from_be_bytes(read_bytes(reader)?);
+        let x2 = u32::from_be_bytes(read_bytes(reader)?);
+        let x = u32::from_be_bytes(read_bytes(reader)?);
+        if x1 >= x2 {
+            return Err(invalid_state("ArithmeticCoder"));
+        }
+        (self.x1, self.x2, self.x) = (x1, x2, x);
+        Ok(())
+    }
+}
+
 #[inline(always)]
 fn lerp(x1: u32, x2: u32, prob: u16) -> u32 {
     // make prob 32-bit & always leave chance
@@ -160,4 +160,53 @@ fn half_bad_model() {
     assert_eq!(compressed.len(), 16 * block_size + block_size / 2 + 1);
 }
 
-// TODO: Fuzzing tests
+// More exhaustive checks live in the cargo-fuzz targets under fuzz/
+#[test]
+fn garbage_input() {
+    let mut rng = crate::corpus::Rng::new(0);
+    let probabilities = [0, 1, 1 << 15, u16::MAX - 1, u16::MAX];
+    for len in [0, 1, 3, 4, 5, 64, 1024] {
+        let input: Vec<u8> = (0..len).map(|_| rng.next_u8()).collect();
+        let mut reader = ACReader::new(input.as_slice());
+        let mut ac = ArithmeticCoder::new_decoder(&mut reader).unwrap();
+        for i in 0..len * 8 + 64 {
+            let bit = ac.decode(probabilities[i % 5], &mut reader).unwrap();
+            assert!(bit <= 1);
+        }
+    }
+}
+
+#[test]
+fn synthetic_corpus_round_trip() {
+    use crate::corpus::corpus;
+    use crate::models::{Model, Order0};
+
+    for (kind, input) in corpus(1 << 12, 0) {
+        let mut compressed = Vec::new();
+        let mut writer = ACWriter::new(&mut compressed);
+        let mut ac = ArithmeticCoder::new_coder();
+        let mut model = Order0::new();
+        for &byte in &input {
+            for bit in (0..8).rev().map(|i| (byte >> i) & 1) {
+                ac.encode(bit, model.predict(), &mut writer).unwrap();
+                model.update(bit);
+            }
+        }
+        ac.flush(&mut writer).unwrap();
+
+        let mut reader = ACReader::new(compressed.as_slice());
+        let mut ac = ArithmeticCoder::new_decoder(&mut reader).unwrap();
+        let mut model = Order0::new();
+        let mut decompressed = Vec::with_capacity(input.len());
+        for _ in 0..input.len() {
+            let mut byte = 0;
+            for _ in 0..8 {
+                let bit = ac.decode(model.predict(), &mut reader).unwrap();
+                model.update(bit);
+                byte = (byte << 1) | bit;
+            }
+            decompressed.push(byte);
+        }
+        assert_eq!(input, decompressed, "{}", kind.name());
+    }
+}
@@ -0,0 +1,214 @@
+use super::package_merge::{canonical, package_merge};
+use crate::u8;
+
+/// A canonical Huffman code over bytes, coded MSB first
+///
+/// Only the code lengths are needed to rebuild it, which is what goes in the
+/// header. Decoding walks the canonical code tree one bit at a time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanonicalCode {
+    code_lens: Vec<u8>,
+    codes: Vec<(u16, u8)>,
+    // symbols ordered by (code length, code) - the order codes are handed out
+    sorted: Vec<u8>,
+    // first code of each length and the index of its symbol in `sorted`
+    first_code: [u16; Self::MAX_LEN as usize + 1],
+    first_idx: [u16; Self::MAX_LEN as usize + 1],
+    len_counts: [u16; Self::MAX_LEN as usize + 1],
+}
+
+/// Position in the code tree: the bits of the current codeword seen so far
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TreePos {
+    code: u16,
+    len: u8,
+}
+
+impl TreePos {
+    /// Bits of the current codeword seen so far
+    pub fn depth(&self) -> u8 {
+        self.len
+    }
+}
+
+impl CanonicalCode {
+    pub const MAX_LEN: u8 = 15;
+
+    /// Builds a length-limited code for the byte `counts` (256 of them)
+    ///
+    /// A code needs at least 2 symbols to code anything, so with fewer the
+    /// smallest missing bytes are given the lowest possible count.
+    pub fn from_counts(counts: &[u32]) -> Self {
+        assert_eq!(counts.len(), 256, "Expected a byte histogram");
+        let mut counts = counts.to_vec();
+        let mut missing = 2_usize.saturating_sub(counts.iter().filter(|&&c| c != 0).count());
+        for count in counts.iter_mut().filter(|count| **count == 0) {
+            if missing == 0 {
+                break;
+            }
+            *count = 1;
+            missing -= 1;
+        }
+        let code_lens = package_merge(&counts, Self::MAX_LEN);
+        Self::from_code_lens(&code_lens).expect("package_merge builds complete codes")
+    }
+
+    /// Rebuilds the code from its lengths, `None` unless they describe a
+    /// complete prefix code over bytes of at most `MAX_LEN` bits
+    pub fn from_code_lens(code_lens: &[u8]) -> Option<Self> {
+        if code_lens.len() != 256 || code_lens.iter().any(|&len| len > Self::MAX_LEN) {
+            return None;
+        }
+        // Kraft sum in units of 2^-MAX_LEN, must be exactly 1
+        let kraft: u32 = code_lens
+            .iter()
+            .filter(|&&len| len != 0)
+            .map(|&len| 1 << (Self::MAX_LEN - len))
+            .sum();
+        if kraft != 1 << Self::MAX_LEN || code_lens.iter().filter(|&&len| len != 0).count() < 2 {
+            return None;
+        }
+
+        let codes = canonical(code_lens);
+        let mut sorted: Vec<u8> = (0..=255)
+            .filter(|&sym| code_lens[usize::from(sym)] != 0)
+            .collect();
+        sorted.sort_unstable_by_key(|&sym| {
+            let (code, len) = codes[usize::from(sym)];
+            (len, code)
+        });
+
+        let mut len_counts = [0; Self::MAX_LEN as usize + 1];
+        for &len in code_lens.iter().filter(|&&len| len != 0) {
+            len_counts[usize::from(len)] += 1;
+        }
+        let mut first_code = [0; Self::MAX_LEN as usize + 1];
+        let mut first_idx = [0; Self::MAX_LEN as usize + 1];
+        for len in 1..=usize::from(Self::MAX_LEN) {
+            first_code[len] = (first_code[len - 1] + len_counts[len - 1]) << 1;
+            first_idx[len] = first_idx[len - 1] + len_counts[len - 1];
+        }
+
+        Some(Self {
+            code_lens: code_lens.to_vec(),
+            codes,
+            sorted,
+            first_code,
+            first_idx,
+            len_counts,
+        })
+    }
+
+    pub fn code_lens(&self) -> &[u8] {
+        &self.code_lens
+    }
+
+    /// The codeword of `byte` and its length in bits
+    pub fn code(&self, byte: u8) -> (u16, u8) {
+        self.codes[usize::from(byte)]
+    }
+
+    pub fn max_len(&self) -> u8 {
+        self.code_lens.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Moves down the tree along `bit`, returns the byte once a leaf is reached
+    /// and starts over at the root
+    pub fn step(&self, pos: &mut TreePos, bit: u8) -> Option<u8> {
+        pos.code = (pos.code << 1) | u16::from(bit);
+        pos.len += 1;
+        let len = usize::from(pos.len);
+        // complete codes always end in a leaf by MAX_LEN bits
+        debug_assert!(pos.len <= Self::MAX_LEN);
+        let offset = pos.code.wrapping_sub(self.first_code[len]);
+        if offset >= self.len_counts[len] {
+            return None;
+        }
+        *pos = TreePos::default();
+        Some(self.sorted[usize::from(self.first_idx[len] + offset)])
+    }
+
+    /// Decodes a whole codeword with `next_bit`, for tests and tools
+    pub fn decode(&self, mut next_bit: impl FnMut() -> u8) -> u8 {
+        let mut pos = TreePos::default();
+        loop {
+            if let Some(byte) = self.step(&mut pos, next_bit()) {
+                return byte;
+            }
+        }
+    }
+
+    /// The bits of `byte`'s codeword, MSB first
+    pub fn bits(&self, byte: u8) -> impl Iterator<Item = u8> {
+        let (code, len) = self.code(byte);
+        (0..len).rev().map(move |i| u8!((code >> i) & 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{corpus::corpus, helpers::histogram};
+
+    #[test]
+    fn round_trip() {
+        for (kind, input) in corpus(1 << 12, 0) {
+            let code = CanonicalCode::from_counts(&histogram(&input));
+            let bits: Vec<u8> = input.iter().flat_map(|&byte| code.bits(byte)).collect();
+            let mut bits = bits.into_iter();
+            for &byte in &input {
+                assert_eq!(
+                    code.decode(|| bits.next().unwrap()),
+                    byte,
+                    "{}",
+                    kind.name()
+                );
+            }
+            assert!(bits.next().is_none());
+            assert_eq!(CanonicalCode::from_code_lens(code.code_lens()), Some(code));
+        }
+    }
+
+    #[test]
+    fn every_byte() {
+        let code = CanonicalCode::from_counts(&[1; 256]);
+        assert_eq!(code.max_len(), 8);
+        for byte in 0..=255 {
+            let mut bits = code.bits(byte);
+            assert_eq!(code.decode(|| bits.next().unwrap()), byte);
+        }
+    }
+
+    #[test]
+    fn too_few_symbols() {
+        let mut counts = [0; 256];
+        let code = CanonicalCode::from_counts(&counts);
+        assert_eq!(code.code_lens().iter().filter(|&&len| len != 0).count(), 2);
+        counts[b'a' as usize] = 100;
+        let code = CanonicalCode::from_counts(&counts);
+        assert_eq!(code.code(b'a').1, 1);
+    }
+
+    #[test]
+    fn rejects_bad_lengths() {
+        let mut code_lens = [0; 256];
+        assert_eq!(CanonicalCode::from_code_lens(&code_lens), None);
+        // a single symbol codes nothing
+        code_lens[0] = 1;
+        assert_eq!(CanonicalCode::from_code_lens(&code_lens), None);
+        code_lens[1] = 1;
+        assert!(CanonicalCode::from_code_lens(&code_lens).is_some());
+        // incomplete
+        code_lens[1] = 2;
+        assert_eq!(CanonicalCode::from_code_lens(&code_lens), None);
+        // oversubscribed
+        code_lens[1] = 1;
+        code_lens[2] = 1;
+        assert_eq!(CanonicalCode::from_code_lens(&code_lens), None);
+        // too long
+        let mut code_lens = [8; 256];
+        code_lens[0] = 16;
+        assert_eq!(CanonicalCode::from_code_lens(&code_lens), None);
+        assert_eq!(CanonicalCode::from_code_lens(&[1, 1]), None);
+    }
+}
@@ -2,17 +2,24 @@ use core::slice::from_mut as into_slice;
 use std::io::{self, ErrorKind, Read, Write};
 
 use super::arithmetic_coder::{ACRead, ACWrite};
+use crate::state::{invalid_state, read_bytes, State};
 
 /// Arithmetic coder read io for `io::Read` types
 pub struct ACReader<R> {
     inner: R,
     buf: u8,
     mask: u8,
+    overrun: u64,
 }
 
 impl<R: Read> ACReader<R> {
     pub fn new(inner: R) -> Self {
-        Self { inner, buf: 0, mask: 0 }
+        Self { inner, buf: 0, mask: 0, overrun: 0 }
+    }
+
+    /// Number of bytes padded with 0s after EOF
+    pub fn overrun(&self) -> u64 {
+        self.overrun
     }
 
     fn read_byte(&mut self) -> io::Result<u8> {
@@ -21,7 +28,10 @@ impl<R: Read> ACReader<R> {
         let result = self.inner.read_exact(into_slice(&mut byte));
 
         match result {
-            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(0),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                self.overrun += 1;
+                Ok(0)
+            }
             _ => result.map(|_| byte),
         }
     }
@@ -60,6 +70,16 @@ impl<W: Write> ACWriter<W> {
     pub fn new(inner: W) -> Self {
         Self { inner, buf: 0, idx: 0, rev_bits: 0 }
     }
+
+    /// The inner writer, call after flushing the coder
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// The inner writer, it has all bytes completed so far
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
 }
 
 impl<W: Write> ACWrite for ACWriter<W> {
@@ -100,7 +120,26 @@ impl<W: Write> ACWrite for ACWriter<W> {
     }
 }
 
+// the inner writer is the caller's, only the bits not written to it yet
+impl<W: Write> State for ACWriter<W> {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&[self.buf, self.idx])?;
+        writer.write_all(&self.rev_bits.to_be_bytes())
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        let [buf, idx] = read_bytes(reader)?;
+        if idx >= 8 {
+            return Err(invalid_state("ACWriter"));
+        }
+        (self.buf, self.idx) = (buf, idx);
+        self.rev_bits = u64::from_be_bytes(read_bytes(reader)?);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)] // groups mark bits written per step
 mod tests {
     use super::{ACRead, ACReader, ACWrite, ACWriter};
 
@@ -120,6 +159,7 @@ mod tests {
         truth.for_each(|&bit| assert_eq!(reader.read_bit().unwrap(), bit));
         // read past EOF
         (0..16).for_each(|_| assert_eq!(reader.read_bit().unwrap(), 0));
+        assert_eq!(reader.overrun(), 2);
     }
 
     #[test]
@@ -136,8 +176,10 @@ mod tests {
         let data = b"\xde\xad";
         let mut reader = ACReader::new(data.as_ref());
         assert_eq!(reader.read_u32().unwrap(), 0xdead0000);
+        assert_eq!(reader.overrun(), 2);
         // read past EOF
         (0..16).for_each(|_| assert_eq!(reader.read_bit().unwrap(), 0));
+        assert_eq!(reader.overrun(), 4);
     }
 
     #[test]
@@ -1,4 +1,5 @@
 pub mod arithmetic_coder;
+pub mod huffman;
 pub mod io;
 pub mod package_merge;
 
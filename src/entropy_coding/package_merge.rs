@@ -6,10 +6,10 @@ pub fn package_merge(counts: &[u32], max_len: u8) -> Vec<u8> {
         .filter(|&(_, count)| count != 0)
         .collect();
     // sort symbols by counts
-    symbol2count.sort_unstable_by(|(_, a), (_, b)| a.cmp(b));
+    symbol2count.sort_unstable_by_key(|&(_, count)| count);
     let sorted_counts: Vec<_> = symbol2count.iter().map(|&x| x.1).collect();
 
-    assert!(sorted_counts.len() != 0, "No symbols provided");
+    assert!(!sorted_counts.is_empty(), "No symbols provided");
     assert!(max_len <= 32, "Max length is too big"); // can be 64 for quad words
     assert!(
         sorted_counts.len() <= 1 << max_len,
@@ -33,12 +33,13 @@ pub fn package_merge(counts: &[u32], max_len: u8) -> Vec<u8> {
 // https://github.com/sellibitze/packagemerge-rs/blob/27adc64e3a8b51b86ea91449c6a4c1971af7c682/src/lib.rs
 fn package_merge_sorted(a: &[u32], max_len: u8) -> Vec<u8> {
     let mut package_depths: Vec<u32> = vec![0; a.len() * 2 - 1];
-    let mut prev: Vec<u32> = a.iter().copied().collect();
+    // package weights can exceed u32 when merging large counts
+    let mut prev: Vec<u64> = a.iter().map(|&x| u64::from(x)).collect();
     let mut curr = Vec::with_capacity(a.len() * 2 - 1);
 
     for depth in 1..max_len {
         let mask = 1 << depth; // records at which depth it was packaged
-        let mut seq = a.iter().peekable(); // always merge with the initial counts
+        let mut seq = a.iter().map(|&x| u64::from(x)).peekable(); // always merge with the initial counts
         let mut packages = prev.chunks_exact(2).map(|x| x[0] + x[1]).peekable();
         curr.clear(); //
 
@@ -48,13 +49,13 @@ fn package_merge_sorted(a: &[u32], max_len: u8) -> Vec<u8> {
                 (None, None) => break,
                 (None, _) => false,
                 (_, None) => true,
-                (Some(a), Some(&b)) => a <= b,
+                (Some(a), Some(b)) => a <= b,
             };
             let next_item = if is_package {
                 package_depths[curr.len()] |= mask;
                 packages.next().unwrap()
             } else {
-                seq.next().copied().unwrap()
+                seq.next().unwrap()
             };
             curr.push(next_item);
         }
@@ -84,12 +85,16 @@ fn package_merge_sorted(a: &[u32], max_len: u8) -> Vec<u8> {
 
 // TODO: write tests
 pub fn canonical(code_lens: &[u8]) -> Vec<(u16, u8)> {
+    assert!(
+        code_lens.iter().all(|&len| len <= 16),
+        "Code length is too big for canonical codes"
+    );
     let mut symbol2code_lens: Vec<_> = code_lens
         .iter()
         .enumerate()
         .filter(|(_, &x)| x != 0)
         .collect();
-    symbol2code_lens.sort_unstable_by(|(_, a), (_, b)| a.cmp(b));
+    symbol2code_lens.sort_unstable_by_key(|&(_, &code_len)| code_len);
 
     let max_len = code_lens
         .iter()
@@ -205,6 +210,20 @@ mod tests {
         package_merge(&[1, 1, 2, 4, 8, 16, 32], 2);
     }
 
+    #[test]
+    fn huge_counts() {
+        let counts = [u32::MAX; 256];
+        assert_eq!(package_merge(&counts, 8), [8; 256]);
+        let counts = [u32::MAX, u32::MAX - 1, 1, 1];
+        assert_eq!(package_merge(&counts, 8), [1, 2, 3, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Code length is too big for canonical codes")]
+    fn canonical_too_long() {
+        canonical(&[1, 17]);
+    }
+
     #[test]
     fn check_canonical_sorted() {
         let code_lens = [2, 2, 2, 3, 3];
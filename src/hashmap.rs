@@ -1,28 +1,28 @@
+use crate::helpers::table_bits;
+
 pub struct HashMap {
     arr: Vec<Cell>,
     log_cell_count: u32,
 }
 
 impl HashMap {
+    /// Allocates the largest power of two number of cells that fits in `size` bytes
     pub fn new(size: usize) -> Self {
-        let cell_size = std::mem::size_of::<Cell>();
-        let log_cell_count = ((size as f64).log2() - (cell_size as f64).log2()) as u32;
+        let log_cell_count = u32::from(table_bits(size, std::mem::size_of::<Cell>()));
         let cell_count = 1 << log_cell_count;
-        println!(
-            "Allocating a hashmap of size {} B ({} MB). Cell count is {} (1 << {})",
-            size,
-            size >> 20,
-            cell_count,
-            log_cell_count
-        );
         Self {
             arr: vec![Cell::empty(); cell_count],
             log_cell_count,
         }
     }
 
+    /// Bytes allocated for the cells
+    pub fn memory(&self) -> usize {
+        std::mem::size_of_val(self.arr.as_slice())
+    }
+
     // Uses high bits of hash first
-    pub fn get_slot(&mut self, hash: u64) -> Slot {
+    pub fn get_slot(&mut self, hash: u64) -> Slot<'_> {
         let index = hash >> (u64::BITS - self.log_cell_count);
         self.arr[index as usize].get_slot(hash)
     }
@@ -73,6 +73,12 @@ impl ACStats {
     }
 }
 
+impl Default for ACStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl entropy_coding::arithmetic_coder::ACWrite for ACStats {
     fn inc_parity(&mut self) {
         self.rev_bits += 1;
@@ -88,3 +94,28 @@ impl entropy_coding::arithmetic_coder::ACWrite for ACStats {
         Ok(())
     }
 }
+
+/// Log2 of the largest power of two table of `entry_size` entries that fits
+/// in `memory` bytes (at least 1 entry)
+pub fn table_bits(memory: usize, entry_size: usize) -> u8 {
+    let entries = (memory / entry_size).max(1);
+    u8::try_from(entries.ilog2()).unwrap()
+}
+
+/// Spreads the bits of `x` over the whole word (the SplitMix64 finalizer), so
+/// the high bits can index a table and the low bits check it
+pub fn hash_u64(x: u64) -> u64 {
+    let mut z = x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Hashes `bytes` 8 at a time with `hash_u64`, starting from `seed`
+pub fn hash_bytes(seed: u64, bytes: &[u8]) -> u64 {
+    bytes.chunks(8).fold(seed, |hash, chunk| {
+        let mut word = [0; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        hash_u64(hash ^ u64::from_le_bytes(word))
+    })
+}
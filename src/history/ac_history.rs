@@ -1,28 +1,44 @@
-use super::History;
+use super::{BitBuffer, History};
+use crate::state::{read_bytes, State};
 use crate::u8;
 use crate::{
     entropy_coding::arithmetic_coder::{ACWrite, ArithmeticCoder},
     models::{ACHashModel, Model},
 };
+use std::io::{self, Read, Write};
 use std::marker::PhantomData;
 
-pub struct ACHistory<M: ACHashModel> {
+/// Bits a hash codes at most, however long the buffer: on predictable history
+/// `max_bits` may never fill, and every hash would code all of it
+pub const MAX_LOOKBACK: usize = 512;
+
+/// Hashes the history by coding it from the newest bit back, until `max_bits`
+/// bits come out, the history buffer runs out or `MAX_LOOKBACK` bits are coded
+pub struct ACHistory<M: ACHashModel, B: BitBuffer = u64> {
     pos: u64,
-    bits: u64, // TODO: u128?
+    bits: B,
     max_bits: u8,
     model: M,
 }
 
 impl<M: ACHashModel> ACHistory<M> {
     pub fn new(max_bits: u8, model: M) -> Self {
-        Self { pos: 0, bits: 0, max_bits, model }
+        Self::with_buffer(max_bits, model, 0)
+    }
+}
+
+impl<M: ACHashModel, B: BitBuffer> ACHistory<M, B> {
+    /// Looks back as far as `bits` holds, e.g. `u128` or a `ByteRing`
+    pub fn with_buffer(max_bits: u8, model: M, bits: B) -> Self {
+        Self { pos: 0, bits, max_bits, model }
     }
 }
 
-impl<M: ACHashModel> History for ACHistory<M> {
+impl<M: ACHashModel, B: BitBuffer> History for ACHistory<M, B> {
     fn update(&mut self, bit: u8) {
-        self.bits = (self.bits << 1) | u64::from(bit);
+        self.bits.push(bit);
         self.pos += 1;
+        self.model.update(bit);
     }
 
     fn hash(&mut self) -> u32 {
@@ -34,15 +50,39 @@ impl<M: ACHashModel> History for ACHistory<M> {
             max_bits: self.max_bits,
         };
         self.model.align(u8!(self.pos & 7));
-        for i in 0..64 {
-            let bit = u8!((self.bits >> i) & 1);
+        // coding stops as soon as the writer is full, so long buffers cost at
+        // most `MAX_LOOKBACK` steps per hash
+        for i in 0..self.bits.capacity().min(MAX_LOOKBACK) {
+            let bit = self.bits.bit(i);
             let res = ac.encode(bit, self.model.predict(), &mut writer);
             if res.is_err() {
                 break;
             }
+            self.model.step(bit);
         }
 
-        writer.state >> (32 - writer.idx)
+        writer
+            .state
+            .checked_shr(32 - u32::from(writer.idx))
+            .unwrap_or(0)
+    }
+
+    fn memory(&self) -> usize {
+        self.model.memory()
+    }
+}
+
+impl<M: ACHashModel + State, B: BitBuffer + State> State for ACHistory<M, B> {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.pos.to_be_bytes())?;
+        self.bits.write_state(writer)?;
+        self.model.write_state(writer)
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.pos = u64::from_be_bytes(read_bytes(reader)?);
+        self.bits.read_state(reader)?;
+        self.model.read_state(reader)
     }
 }
 
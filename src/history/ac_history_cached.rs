@@ -1,77 +1,199 @@
-use super::History;
-use crate::u8;
+use super::{BitBuffer, History, MAX_LOOKBACK};
 use crate::{
     entropy_coding::arithmetic_coder::{ACWrite, ArithmeticCoder},
+    helpers::{hash_u64, table_bits},
     models::{ACHashModel, Model},
 };
-use std::collections::HashMap;
+use crate::{u8, usize};
 
-pub struct ACHistoryCached<M: ACHashModel> {
+/// `ACHistory` that caches coder states of the newest history bits
+///
+/// The states of the newest `cache_size` and `cache_size / 2` bits are kept
+/// in a direct-mapped table of fixed size, colliding keys evict each other.
+/// States are keyed by the model's epoch too, so once an adaptive model
+/// refreshes its probabilities the states coded with the old ones miss.
+pub struct ACHistoryCached<M: ACHashModel, B: BitBuffer = u64> {
     pos: u64,
-    bits: u64, // TODO: u128?
+    bits: B,
     max_bits: u8,
     model: M,
     cache_size: u8,
-    cache: HashMap<(u64, u8, u8), (EntropyWriter, ArithmeticCoder<EntropyWriter>)>,
+    cache: Vec<Option<CacheEntry>>,
+    cache_bits: u8,
+    stats: CacheStats,
 }
 
+// newest bits, alignment, cache level, model epoch
+type CacheKey = (u64, u8, u8, u64);
+
+#[derive(Clone)]
+struct CacheEntry {
+    key: CacheKey,
+    writer: EntropyWriter,
+    ac: ArithmeticCoder<EntropyWriter>,
+}
+
+/// Cache lookups of `ACHistoryCached` since it was created
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Inserts that replaced a different key
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+}
+
+/// Cache capacity `ACHistoryCached::new` starts with
+pub const DEFAULT_CACHE_BYTES: usize = 1 << 20;
+
 impl<M: ACHashModel> ACHistoryCached<M> {
     pub fn new(max_bits: u8, model: M, cache_size: u8) -> Self {
+        Self::with_buffer(max_bits, model, cache_size, 0)
+    }
+}
+
+impl<M: ACHashModel, B: BitBuffer> ACHistoryCached<M, B> {
+    /// Looks back as far as `bits` holds, e.g. `u128` or a `ByteRing`
+    pub fn with_buffer(max_bits: u8, model: M, cache_size: u8, bits: B) -> Self {
+        assert!(cache_size < 64, "Cache keys hold less than 64 bits");
         Self {
             pos: 0,
-            bits: 0,
+            bits,
             max_bits,
             model,
             cache_size,
-            cache: HashMap::new(),
+            cache: Vec::new(),
+            cache_bits: 0,
+            stats: CacheStats::default(),
         }
+        .cache_bytes(DEFAULT_CACHE_BYTES)
+    }
+
+    /// Sizes the cache to the largest power of two number of entries that fits
+    /// in `bytes` (at least 1), dropping everything cached so far
+    pub fn cache_bytes(mut self, bytes: usize) -> Self {
+        self.cache_bits = table_bits(bytes, std::mem::size_of::<Option<CacheEntry>>());
+        self.cache = vec![None; 1 << self.cache_bits];
+        self
+    }
+
+    /// Number of newest history bits the (first level) cache keys on
+    pub fn cache_size(&self) -> u8 {
+        self.cache_size
+    }
+
+    /// Bytes allocated for the cache
+    pub fn cache_memory(&self) -> usize {
+        std::mem::size_of_val(self.cache.as_slice())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    fn slot(&self, key: CacheKey) -> usize {
+        let (bits, alignment, level, epoch) = key;
+        let hash = hash_u64(
+            bits ^ (u64::from(alignment) << 56) ^ (u64::from(level) << 60) ^ hash_u64(epoch),
+        );
+        usize!(hash
+            .checked_shr(u64::BITS - u32::from(self.cache_bits))
+            .unwrap_or(0))
+    }
+
+    fn get(&self, key: CacheKey) -> Option<&CacheEntry> {
+        self.cache[self.slot(key)]
+            .as_ref()
+            .filter(|entry| entry.key == key)
+    }
+
+    fn insert(
+        &mut self,
+        key: CacheKey,
+        writer: &EntropyWriter,
+        ac: &ArithmeticCoder<EntropyWriter>,
+    ) {
+        let slot = self.slot(key);
+        if self.cache[slot]
+            .as_ref()
+            .is_some_and(|entry| entry.key != key)
+        {
+            self.stats.evictions += 1;
+        }
+        self.cache[slot] = Some(CacheEntry { key, writer: writer.clone(), ac: ac.clone() });
     }
 }
 
-impl<M: ACHashModel> History for ACHistoryCached<M> {
+impl<M: ACHashModel, B: BitBuffer> History for ACHistoryCached<M, B> {
     fn update(&mut self, bit: u8) {
-        self.bits = (self.bits << 1) | u64::from(bit);
+        self.bits.push(bit);
         self.pos += 1;
+        self.model.update(bit);
     }
 
     fn hash(&mut self) -> u32 {
         let alignment = u8!(self.pos & 7);
+        let epoch = self.model.epoch();
 
         let (c1, c2) = (self.cache_size, self.cache_size / 2);
         let (m1, m2) = ((1 << c1) - 1, (1 << c2) - 1);
         let (k1, k2) = (
-            (self.bits & m1, alignment, 0),
-            (self.bits & m2, alignment, 1),
+            (self.bits.newest() & m1, alignment, 0, epoch),
+            (self.bits.newest() & m2, alignment, 1, epoch),
         );
-        let (start, mut writer, mut ac) = match self.cache.get(&k1) {
-            Some((writer, ac)) => (c1, writer.clone(), ac.clone()),
-            None => match self.cache.get(&k2) {
-                Some((writer, ac)) => (c2, writer.clone(), ac.clone()),
-                None => (
-                    0,
-                    EntropyWriter::new(self.max_bits),
-                    ArithmeticCoder::new_coder(),
-                ),
-            },
+        let cached = match self.get(k1) {
+            Some(entry) => Some((c1, entry)),
+            None => self.get(k2).map(|entry| (c2, entry)),
         };
+        let (start, mut writer, mut ac) = match cached {
+            Some((start, entry)) => (start, entry.writer.clone(), entry.ac.clone()),
+            None => (
+                0,
+                EntropyWriter::new(self.max_bits),
+                ArithmeticCoder::new_coder(),
+            ),
+        };
+        match start {
+            0 => self.stats.misses += 1,
+            _ => self.stats.hits += 1,
+        }
 
-        let alignment = ((alignment + 32) - start) & 7;
         self.model.align(alignment);
-        for i in start..64 {
-            let bit = u8!((self.bits >> i) & 1);
+        for i in 0..usize::from(start) {
+            self.model.step(self.bits.bit(i));
+        }
+        for i in usize::from(start)..self.bits.capacity().min(MAX_LOOKBACK) {
+            let bit = self.bits.bit(i);
             let res = ac.encode(bit, self.model.predict(), &mut writer);
             if res.is_err() {
                 break;
             }
-            if i == c2 - 1 {
-                self.cache.insert(k2, (writer.clone(), ac.clone()));
+            self.model.step(bit);
+            if i + 1 == usize::from(c2) {
+                self.insert(k2, &writer, &ac);
             }
-            if i == c1 - 1 {
-                self.cache.insert(k1, (writer.clone(), ac.clone()));
+            if i + 1 == usize::from(c1) {
+                self.insert(k1, &writer, &ac);
             }
         }
 
-        writer.state >> (32 - writer.idx)
+        writer
+            .state
+            .checked_shr(32 - u32::from(writer.idx))
+            .unwrap_or(0)
+    }
+
+    fn memory(&self) -> usize {
+        self.model.memory() + self.cache.len() * std::mem::size_of::<Option<CacheEntry>>()
     }
 }
 
@@ -122,3 +244,147 @@ impl ACWrite for EntropyWriter {
         unimplemented!("Entropy writer doesn't implement flushing")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        corpus::Rng,
+        history::{ACHistory, ByteRing},
+        models::ac_hash::{AdaptiveOrder0, AdaptiveOrder1, StationaryModel},
+    };
+    use std::{cell::Cell, rc::Rc};
+
+    fn check_cache_matches(model: impl ACHashModel + Clone, bits: usize) {
+        for cache_size in [0, 4, 8, 12, 16, 20, 24] {
+            for max_bits in [8, 13, 21] {
+                let mut cached = ACHistoryCached::new(max_bits, model.clone(), cache_size);
+                let mut uncached = ACHistory::new(max_bits, model.clone());
+
+                // biased bits, so contexts repeat and the cache gets hits
+                let mut rng = Rng::new(u64::from(cache_size) << 8 | u64::from(max_bits));
+                for i in 0..bits {
+                    let bit = u8::from(rng.below(8) == 0);
+                    cached.update(bit);
+                    uncached.update(bit);
+                    assert_eq!(
+                        cached.hash(),
+                        uncached.hash(),
+                        "bit {} [cache: {}, max_bits: {}]",
+                        i,
+                        cache_size,
+                        max_bits
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cache_matches_uncached() {
+        check_cache_matches(StationaryModel::for_enwik7(), 1 << 13);
+        // past the refreshes at 2^8 and 2^12 bytes
+        check_cache_matches(AdaptiveOrder0::new(), (1 << 15) + 64);
+        check_cache_matches(AdaptiveOrder1::new(), (1 << 15) + 64);
+    }
+
+    #[test]
+    fn bounded_cache() {
+        let model = StationaryModel::for_enwik7();
+        for bytes in [0, 1 << 10, 1 << 16] {
+            let mut cached = ACHistoryCached::new(21, model.clone(), 16).cache_bytes(bytes);
+            let mut uncached = ACHistory::new(21, model.clone());
+            assert!(cached.cache_memory() <= bytes.max(std::mem::size_of::<Option<CacheEntry>>()));
+
+            let mut rng = Rng::new(bytes as u64);
+            for _ in 0..(1 << 14) {
+                let bit = u8::from(rng.below(4) == 0);
+                cached.update(bit);
+                uncached.update(bit);
+                assert_eq!(cached.hash(), uncached.hash());
+            }
+            let stats = cached.stats();
+            assert_eq!(stats.hits + stats.misses, 1 << 14);
+            assert!(stats.evictions > 0, "{:?}", stats);
+        }
+    }
+
+    #[test]
+    fn long_window() {
+        // zeros are (nearly) free, only the byte 20 bytes back shows in the hash
+        let model = StationaryModel::from_table([1; 8]);
+        let hash = |history: &mut dyn History, first: u8| {
+            for byte in std::iter::once(first).chain([0; 20]) {
+                for bit in (0..8).rev().map(|i| (byte >> i) & 1) {
+                    history.update(bit);
+                }
+            }
+            history.hash()
+        };
+        let short = |first| hash(&mut ACHistory::new(16, model.clone()), first);
+        assert_eq!(short(b'a'), short(b'b'));
+        let long = |first| hash(&mut ACHistory::with_buffer(16, model.clone(), 0u128), first);
+        assert_eq!(long(b'a'), long(b'b'));
+        let ring = |first| {
+            let bits = ByteRing::new(32);
+            hash(&mut ACHistory::with_buffer(16, model.clone(), bits), first)
+        };
+        assert_ne!(ring(b'a'), ring(b'b'));
+        let cached = |first| {
+            let bits = ByteRing::new(32);
+            hash(
+                &mut ACHistoryCached::with_buffer(16, model.clone(), 12, bits),
+                first,
+            )
+        };
+        assert_eq!(cached(b'a'), ring(b'a'));
+        assert_ne!(cached(b'a'), cached(b'b'));
+    }
+
+    /// Counts the steps passes take, predicts (nearly) certain 0s
+    #[derive(Clone, Default)]
+    struct CountingModel {
+        steps: Rc<Cell<usize>>,
+    }
+
+    impl ACHashModel for CountingModel {
+        fn predict(&mut self) -> u16 {
+            1
+        }
+
+        fn align(&mut self, _alignment: u8) {}
+
+        fn step(&mut self, _bit: u8) {
+            self.steps.set(self.steps.get() + 1);
+        }
+    }
+
+    #[test]
+    fn bounded_lookback() {
+        // 0s never fill `max_bits`, so only the lookback stops a pass
+        let steps_per_hash = |history: &mut dyn History, steps: &Cell<usize>| {
+            for _ in 0..1 << 12 {
+                history.update(0);
+            }
+            steps.set(0);
+            for _ in 0..64 {
+                history.update(0);
+                history.hash();
+            }
+            steps.get() / 64
+        };
+        for len in [1 << 6, 1 << 10, 1 << 16] {
+            let model = CountingModel::default();
+            let steps = model.steps.clone();
+            let mut history = ACHistory::with_buffer(16, model, ByteRing::new(len));
+            let uncached = steps_per_hash(&mut history, &steps);
+            assert_eq!(uncached, (len * 8).min(MAX_LOOKBACK), "{} bytes", len);
+
+            let model = CountingModel::default();
+            let steps = model.steps.clone();
+            let mut history = ACHistoryCached::with_buffer(16, model, 12, ByteRing::new(len));
+            let cached = steps_per_hash(&mut history, &steps);
+            assert_eq!(cached, uncached, "{} bytes", len);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use super::History;
+use crate::{helpers::hash_u64, u32};
+use std::collections::VecDeque;
+
+/// `low`'s hash in the low `low_bits` bits with `high`'s hash above it
+///
+/// Models mask contexts from the low bits, so `low` should be the history
+/// that matters most, e.g. a raw window of the last bits under an
+/// entropy-hashed longer window.
+pub struct Concat<A: History, B: History> {
+    low: A,
+    high: B,
+    low_bits: u8,
+}
+
+impl<A: History, B: History> Concat<A, B> {
+    pub fn new(low: A, low_bits: u8, high: B) -> Self {
+        assert!(low_bits < 32, "Concat needs some bits for the high history");
+        Self { low, high, low_bits }
+    }
+}
+
+impl<A: History, B: History> History for Concat<A, B> {
+    fn update(&mut self, bit: u8) {
+        self.low.update(bit);
+        self.high.update(bit);
+    }
+
+    fn hash(&mut self) -> u32 {
+        let mask = (1 << self.low_bits) - 1;
+        (self.high.hash() << self.low_bits) | (self.low.hash() & mask)
+    }
+}
+
+/// The xor of two hashes, cheap but symmetric and prone to cancelling out
+pub struct Xor<A: History, B: History> {
+    a: A,
+    b: B,
+}
+
+impl<A: History, B: History> Xor<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self { a, b }
+    }
+}
+
+impl<A: History, B: History> History for Xor<A, B> {
+    fn update(&mut self, bit: u8) {
+        self.a.update(bit);
+        self.b.update(bit);
+    }
+
+    fn hash(&mut self) -> u32 {
+        self.a.hash() ^ self.b.hash()
+    }
+}
+
+/// Hashes both hashes together, every bit of the result depends on both
+pub struct Mix<A: History, B: History> {
+    a: A,
+    b: B,
+}
+
+impl<A: History, B: History> Mix<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self { a, b }
+    }
+}
+
+impl<A: History, B: History> History for Mix<A, B> {
+    fn update(&mut self, bit: u8) {
+        self.a.update(bit);
+        self.b.update(bit);
+    }
+
+    fn hash(&mut self) -> u32 {
+        let hash = hash_u64((u64::from(self.a.hash()) << 32) | u64::from(self.b.hash()));
+        u32!(hash >> 32)
+    }
+}
+
+/// Only passes on the bits at the positions (MSB first) set in `mask`
+///
+/// E.g. `0b1101_1111` drops the ASCII case bit, `0b1111_0000` keeps high nibbles.
+pub struct Sparse<H: History> {
+    inner: H,
+    mask: u8,
+    pos: u8,
+}
+
+impl<H: History> Sparse<H> {
+    pub fn new(inner: H, mask: u8) -> Self {
+        Self { inner, mask, pos: 0 }
+    }
+}
+
+impl<H: History> History for Sparse<H> {
+    fn update(&mut self, bit: u8) {
+        if self.mask & (0x80 >> self.pos) != 0 {
+            self.inner.update(bit);
+        }
+        self.pos = (self.pos + 1) & 7;
+    }
+
+    fn hash(&mut self) -> u32 {
+        self.inner.hash()
+    }
+}
+
+/// Passes on the stream `skip` bytes late, so the newest bytes don't show
+pub struct Skip<H: History> {
+    inner: H,
+    delayed: VecDeque<u8>,
+    skip_bits: usize,
+}
+
+impl<H: History> Skip<H> {
+    pub fn new(inner: H, skip: usize) -> Self {
+        let skip_bits = skip * 8;
+        Self {
+            inner,
+            delayed: VecDeque::with_capacity(skip_bits + 1),
+            skip_bits,
+        }
+    }
+}
+
+impl<H: History> History for Skip<H> {
+    fn update(&mut self, bit: u8) {
+        self.delayed.push_back(bit);
+        if self.delayed.len() > self.skip_bits {
+            let bit = self.delayed.pop_front().unwrap();
+            self.inner.update(bit);
+        }
+    }
+
+    fn hash(&mut self) -> u32 {
+        self.inner.hash()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::RawHistory;
+
+    fn feed(history: &mut impl History, bytes: &[u8]) -> u32 {
+        for &byte in bytes {
+            for bit in (0..8).rev().map(|i| (byte >> i) & 1) {
+                history.update(bit);
+            }
+        }
+        history.hash()
+    }
+
+    #[test]
+    fn concat() {
+        let mut history = Concat::new(RawHistory::new(), 8, Skip::new(RawHistory::new(), 1));
+        assert_eq!(
+            feed(&mut history, b"abc"),
+            u32::from_be_bytes([0, b'a', b'b', b'c'])
+        );
+    }
+
+    #[test]
+    fn xor_and_mix() {
+        let mut xor = Xor::new(RawHistory::new(), RawHistory::new());
+        assert_eq!(feed(&mut xor, b"abcd"), 0);
+        let mut mix = Mix::new(RawHistory::new(), Skip::new(RawHistory::new(), 1));
+        let mut other = Mix::new(RawHistory::new(), Skip::new(RawHistory::new(), 2));
+        assert_ne!(feed(&mut mix, b"abcd"), feed(&mut other, b"abcd"));
+    }
+
+    #[test]
+    fn sparse() {
+        let mut lower = Sparse::new(RawHistory::new(), 0b1101_1111);
+        let mut upper = Sparse::new(RawHistory::new(), 0b1101_1111);
+        assert_eq!(feed(&mut lower, b"the"), feed(&mut upper, b"THE"));
+        let mut nibbles = Sparse::new(RawHistory::new(), 0b1111_0000);
+        assert_eq!(feed(&mut nibbles, &[0x12, 0x34, 0x56]), 0x135);
+    }
+
+    #[test]
+    fn skip() {
+        let mut history = Skip::new(RawHistory::new(), 2);
+        assert_eq!(feed(&mut history, b"ab"), 0);
+        assert_eq!(
+            feed(&mut history, b"cde"),
+            u32::from_be_bytes([0, b'a', b'b', b'c'])
+        );
+    }
+}
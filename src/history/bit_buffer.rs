@@ -0,0 +1,220 @@
+use crate::state::{invalid_state, read_bytes, State};
+use crate::{u8, usize};
+use std::io::{self, Read, Write};
+
+/// Storage for the newest bits of a history, read back from the newest
+pub trait BitBuffer {
+    fn push(&mut self, bit: u8);
+    /// The bit `i` positions back, 0 is the newest, 0s before the stream start
+    fn bit(&self, i: usize) -> u8;
+    /// The newest 64 bits, the newest in the lowest bit
+    fn newest(&self) -> u64;
+    /// Number of bits that can be read back
+    fn capacity(&self) -> usize;
+}
+
+impl BitBuffer for u64 {
+    fn push(&mut self, bit: u8) {
+        *self = (*self << 1) | u64::from(bit);
+    }
+
+    fn bit(&self, i: usize) -> u8 {
+        u8!((*self >> i) & 1)
+    }
+
+    fn newest(&self) -> u64 {
+        *self
+    }
+
+    fn capacity(&self) -> usize {
+        64
+    }
+}
+
+impl BitBuffer for u128 {
+    fn push(&mut self, bit: u8) {
+        *self = (*self << 1) | u128::from(bit);
+    }
+
+    fn bit(&self, i: usize) -> u8 {
+        u8!((*self >> i) & 1)
+    }
+
+    fn newest(&self) -> u64 {
+        *self as u64
+    }
+
+    fn capacity(&self) -> usize {
+        128
+    }
+}
+
+/// The newest `len` whole bytes plus the bits of the current byte
+#[derive(Clone)]
+pub struct ByteRing {
+    bytes: Vec<u8>,
+    // next byte to overwrite, the oldest one
+    pos: usize,
+    // bits of the current byte behind a leading 1
+    partial: u16,
+}
+
+impl ByteRing {
+    pub fn new(len: usize) -> Self {
+        assert!(len > 0, "ByteRing needs at least one byte");
+        Self { bytes: vec![0; len], pos: 0, partial: 1 }
+    }
+
+    fn partial_len(&self) -> usize {
+        usize!(self.partial.ilog2())
+    }
+
+    /// The byte `i` whole bytes back, 0 is the newest
+    fn byte(&self, i: usize) -> u8 {
+        let len = self.bytes.len();
+        self.bytes[(self.pos + len - 1 - i % len) % len]
+    }
+}
+
+impl BitBuffer for ByteRing {
+    fn push(&mut self, bit: u8) {
+        self.partial = (self.partial << 1) | u16::from(bit);
+        if self.partial >= 0x100 {
+            self.bytes[self.pos] = u8!(self.partial & 0xff);
+            self.pos = (self.pos + 1) % self.bytes.len();
+            self.partial = 1;
+        }
+    }
+
+    fn bit(&self, i: usize) -> u8 {
+        let partial_len = self.partial_len();
+        if i < partial_len {
+            return u8!((self.partial >> i) & 1);
+        }
+        let i = i - partial_len;
+        if i >= self.bytes.len() * 8 {
+            return 0;
+        }
+        (self.byte(i / 8) >> (i % 8)) & 1
+    }
+
+    fn newest(&self) -> u64 {
+        let partial_len = self.partial_len();
+        let mut bits = 0;
+        for i in (0..8.min(self.bytes.len())).rev() {
+            bits = (bits << 8) | u64::from(self.byte(i));
+        }
+        let partial = u64::from(self.partial) & ((1 << partial_len) - 1);
+        bits.checked_shl(u32::try_from(partial_len).unwrap())
+            .unwrap_or(0)
+            | partial
+    }
+
+    fn capacity(&self) -> usize {
+        self.bytes.len() * 8 + self.partial_len()
+    }
+}
+
+impl State for u64 {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.to_be_bytes())
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        *self = u64::from_be_bytes(read_bytes(reader)?);
+        Ok(())
+    }
+}
+
+impl State for u128 {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.to_be_bytes())
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        *self = u128::from_be_bytes(read_bytes(reader)?);
+        Ok(())
+    }
+}
+
+impl State for ByteRing {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&(self.pos as u64).to_be_bytes())?;
+        writer.write_all(&self.partial.to_be_bytes())?;
+        writer.write_all(&self.bytes)
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        let pos = u64::from_be_bytes(read_bytes(reader)?);
+        let partial = u16::from_be_bytes(read_bytes(reader)?);
+        if pos >= self.bytes.len() as u64 || !(1..0x100).contains(&partial) {
+            return Err(invalid_state("ByteRing"));
+        }
+        reader.read_exact(&mut self.bytes)?;
+        self.pos = usize!(pos);
+        self.partial = partial;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::Rng;
+
+    #[test]
+    fn buffers_agree() {
+        let mut rng = Rng::new(0);
+        let mut small = 0u64;
+        let mut large = 0u128;
+        let mut ring = ByteRing::new(16);
+        for n in 0..1000 {
+            let bit = u8!(rng.below(2));
+            small.push(bit);
+            large.push(bit);
+            ring.push(bit);
+            assert_eq!(ring.newest(), small, "after {} bits", n);
+            assert_eq!(large.newest(), small);
+            for i in 0..64 {
+                assert_eq!(large.bit(i), small.bit(i));
+            }
+            for i in 0..128.min(ring.capacity()) {
+                assert_eq!(ring.bit(i), large.bit(i), "bit {} after {} bits", i, n);
+            }
+        }
+    }
+
+    #[test]
+    fn ring_capacity() {
+        let mut ring = ByteRing::new(2);
+        assert_eq!(ring.capacity(), 16);
+        for bit in [1, 0, 1] {
+            ring.push(bit);
+        }
+        assert_eq!(ring.capacity(), 19);
+        assert_eq!(ring.bit(0), 1);
+        assert_eq!(ring.bit(1), 0);
+        assert_eq!(ring.bit(2), 1);
+        assert_eq!(ring.bit(3), 0);
+        assert_eq!(ring.bit(100), 0);
+    }
+
+    #[test]
+    fn ring_state() {
+        let mut ring = ByteRing::new(3);
+        for bit in [1, 0, 1, 1, 0, 0, 1, 0, 1, 1, 1] {
+            ring.push(bit);
+        }
+        let mut state = Vec::new();
+        ring.write_state(&mut state).unwrap();
+        let mut restored = ByteRing::new(3);
+        restored.read_state(&mut state.as_slice()).unwrap();
+        for i in 0..ring.capacity() {
+            assert_eq!(restored.bit(i), ring.bit(i));
+        }
+        assert_eq!(restored.capacity(), ring.capacity());
+
+        state[7] = 3;
+        assert!(ByteRing::new(3).read_state(&mut state.as_slice()).is_err());
+    }
+}
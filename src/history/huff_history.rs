@@ -6,47 +6,63 @@ use crate::{
 
 use super::History;
 
+// (code, length) of every symbol
+type Table = Vec<(u16, u8)>;
+
 pub struct HuffHistory {
     pos: u64,
     bits: u64,
     compressed_bits: u32,
-    table: Vec<(u16, u8)>,
-    rem_table: Vec<(u16, u8)>,
+    table: Table,
+    rem_table: Table,
+    rebuild: Option<Rebuild>,
+}
+
+/// Counts of the bytes seen so far, for histories that rebuild their code
+struct Rebuild {
+    counts: Vec<u32>,
+    interval: u64,
+    bytes: u64,
+    huff_size: u8,
+    rem_huff_size: u8,
 }
 
 impl HuffHistory {
     pub fn new(buf: &[u8], huff_size: u8, rem_huff_size: u8) -> Self {
-        let counts = histogram(&buf);
-        let code_lens = package_merge(&counts, huff_size);
-        let mut huffman = canonical(&code_lens);
-        for i in 0..huffman.len() {
-            let (mut code, len) = huffman[i];
-            code = code.reverse_bits().overflowing_shr(u32::from(16 - len)).0;
-            huffman[i] = (code, len);
-        }
-
-        let mut rem_counts = vec![0; 256];
-        for (byte, count) in counts.iter().enumerate() {
-            for bit_len in 0..8 {
-                let sym_bits = byte >> (8 - bit_len);
-                let sym = (1 << bit_len) | sym_bits;
-                rem_counts[sym] += count;
-            }
-        }
-        let rem_code_lens = package_merge(&rem_counts, rem_huff_size); // TODO: maybe other param?
-        let mut rem_huffman = canonical(&rem_code_lens);
-        for i in 0..rem_huffman.len() {
-            let (mut code, len) = rem_huffman[i];
-            code = code.reverse_bits().overflowing_shr(u32::from(16 - len)).0;
-            rem_huffman[i] = (code, len);
+        let (table, rem_table) = tables(&histogram(buf), huff_size, rem_huff_size);
+        Self {
+            pos: 0,
+            bits: 0,
+            compressed_bits: 0,
+            table,
+            rem_table,
+            rebuild: None,
         }
+    }
 
+    /// Starts from a code for uniform bytes and rebuilds the codes from the
+    /// bytes seen so far every `interval` bytes, so a decoder can follow
+    pub fn adaptive(huff_size: u8, rem_huff_size: u8, interval: u64) -> Self {
+        assert!(
+            huff_size >= 8 && rem_huff_size >= 8,
+            "Adaptive codes cover all 256 bytes, lengths must be at least 8"
+        );
+        assert!(interval > 0, "Rebuild interval must be positive");
+        let counts = vec![0; 256];
+        let (table, rem_table) = tables(&smoothed(&counts), huff_size, rem_huff_size);
         Self {
             pos: 0,
             bits: 0,
             compressed_bits: 0,
-            table: huffman,
-            rem_table: rem_huffman,
+            table,
+            rem_table,
+            rebuild: Some(Rebuild {
+                counts,
+                interval,
+                bytes: 0,
+                huff_size,
+                rem_huff_size,
+            }),
         }
     }
 }
@@ -55,6 +71,20 @@ impl History for HuffHistory {
     fn update(&mut self, bit: u8) {
         self.bits = (self.bits << 1) | u64::from(bit);
         self.pos += 1;
+
+        let Some(rebuild) = self.rebuild.as_mut() else {
+            return;
+        };
+        if self.pos & 7 != 0 {
+            return;
+        }
+        rebuild.counts[usize::from(u8!(self.bits & 255))] += 1;
+        rebuild.bytes += 1;
+        if rebuild.bytes % rebuild.interval == 0 {
+            let counts = smoothed(&rebuild.counts);
+            (self.table, self.rem_table) =
+                tables(&counts, rebuild.huff_size, rebuild.rem_huff_size);
+        }
     }
 
     fn hash(&mut self) -> u32 {
@@ -71,3 +101,36 @@ impl History for HuffHistory {
         (self.compressed_bits << len) | u32::from(code)
     }
 }
+
+/// Every byte stays codable: counts are offset by 1
+fn smoothed(counts: &[u32]) -> Vec<u32> {
+    counts
+        .iter()
+        .map(|&count| count.saturating_add(1))
+        .collect()
+}
+
+/// Bit-reversed canonical codes of the bytes and of the partial bytes
+fn tables(counts: &[u32], huff_size: u8, rem_huff_size: u8) -> (Table, Table) {
+    let code_lens = package_merge(counts, huff_size);
+    let mut huffman = canonical(&code_lens);
+    for (code, len) in huffman.iter_mut() {
+        *code = code.reverse_bits().overflowing_shr(u32::from(16 - *len)).0;
+    }
+
+    let mut rem_counts = vec![0; 256];
+    for (byte, count) in counts.iter().enumerate() {
+        for bit_len in 0..8 {
+            let sym_bits = byte >> (8 - bit_len);
+            let sym = (1 << bit_len) | sym_bits;
+            rem_counts[sym] += count;
+        }
+    }
+    let rem_code_lens = package_merge(&rem_counts, rem_huff_size); // TODO: maybe other param?
+    let mut rem_huffman = canonical(&rem_code_lens);
+    for (code, len) in rem_huffman.iter_mut() {
+        *code = code.reverse_bits().overflowing_shr(u32::from(16 - *len)).0;
+    }
+
+    (huffman, rem_huffman)
+}
@@ -1,11 +1,20 @@
 pub mod ac_history;
 pub mod ac_history_cached;
+pub mod adapters;
+pub mod bit_buffer;
 pub mod huff_history;
 pub mod raw_history;
 
-pub use self::{ac_history::*, ac_history_cached::*, huff_history::*, raw_history::*};
+pub use self::{
+    ac_history::*, ac_history_cached::*, adapters::*, bit_buffer::*, huff_history::*,
+    raw_history::*,
+};
 
 pub trait History {
     fn update(&mut self, bit: u8);
     fn hash(&mut self) -> u32;
+    /// Bytes of tables the history allocated, e.g. for an adaptive hash model
+    fn memory(&self) -> usize {
+        0
+    }
 }
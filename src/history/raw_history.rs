@@ -1,4 +1,6 @@
 use super::History;
+use crate::state::{read_bytes, State};
+use std::io::{self, Read, Write};
 
 pub struct RawHistory {
     bits: u32,
@@ -10,6 +12,12 @@ impl RawHistory {
     }
 }
 
+impl Default for RawHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl History for RawHistory {
     fn update(&mut self, bit: u8) {
         self.bits = (self.bits << 1) | u32::from(bit);
@@ -19,3 +27,14 @@ impl History for RawHistory {
         self.bits
     }
 }
+
+impl State for RawHistory {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.bits.to_be_bytes())
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.bits = u32::from_be_bytes(read_bytes(reader)?);
+        Ok(())
+    }
+}
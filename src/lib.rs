@@ -2,12 +2,16 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
+pub mod compressor;
+pub mod corpus;
 pub mod entropy_coding;
 pub mod helpers;
 pub mod history;
 pub mod macros;
 pub mod models;
+pub mod state;
+pub mod state_table;
+pub mod transform;
 
 mod hashmap;
 mod mixers;
-mod state_table;
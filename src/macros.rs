@@ -46,55 +46,60 @@ macro_rules! unroll_collect {
 
 #[macro_export]
 macro_rules! u8 {
-    ($a:expr) => {
+    ($a:expr) => {{
+        let a = $a;
         if cfg!(feature = "unsafe_conversions") {
-            unsafe { u8::try_from($a).unwrap_unchecked() }
+            unsafe { u8::try_from(a).unwrap_unchecked() }
         } else {
-            u8::try_from($a).unwrap()
+            u8::try_from(a).unwrap()
         }
-    };
+    }};
 }
 
 #[macro_export]
 macro_rules! u16 {
-    ($a:expr) => {
+    ($a:expr) => {{
+        let a = $a;
         if cfg!(feature = "unsafe_conversions") {
-            unsafe { u16::try_from($a).unwrap_unchecked() }
+            unsafe { u16::try_from(a).unwrap_unchecked() }
         } else {
-            u16::try_from($a).unwrap()
+            u16::try_from(a).unwrap()
         }
-    };
+    }};
 }
 
 #[macro_export]
 macro_rules! u32 {
-    ($a:expr) => {
+    ($a:expr) => {{
+        let a = $a;
         if cfg!(feature = "unsafe_conversions") {
-            unsafe { u32::try_from($a).unwrap_unchecked() }
+            unsafe { u32::try_from(a).unwrap_unchecked() }
         } else {
-            u32::try_from($a).unwrap()
+            u32::try_from(a).unwrap()
         }
-    };
+    }};
 }
 
 #[macro_export]
 macro_rules! u64 {
-    ($a:expr) => {
+    ($a:expr) => {{
+        let a = $a;
         if cfg!(feature = "unsafe_conversions") {
-            unsafe { u64::try_from($a).unwrap_unchecked() }
+            unsafe { u64::try_from(a).unwrap_unchecked() }
         } else {
-            u64::try_from($a).unwrap()
+            u64::try_from(a).unwrap()
         }
-    };
+    }};
 }
 
 #[macro_export]
 macro_rules! usize {
-    ($a:expr) => {
+    ($a:expr) => {{
+        let a = $a;
         if cfg!(feature = "unsafe_conversions") {
-            unsafe { usize::try_from($a).unwrap_unchecked() }
+            unsafe { usize::try_from(a).unwrap_unchecked() }
         } else {
-            usize::try_from($a).unwrap()
+            usize::try_from(a).unwrap()
         }
-    };
+    }};
 }
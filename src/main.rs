@@ -1,18 +1,12 @@
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom};
 use std::time::Instant;
-use std::{env, fs, fs::File, path::PathBuf};
-
-use weath3rb0i::{
-    entropy_coding::{
-        arithmetic_coder::ArithmeticCoder,
-        io::{ACReader, ACWriter},
-    },
-    history::ACHistory,
-    models::{ac_hash::StationaryModel, Model},
-};
+use std::{env, fs, fs::File, fs::OpenOptions, path::PathBuf};
 
-const MAGIC_STR: &[u8; 4] = b"w30i";
-const MAGIC_NUM: u32 = u32::from_be_bytes(*MAGIC_STR);
+use weath3rb0i::compressor::{
+    self, Checkpoint, Dictionary, Limits, Options, Preset, Reference, SeekableReader, Summary,
+    TextModels,
+};
+use weath3rb0i::transform::TextOptions;
 
 #[derive(Clone, Copy)]
 enum Action {
@@ -22,7 +16,121 @@ enum Action {
 }
 
 fn main() -> std::io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let mut options = Options::default();
+    if let Some(idx) = args.iter().position(|arg| arg == "-m") {
+        let Some(memory) = args
+            .get(idx + 1)
+            .and_then(|x| compressor::parse_size(x))
+            .filter(|&memory| memory <= compressor::MAX_MEMORY)
+        else {
+            print_usage_and_exit("-m expects a size like 256MB (at most 1GB)");
+        };
+        options = options.memory(memory);
+        args.drain(idx..=idx + 1);
+    }
+    if let Some(idx) = args.iter().position(|arg| arg == "--preset") {
+        let Some(preset) = args.get(idx + 1).and_then(|x| Preset::from_name(x)) else {
+            print_usage_and_exit("--preset expects text, binary or store");
+        };
+        options = options.preset(preset);
+        args.drain(idx..=idx + 1);
+    }
+    if let Some(idx) = args.iter().position(|arg| arg == "--models") {
+        let Some(models) = args.get(idx + 1).and_then(|x| TextModels::from_names(x)) else {
+            print_usage_and_exit("--models expects a list like states,run,bytes");
+        };
+        options = options.models(models);
+        args.drain(idx..=idx + 1);
+    }
+    if let Some(idx) = args.iter().position(|arg| arg == "--blocks") {
+        let Some(block_size) = args
+            .get(idx + 1)
+            .and_then(|x| compressor::parse_size(x))
+            .filter(|size| (1..=compressor::MAX_BLOCK_SIZE).contains(size))
+        else {
+            print_usage_and_exit("--blocks expects a size like 1MB (at most 1GB)");
+        };
+        options = options.blocks(block_size);
+        args.drain(idx..=idx + 1);
+    }
+    let mut checkpoint = None;
+    if let Some(idx) = args.iter().position(|arg| arg == "--checkpoint") {
+        let Some(interval) = args
+            .get(idx + 1)
+            .and_then(|x| compressor::parse_size(x))
+            .filter(|&interval| interval > 0)
+        else {
+            print_usage_and_exit("--checkpoint expects a size like 256MB");
+        };
+        checkpoint = Some(interval);
+        args.drain(idx..=idx + 1);
+    }
+    let mut dictionary = None;
+    if let Some(idx) = args.iter().position(|arg| arg == "--dict") {
+        let Some(path) = args.get(idx + 1) else {
+            print_usage_and_exit("--dict expects a dictionary file");
+        };
+        dictionary = Some(Dictionary::read(&mut BufReader::new(File::open(path)?))?);
+        args.drain(idx..=idx + 1);
+    }
+    let mut reference_bytes = None;
+    if let Some(idx) = args.iter().position(|arg| arg == "--ref") {
+        let Some(path) = args.get(idx + 1) else {
+            print_usage_and_exit("--ref expects a reference file");
+        };
+        reference_bytes = Some(fs::read(path)?);
+        args.drain(idx..=idx + 1);
+    }
+    let reference = reference_bytes.as_deref().map(Reference::new);
+    if dictionary.is_some() && options.models != TextModels::default() {
+        print_usage_and_exit("--models doesn't apply with --dict");
+    }
+    if checkpoint.is_some() && dictionary.is_some() {
+        print_usage_and_exit("--checkpoint doesn't apply with --dict");
+    }
+    if reference.is_some() && (checkpoint.is_some() || dictionary.is_some()) {
+        print_usage_and_exit("--ref doesn't apply with --checkpoint or --dict");
+    }
+    if let Some(idx) = args.iter().position(|arg| arg == "--huffman") {
+        options = options.huffman(true);
+        args.remove(idx);
+    }
+    if let Some(idx) = args.iter().position(|arg| arg == "--seekable") {
+        options = options.seekable(true);
+        args.remove(idx);
+    }
+    if let Some(idx) = args.iter().position(|arg| arg == "--dedup") {
+        options = options.dedup(true);
+        args.remove(idx);
+    }
+    let eol = match args.iter().position(|arg| arg == "--eol") {
+        Some(idx) => {
+            args.remove(idx);
+            true
+        }
+        None => false,
+    };
+    if let Some(idx) = args.iter().position(|arg| arg == "--text") {
+        options = options.text(TextOptions::default().eol(eol));
+        args.remove(idx);
+    } else if eol {
+        print_usage_and_exit("--eol only applies with --text");
+    }
+
+    if args.len() == 5 && args[1] == "x" {
+        let range = (
+            compressor::parse_size(&args[3]),
+            compressor::parse_size(&args[4]),
+        );
+        let (Some(offset), Some(len)) = range else {
+            print_usage_and_exit("x expects an offset and a length like 1MB");
+        };
+        return extract(PathBuf::from(&args[2]), offset, len, dictionary);
+    }
+    if args.len() == 3 && args[1] == "train" {
+        return train(PathBuf::from(&args[2]), &options);
+    }
     if args.len() != 3 {
         print_usage_and_exit("Invokation doesn't match usage! Provide 2 arguments.");
     }
@@ -42,17 +150,40 @@ fn main() -> std::io::Result<()> {
         for file in fs::read_dir(path)? {
             let file_path = file?.path();
             if file_path.is_file() {
-                run(file_path, action)?;
+                let dictionary = dictionary.as_ref();
+                run(
+                    file_path,
+                    action,
+                    &options,
+                    dictionary,
+                    reference.as_ref(),
+                    checkpoint,
+                )?;
             }
         }
     } else if path.is_file() {
-        run(path, action)?;
+        let dictionary = dictionary.as_ref();
+        run(
+            path,
+            action,
+            &options,
+            dictionary,
+            reference.as_ref(),
+            checkpoint,
+        )?;
     }
 
     Ok(())
 }
 
-fn run(file_path: PathBuf, action: Action) -> std::io::Result<()> {
+fn run(
+    file_path: PathBuf,
+    action: Action,
+    options: &Options,
+    dictionary: Option<&Dictionary>,
+    reference: Option<&Reference>,
+    checkpoint: Option<u64>,
+) -> std::io::Result<()> {
     assert!(file_path.is_file());
 
     let out_path = {
@@ -70,94 +201,209 @@ fn run(file_path: PathBuf, action: Action) -> std::io::Result<()> {
     let timer = Instant::now();
     match action {
         Action::Compress => {
-            compress(file_path, out_path)?;
+            let summary = match checkpoint {
+                Some(interval) => compress_checkpointed(file_path, out_path, options, interval)?,
+                None => compress(file_path, out_path, options, dictionary, reference)?,
+            };
             println!("Compression took: {:?}", timer.elapsed());
+            print_memory_usage(&summary);
         }
         Action::Decompress => {
-            decompress(file_path, out_path)?;
+            let summary = decompress(file_path, out_path, dictionary, reference)?;
             println!("Decompression took: {:?}", timer.elapsed());
+            print_memory_usage(&summary);
         }
         Action::Test => {
-            run(file_path, Action::Compress)?;
-            run(out_path, Action::Decompress)?;
+            run(
+                file_path,
+                Action::Compress,
+                options,
+                dictionary,
+                reference,
+                checkpoint,
+            )?;
+            run(
+                out_path,
+                Action::Decompress,
+                options,
+                dictionary,
+                reference,
+                checkpoint,
+            )?;
         }
     }
 
     Ok(())
 }
 
-fn compress(input_file: PathBuf, output_file: PathBuf) -> std::io::Result<()> {
-    let mut writer = BufWriter::new(File::create(output_file)?);
-    let reader = {
-        let f = File::open(input_file)?;
-        let len = f.metadata()?.len();
+fn compress(
+    input_file: PathBuf,
+    output_file: PathBuf,
+    options: &Options,
+    dictionary: Option<&Dictionary>,
+    reference: Option<&Reference>,
+) -> std::io::Result<Summary> {
+    let f = File::open(input_file)?;
+    let len = f.metadata()?.len();
+    let reader = BufReader::new(f);
+    let writer = BufWriter::new(File::create(output_file)?);
+    match (dictionary, reference) {
+        (Some(dictionary), _) => {
+            compressor::compress_with_dictionary(reader, len, writer, options, dictionary)
+        }
+        (None, Some(reference)) => {
+            compressor::compress_with_reference(reader, len, writer, options, reference)
+        }
+        (None, None) => compressor::compress_with(reader, len, writer, options),
+    }
+}
 
-        writer.write_all(MAGIC_STR)?;
-        writer.write_all(&len.to_be_bytes())?;
-        BufReader::new(f)
+/// Compresses with a checkpoint next to the output every `interval` bytes of
+/// input, resuming from it if an earlier run left one behind
+fn compress_checkpointed(
+    input_file: PathBuf,
+    output_file: PathBuf,
+    options: &Options,
+    interval: u64,
+) -> std::io::Result<Summary> {
+    let checkpoint_file = output_file.with_extension("ckpt");
+    let save = |checkpoint: &Checkpoint, output: &mut BufWriter<File>| {
+        // the output the checkpoint covers reaches the disk before it does
+        output.get_ref().sync_data()?;
+        // a crash while saving leaves the last checkpoint intact
+        let tmp_file = checkpoint_file.with_extension("ckpt.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_file)?);
+        checkpoint.write(&mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&tmp_file, &checkpoint_file)
     };
-    let mut writer = ACWriter::new(writer);
-    let mut ac = ArithmeticCoder::new_coder();
-    let mut model = init_model();
-
-    for byte in reader.bytes().map(|byte| byte.unwrap()) {
-        for bit in (0..8).rev().map(|i| (byte >> i) & 1) {
-            let p = model.predict();
-            model.update(bit);
-            ac.encode(bit, p, &mut writer)?;
+
+    let f = File::open(input_file)?;
+    let len = f.metadata()?.len();
+    let mut reader = BufReader::new(f);
+    let summary = if checkpoint_file.is_file() {
+        let checkpoint = Checkpoint::read(&mut BufReader::new(File::open(&checkpoint_file)?))?;
+        if checkpoint.header.len != len {
+            print_usage_and_exit("The checkpoint next to the output is for another input");
         }
+        println!("Resuming from byte {}", checkpoint.input_pos);
+        reader.seek(SeekFrom::Start(checkpoint.input_pos))?;
+        let mut out = OpenOptions::new().write(true).open(&output_file)?;
+        out.set_len(checkpoint.output_pos)?;
+        out.seek(SeekFrom::End(0))?;
+        let writer = BufWriter::new(out);
+        compressor::resume_compression(reader, writer, &checkpoint, interval, save)?
+    } else {
+        let writer = BufWriter::new(File::create(output_file)?);
+        compressor::compress_with_checkpoints(reader, len, writer, options, interval, save)?
+    };
+    if checkpoint_file.is_file() {
+        fs::remove_file(checkpoint_file)?;
     }
-
-    ac.flush(&mut writer)?;
-    Ok(())
+    Ok(summary)
 }
 
-fn decompress(input_file: PathBuf, output_file: PathBuf) -> std::io::Result<()> {
-    let mut reader = BufReader::new(File::open(input_file)?);
-    let mut writer = BufWriter::new(File::create(output_file)?);
-
-    let len = {
-        let mut len_buf = [0; std::mem::size_of::<u32>() + std::mem::size_of::<u64>()];
-        reader.read_exact(&mut len_buf)?;
+fn decompress(
+    input_file: PathBuf,
+    output_file: PathBuf,
+    dictionary: Option<&Dictionary>,
+    reference: Option<&Reference>,
+) -> std::io::Result<Summary> {
+    let reader = BufReader::new(File::open(input_file)?);
+    let writer = BufWriter::new(File::create(output_file)?);
+    let limits = Limits::unlimited();
+    match (dictionary, reference) {
+        (Some(dictionary), _) => {
+            compressor::decompress_with_dictionary(reader, writer, &limits, dictionary)
+        }
+        (None, Some(reference)) => {
+            compressor::decompress_with_reference(reader, writer, &limits, reference)
+        }
+        (None, None) => compressor::decompress_with_limits(reader, writer, &limits),
+    }
+}
 
-        let magic_num = u32::from_be_bytes(len_buf[..4].try_into().unwrap());
-        assert_eq!(magic_num, MAGIC_NUM, "Magic numbers don't match up - file wasn't compressed with (this version of) weath3rb0i!");
-        u64::from_be_bytes(len_buf[4..].try_into().unwrap())
+/// Trains a dictionary on the file (or all files of the directory) at `path`
+/// and writes it next to the outputs as `<name>.dict`
+fn train(path: PathBuf, options: &Options) -> std::io::Result<()> {
+    let mut corpus = Vec::new();
+    let files = match path.is_dir() {
+        true => fs::read_dir(&path)?
+            .map(|file| file.map(|file| file.path()))
+            .collect::<std::io::Result<Vec<_>>>()?,
+        false => vec![path.clone()],
     };
-    let mut reader = ACReader::new(reader);
-    let mut ac = ArithmeticCoder::new_decoder(&mut reader)?;
-    let mut model = init_model();
-
-    for _ in 0..len {
-        let mut byte = 0;
-        for _ in 0..u8::BITS {
-            let p = model.predict();
-            let bit = ac.decode(p, &mut reader)?;
-            model.update(bit);
-            byte = (byte << 1) | bit;
-        }
-        writer.write_all(&[byte])?;
+    for file in files.iter().filter(|file| file.is_file()) {
+        File::open(file)?.read_to_end(&mut corpus)?;
+    }
+    let preset = options.preset.unwrap_or_else(|| Preset::detect(&corpus));
+    if preset == Preset::Store {
+        print_usage_and_exit("Stored files have no models to train, pick --preset text or binary");
     }
 
-    writer.flush()?;
+    let timer = Instant::now();
+    let dictionary = Dictionary::train(&corpus, options.memory, preset);
+    let mut out_path = std::env::current_dir()?;
+    out_path.push(path.file_name().unwrap());
+    out_path.set_extension("dict");
+    dictionary.write(&mut BufWriter::new(File::create(out_path)?))?;
+    println!("Training took: {:?}", timer.elapsed());
+    println!("Preset: {}", preset.name());
+    println!("Dictionary id: {:016x}", dictionary.id);
+    Ok(())
+}
+
+/// Writes `len` bytes from `offset` on of the seekable stream in `input_file` to stdout
+fn extract(
+    input_file: PathBuf,
+    offset: u64,
+    len: u64,
+    dictionary: Option<Dictionary>,
+) -> std::io::Result<()> {
+    let inner = BufReader::new(File::open(input_file)?);
+    let mut reader = match dictionary {
+        Some(dictionary) => SeekableReader::with_dictionary(inner, dictionary)?,
+        None => SeekableReader::new(inner)?,
+    };
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut stdout = std::io::stdout().lock();
+    std::io::copy(&mut reader.take(len), &mut stdout)?;
     Ok(())
 }
 
-fn init_model() -> impl Model {
-    use weath3rb0i::models::*;
-    // BestOfTwoModel::new(Order0::new(), Order1::new())
-    // BestOfTwoModel::new(Order0Entropy::new(), Order0::new())
-    // BestOfTwoModel::new(Order1::new(), Order0Entropy::new())
-    OrderNEntropy::new(11, 3, ACHistory::new(8, StationaryModel::for_book1()))
+fn print_memory_usage(summary: &Summary) {
+    println!("Preset: {}", summary.preset.name());
+    for (name, bytes) in &summary.memory_usage {
+        println!("  {}: {} KB", name, bytes >> 10);
+    }
+    println!("Model memory: {} KB", summary.total_memory() >> 10);
 }
 
 fn print_usage_and_exit(msg: &str) -> ! {
-    println!("Usage: weath3rb0i <Action> <Path>");
+    println!(
+        "Usage: weath3rb0i [-m <Memory>] [--preset <Preset>] [--models <Models>] [--blocks <Size>] [--seekable] [--dict <Dict>] [--checkpoint <Size>] [--ref <File>] [--huffman] [--dedup] [--text [--eol]] <Action> <Path>"
+    );
+    println!("       weath3rb0i [--dict <Dict>] x <File> <Offset> <Length>");
+    println!("       weath3rb0i [-m <Memory>] [--preset <Preset>] train <Path>");
     println!("<Action> [single file]: c (compress), d (decompress), t (test = c + d)");
+    println!("x: write <Length> bytes from <Offset> of a seekable stream to stdout");
+    println!("train: train the models on the file(s) at <Path>, writes <name>.dict");
+    println!("<Dict> models trained with train, needed again to decompress");
+    println!("--ref: compress as a delta against <File>, needed again to decompress");
+    println!("--checkpoint: save <name>.ckpt every <Size> of input, c resumes from it");
     println!("<Path> can be a single file or a directory");
+    println!("<Memory> budget for all models, e.g. 256MB (stored in the header, default 8KB)");
+    println!("<Preset> text, binary or store (no modeling), picked from the input by default");
+    println!(
+        "<Models> mixed into the text preset, comma separated: states, run, bytes, adaptive0 or adaptive1"
+    );
+    println!("<Size> of blocks, blocks the models would expand are stored as is");
+    println!("--seekable: fresh models per block (1MB unless --blocks) and a block index");
+    println!("--huffman: code bytes as Huffman codewords, fewer binary decisions on text");
+    println!("--dedup: replace repeats of 32+ bytes with references before modeling");
+    println!("--text: escape capitals and replace frequent words before modeling");
+    println!("--eol: with --text, store CRLF line endings as LF");
     println!("Note: Directories are shallow traversed");
     println!("\n{}", msg);
     std::process::exit(1);
 }
-
-// TODO: Add tests
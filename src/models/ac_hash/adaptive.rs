@@ -0,0 +1,436 @@
+use crate::state::{invalid_state, read_bytes, State};
+use crate::{
+    models::{
+        counter::{read_counters, write_counters},
+        ACHashModel, Counter,
+    },
+    u8,
+};
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+
+// counters of one bit position: every (known bits, their value) pair below 2^8
+const NODES: usize = 256;
+
+/// Order-0 model of the history that learns from the stream
+///
+/// A pass codes every byte from its last bit to its first, so the context of
+/// a bit is its position and the bits after it in the byte, as far as the pass
+/// has seen them. Counters only learn from complete bytes, for every number of
+/// known bits a pass could start with.
+#[derive(Clone)]
+pub struct AdaptiveOrder0 {
+    stats: Stats,
+    stream: Stream,
+    pass: Pass,
+}
+
+impl AdaptiveOrder0 {
+    /// Bytes allocated for the counters
+    pub const MEMORY: usize = Stats::memory(8 * NODES);
+
+    pub fn new() -> Self {
+        Self {
+            stats: Stats::new(8 * NODES),
+            stream: Stream::new(),
+            pass: Pass::new(0),
+        }
+    }
+}
+
+impl Default for AdaptiveOrder0 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ACHashModel for AdaptiveOrder0 {
+    fn predict(&mut self) -> u16 {
+        self.stats.p(self.pass.idx())
+    }
+
+    fn align(&mut self, alignment: u8) {
+        self.pass = Pass::new(alignment);
+    }
+
+    fn step(&mut self, bit: u8) {
+        self.pass.step(bit);
+    }
+
+    fn epoch(&self) -> u64 {
+        self.stream.refreshes()
+    }
+
+    fn memory(&self) -> usize {
+        Self::MEMORY
+    }
+
+    fn update(&mut self, bit: u8) {
+        if let Some(byte) = self.stream.update(bit) {
+            self.stats.learn(0, byte);
+            if self.stream.refresh_due() {
+                self.stats.refresh();
+            }
+        }
+    }
+}
+
+/// Order-1 model of the history that learns from the stream
+///
+/// Like `AdaptiveOrder0`, but a pass has already seen the byte after the one
+/// it's coding, which makes the order-1 context. Until a pass has coded a
+/// whole byte there is none and the order-0 counters predict instead.
+#[derive(Clone)]
+pub struct AdaptiveOrder1 {
+    order0: AdaptiveOrder0,
+    stats: Stats,
+    // the byte before the one being completed in the stream
+    prev: Option<u8>,
+}
+
+impl AdaptiveOrder1 {
+    /// Bytes allocated for the counters
+    pub const MEMORY: usize = Stats::memory(256 * 8 * NODES) + AdaptiveOrder0::MEMORY;
+
+    pub fn new() -> Self {
+        Self {
+            order0: AdaptiveOrder0::new(),
+            stats: Stats::new(256 * 8 * NODES),
+            prev: None,
+        }
+    }
+}
+
+impl Default for AdaptiveOrder1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ACHashModel for AdaptiveOrder1 {
+    fn predict(&mut self) -> u16 {
+        let pass = &self.order0.pass;
+        match pass.next_byte {
+            Some(next) => self.stats.p(usize::from(next) * 8 * NODES + pass.idx()),
+            None => self.order0.predict(),
+        }
+    }
+
+    fn align(&mut self, alignment: u8) {
+        self.order0.align(alignment);
+    }
+
+    fn step(&mut self, bit: u8) {
+        self.order0.step(bit);
+    }
+
+    fn epoch(&self) -> u64 {
+        self.order0.epoch()
+    }
+
+    fn memory(&self) -> usize {
+        Self::MEMORY
+    }
+
+    fn update(&mut self, bit: u8) {
+        let Some(byte) = self.order0.stream.update(bit) else {
+            return;
+        };
+        self.order0.stats.learn(0, byte);
+        // a pass codes `prev` after it has seen `byte`
+        if let Some(prev) = self.prev {
+            self.stats.learn(usize::from(byte) * 8 * NODES, prev);
+        }
+        self.prev = Some(byte);
+        if self.order0.stream.refresh_due() {
+            self.order0.stats.refresh();
+            self.stats.refresh();
+        }
+    }
+}
+
+/// Counters that learn all the time and the probabilities passes predict with
+///
+/// The probabilities are only refreshed from the counters at growing
+/// intervals: a history has to keep hashing to the same context for the
+/// statistics of that context to be of any use.
+#[derive(Clone)]
+struct Stats {
+    counters: Vec<Counter>,
+    table: Vec<u16>,
+}
+
+impl Stats {
+    fn new(len: usize) -> Self {
+        Self {
+            counters: vec![Counter::new(); len],
+            table: vec![Counter::new().p(); len],
+        }
+    }
+
+    /// Bytes of the counters and probabilities of `len` contexts
+    const fn memory(len: usize) -> usize {
+        len * (size_of::<Counter>() + size_of::<u16>())
+    }
+
+    fn p(&self, idx: usize) -> u16 {
+        self.table[idx]
+    }
+
+    fn refresh(&mut self) {
+        for (p, counter) in self.table.iter_mut().zip(&self.counters) {
+            *p = counter.p();
+        }
+    }
+
+    /// Updates the counters of every context a pass can code the bits of `byte` in
+    fn learn(&mut self, offset: usize, byte: u8) {
+        for pos in 0..8u8 {
+            let bit = (byte >> (7 - pos)) & 1;
+            // a pass may have seen up to all bits after `pos`
+            for known in 0..8 - pos {
+                let value = (byte >> (7 - pos - known)) & ((1 << known) - 1);
+                let node = (1 << known) | usize::from(value);
+                self.counters[offset + usize::from(pos) * NODES + node].update(bit);
+            }
+        }
+    }
+}
+
+/// Collects the stream into bytes
+#[derive(Clone)]
+struct Stream {
+    // bits of the current byte behind a leading 1
+    partial: u16,
+    bytes: u64,
+}
+
+impl Stream {
+    // bytes before the first refresh of the probabilities, every following
+    // one waits 16 times as long
+    const FIRST_REFRESH: u64 = 1 << 8;
+
+    fn new() -> Self {
+        Self { partial: 1, bytes: 0 }
+    }
+
+    fn refresh_due(&self) -> bool {
+        // at 2^8, 2^12, 2^16... bytes
+        self.bytes >= Self::FIRST_REFRESH
+            && self.bytes.is_power_of_two()
+            && self.bytes.trailing_zeros().is_multiple_of(4)
+    }
+
+    /// Number of refreshes so far
+    fn refreshes(&self) -> u64 {
+        match self.bytes {
+            bytes if bytes < Self::FIRST_REFRESH => 0,
+            bytes => u64::from(bytes.ilog2() - Self::FIRST_REFRESH.ilog2()) / 4 + 1,
+        }
+    }
+
+    fn update(&mut self, bit: u8) -> Option<u8> {
+        self.partial = (self.partial << 1) | u16::from(bit);
+        if self.partial < 0x100 {
+            return None;
+        }
+        let byte = u8!(self.partial & 0xff);
+        self.partial = 1;
+        self.bytes += 1;
+        Some(byte)
+    }
+}
+
+/// Where a pass is: the position of the next bit to code within its byte and
+/// the bits after it that were already coded
+#[derive(Clone)]
+struct Pass {
+    pos: u8,
+    known: u8,
+    value: u16,
+    // whether the pass started at the end of the current byte
+    complete: bool,
+    next_byte: Option<u8>,
+}
+
+impl Pass {
+    fn new(alignment: u8) -> Self {
+        Self {
+            pos: (alignment + 7) & 7,
+            known: 0,
+            value: 0,
+            complete: alignment == 0,
+            next_byte: None,
+        }
+    }
+
+    fn idx(&self) -> usize {
+        usize::from(self.pos) * NODES + ((1 << self.known) | usize::from(self.value))
+    }
+
+    fn step(&mut self, bit: u8) {
+        self.value |= u16::from(bit) << self.known;
+        self.known += 1;
+        if self.pos > 0 {
+            self.pos -= 1;
+            return;
+        }
+        // the byte is done, continue at the last bit of the one before
+        self.next_byte = self.complete.then_some(u8!(self.value));
+        self.complete = true;
+        self.pos = 7;
+        self.known = 0;
+        self.value = 0;
+    }
+}
+
+// a pass starts over with every `align`, between passes it isn't state
+impl State for AdaptiveOrder0 {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.stats.write_state(writer)?;
+        writer.write_all(&self.stream.partial.to_be_bytes())?;
+        writer.write_all(&self.stream.bytes.to_be_bytes())
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.stats.read_state(reader)?;
+        let partial = u16::from_be_bytes(read_bytes(reader)?);
+        let bytes = u64::from_be_bytes(read_bytes(reader)?);
+        if !(1..0x100).contains(&partial) {
+            return Err(invalid_state("AdaptiveOrder0"));
+        }
+        self.stream = Stream { partial, bytes };
+        Ok(())
+    }
+}
+
+impl State for AdaptiveOrder1 {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.order0.write_state(writer)?;
+        self.stats.write_state(writer)?;
+        let prev = self.prev.map_or([0, 0], |prev| [1, prev]);
+        writer.write_all(&prev)
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.order0.read_state(reader)?;
+        self.stats.read_state(reader)?;
+        self.prev = match read_bytes(reader)? {
+            [0, 0] => None,
+            [1, prev] => Some(prev),
+            _ => return Err(invalid_state("AdaptiveOrder1")),
+        };
+        Ok(())
+    }
+}
+
+impl State for Stats {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_counters(writer, &self.counters)?;
+        let table: Vec<u8> = self.table.iter().flat_map(|p| p.to_be_bytes()).collect();
+        writer.write_all(&table)
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        read_counters(reader, &mut self.counters)?;
+        let mut table = vec![0; self.table.len() * size_of::<u16>()];
+        reader.read_exact(&mut table)?;
+        for (p, bytes) in self.table.iter_mut().zip(table.chunks_exact(2)) {
+            *p = u16::from_be_bytes([bytes[0], bytes[1]]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(model: &mut impl ACHashModel, bytes: &[u8]) {
+        for &byte in bytes {
+            for bit in (0..8).rev().map(|i| (byte >> i) & 1) {
+                model.update(bit);
+            }
+        }
+    }
+
+    /// Probabilities a pass over `bytes` (newest last) predicts for each bit
+    fn pass(model: &mut impl ACHashModel, bytes: &[u8]) -> Vec<u16> {
+        model.align(0);
+        let mut res = Vec::new();
+        for &byte in bytes.iter().rev() {
+            for bit in (0..8).map(|i| (byte >> i) & 1) {
+                res.push(model.predict());
+                model.step(bit);
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn order0_learns_bytes() {
+        let mut model = AdaptiveOrder0::new();
+        assert!(pass(&mut model, b"a").iter().all(|&p| p == 1 << 15));
+        feed(&mut model, &b"a".repeat(256));
+        // 'a' = 0b0110_0001, predicted from the last bit to the first
+        let expected = [1, 0, 0, 0, 0, 1, 1, 0];
+        for (p, bit) in pass(&mut model, b"a").into_iter().zip(expected) {
+            assert!(if bit == 1 { p > 60000 } else { p < 5000 }, "p = {}", p);
+        }
+    }
+
+    #[test]
+    fn order1_uses_next_byte() {
+        let mut model = AdaptiveOrder1::new();
+        // 'x' is always followed by 'y' and 'z' by 'w', order-0 can't tell
+        // which of 'x'/'z' comes before a byte
+        feed(&mut model, &b"xyzw".repeat(64));
+        let before_y = pass(&mut model, b"xy");
+        let before_w = pass(&mut model, b"zw");
+        for (bit, (p_x, p_z)) in before_y[8..].iter().zip(&before_w[8..]).enumerate() {
+            let (x, z) = ((b'x' >> bit) & 1, (b'z' >> bit) & 1);
+            if x != z {
+                assert!(p_x.abs_diff(1 << 15) > 20000 && p_z.abs_diff(1 << 15) > 20000);
+            }
+        }
+    }
+
+    #[test]
+    fn partial_bytes() {
+        // passes can start anywhere within a byte, every context is learned
+        let mut model = AdaptiveOrder1::new();
+        feed(&mut model, &b"abc".repeat(100));
+        for alignment in 0..8 {
+            model.align(alignment);
+            for _ in 0..64 {
+                let p = model.predict();
+                assert!(p > 0);
+                model.step(0);
+            }
+        }
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut model = AdaptiveOrder1::new();
+        // stops within a byte
+        feed(&mut model, b"abcab");
+        model.update(1);
+        let mut state = Vec::new();
+        model.write_state(&mut state).unwrap();
+
+        let mut restored = AdaptiveOrder1::new();
+        restored.read_state(&mut state.as_slice()).unwrap();
+        assert_eq!(restored.epoch(), model.epoch());
+        feed(&mut model, b"c");
+        feed(&mut restored, b"c");
+        assert_eq!(pass(&mut restored, b"abc"), pass(&mut model, b"abc"));
+
+        // the partial byte is always behind a leading 1
+        let mut stats = Vec::new();
+        Stats::new(8 * NODES).write_state(&mut stats).unwrap();
+        let partial = stats.len();
+        state[partial..partial + 2].copy_from_slice(&[0, 0]);
+        assert!(restored.read_state(&mut state.as_slice()).is_err());
+    }
+}
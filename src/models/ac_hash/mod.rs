@@ -1,3 +1,61 @@
+pub mod adaptive;
 pub mod stationary;
 
-pub use self::stationary::*;
+pub use self::{adaptive::*, stationary::*};
+
+use super::ACHashModel;
+use crate::state::State;
+use std::io::{self, Read, Write};
+
+/// One of the hash models, picked when a stream's header is read
+pub enum AnyHashModel {
+    Stationary(StationaryModel),
+    Order0(AdaptiveOrder0),
+    Order1(Box<AdaptiveOrder1>),
+}
+
+macro_rules! dispatch {
+    ($model:expr, $inner:ident => $call:expr) => {
+        match $model {
+            AnyHashModel::Stationary($inner) => $call,
+            AnyHashModel::Order0($inner) => $call,
+            AnyHashModel::Order1($inner) => $call,
+        }
+    };
+}
+
+impl ACHashModel for AnyHashModel {
+    fn predict(&mut self) -> u16 {
+        dispatch!(self, model => model.predict())
+    }
+
+    fn align(&mut self, alignment: u8) {
+        dispatch!(self, model => model.align(alignment))
+    }
+
+    fn step(&mut self, bit: u8) {
+        dispatch!(self, model => model.step(bit))
+    }
+
+    fn update(&mut self, bit: u8) {
+        dispatch!(self, model => model.update(bit))
+    }
+
+    fn epoch(&self) -> u64 {
+        dispatch!(self, model => model.epoch())
+    }
+
+    fn memory(&self) -> usize {
+        dispatch!(self, model => model.memory())
+    }
+}
+
+impl State for AnyHashModel {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        dispatch!(self, model => model.write_state(writer))
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        dispatch!(self, model => model.read_state(reader))
+    }
+}
@@ -1,7 +1,9 @@
+use crate::state::{invalid_state, read_bytes, State};
 use crate::{
     models::{ACHashModel, Counter},
     unroll_for,
 };
+use std::io::{self, Read, Write};
 
 // encodes bits in reverse
 #[derive(Clone)]
@@ -52,7 +54,26 @@ impl ACHashModel for StationaryModel {
     }
 
     fn predict(&mut self) -> u16 {
-        self.alignment = (self.alignment + 7) & 7; // -1 = 7 (mod 8)
-        self.table[usize::from(self.alignment)]
+        let alignment = (self.alignment + 7) & 7; // -1 = 7 (mod 8)
+        self.table[usize::from(alignment)]
+    }
+
+    fn step(&mut self, _bit: u8) {
+        self.alignment = (self.alignment + 7) & 7;
+    }
+}
+
+impl State for StationaryModel {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&[self.alignment])
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        let [alignment] = read_bytes(reader)?;
+        if alignment >= 8 {
+            return Err(invalid_state("StationaryModel"));
+        }
+        self.alignment = alignment;
+        Ok(())
     }
 }
@@ -1,4 +1,5 @@
 use crate::u16;
+use std::io::{self, Error, ErrorKind, Read, Write};
 
 #[derive(Copy, Clone)]
 pub struct Counter {
@@ -25,3 +26,34 @@ impl Counter {
         }
     }
 }
+
+/// Writes a table of counters, 4 bytes each
+pub fn write_counters(writer: &mut impl Write, counters: &[Counter]) -> io::Result<()> {
+    let bytes: Vec<u8> = counters
+        .iter()
+        .flat_map(|counter| counter.data.map(u16::to_be_bytes))
+        .flatten()
+        .collect();
+    writer.write_all(&bytes)
+}
+
+/// Fills `counters` with what `write_counters` wrote for a table of the same size
+pub fn read_counters(reader: &mut impl Read, counters: &mut [Counter]) -> io::Result<()> {
+    let mut bytes = vec![0; counters.len() * 4];
+    reader.read_exact(&mut bytes)?;
+    for (counter, bytes) in counters.iter_mut().zip(bytes.chunks_exact(4)) {
+        let data = [0, 2].map(|i| u16::from_be_bytes([bytes[i], bytes[i + 1]]));
+        // `update` halves the counts before they reach the maximum
+        if data.contains(&u16::MAX) {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid counter"));
+        }
+        counter.data = data;
+    }
+    Ok(())
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
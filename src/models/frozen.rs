@@ -1,4 +1,6 @@
-use crate::models::{AdaptiveModel, Model};
+use crate::models::{train, AdaptiveModel, Model, Tables};
+use crate::state::State;
+use std::io::{self, Read, Write};
 
 pub struct FrozenModel<T: AdaptiveModel> {
     pub model: T,
@@ -8,6 +10,12 @@ impl<T: AdaptiveModel> FrozenModel<T> {
     pub fn new(model: T) -> Self {
         Self { model }
     }
+
+    /// Trains `model` on `corpus`, then stops it from learning
+    pub fn trained(mut model: T, corpus: &[u8]) -> Self {
+        train(&mut model, corpus);
+        Self::new(model)
+    }
 }
 
 impl<T: AdaptiveModel> Model for FrozenModel<T> {
@@ -18,4 +26,28 @@ impl<T: AdaptiveModel> Model for FrozenModel<T> {
     fn update(&mut self, bit: u8) {
         self.model.update(bit);
     }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        self.model.memory_usage()
+    }
+}
+
+impl<T: AdaptiveModel + Tables> Tables for FrozenModel<T> {
+    fn write_tables(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.model.write_tables(writer)
+    }
+
+    fn read_tables(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.model.read_tables(reader)
+    }
+}
+
+impl<T: AdaptiveModel + State> State for FrozenModel<T> {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.model.write_state(writer)
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.model.read_state(reader)
+    }
 }
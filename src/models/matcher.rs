@@ -0,0 +1,142 @@
+use super::{counter::Counter, AdaptiveModel};
+use crate::{helpers::hash_u64, u8, usize};
+use std::mem::size_of_val;
+
+const HALF: u16 = 1 << 15;
+// bytes of context a match is looked up by
+const MIN_LEN: usize = 6;
+// matches longer than this share a confidence counter
+const MAX_LEN: usize = 31;
+
+/// Predicts that the input continues the way it did after the last earlier
+/// occurrence of its recent bytes
+///
+/// Every position is indexed by a hash of the `MIN_LEN` bytes in front of it.
+/// Without a match, each byte boundary looks up the newest position with the
+/// same context and, if the bytes really agree, follows it from there. While
+/// the current byte still agrees with the byte the match points at, its next
+/// bit is predicted with a confidence learned per match length, otherwise the
+/// model has no opinion (p = 1/2). The whole history is kept, so a match can
+/// reach back to anything the model saw - e.g. a reference it was trained on.
+pub struct MatchModel {
+    history: Vec<u8>,
+    /// Position + 1 of the byte that followed each hashed context, 0 for none
+    table: Vec<u32>,
+    confidence: [Counter; MAX_LEN + 1],
+    /// Where the byte the match predicts is in `history`
+    ptr: usize,
+    /// Bytes the match agreed on so far, 0 without a match
+    len: usize,
+    // bits of the current byte behind a leading 1
+    partial: u32,
+    table_bits: u8,
+}
+
+impl MatchModel {
+    pub fn new(table_bits: u8) -> Self {
+        assert!(
+            (1..=32).contains(&table_bits),
+            "MatchModel table bits must be 1..=32, got {}",
+            table_bits
+        );
+        Self {
+            history: Vec::new(),
+            table: vec![0; 1 << table_bits],
+            confidence: [Counter::new(); MAX_LEN + 1],
+            ptr: 0,
+            len: 0,
+            partial: 1,
+            table_bits,
+        }
+    }
+
+    /// The bit the match expects next and its length, if it has an opinion
+    fn expected(&self) -> Option<(u8, usize)> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.history[self.ptr];
+        let bits_seen = self.partial.ilog2();
+        let expected_prefix = (0x100 | u32::from(byte)) >> (8 - bits_seen);
+        if expected_prefix != self.partial {
+            return None;
+        }
+        let bit = (byte >> (7 - bits_seen)) & 1;
+        Some((bit, self.len.min(MAX_LEN)))
+    }
+
+    /// Table slot of the context in front of the end of the history
+    fn slot(&self) -> usize {
+        let ctx = &self.history[self.history.len() - MIN_LEN..];
+        let ctx = ctx
+            .iter()
+            .fold(0, |ctx, &byte| (ctx << 8) | u64::from(byte));
+        usize!(hash_u64(ctx) >> (u64::BITS - u32::from(self.table_bits)))
+    }
+
+    /// Follows the newest earlier occurrence of the current context, if its
+    /// bytes agree and not just its hash
+    fn find_match(&mut self, slot: usize) {
+        let Some(candidate) = usize!(self.table[slot]).checked_sub(1) else {
+            return;
+        };
+        let end = self.history.len();
+        let len = (1..=MAX_LEN.min(candidate))
+            .take_while(|&i| self.history[candidate - i] == self.history[end - i])
+            .count();
+        if len >= MIN_LEN {
+            self.ptr = candidate;
+            self.len = len;
+        }
+    }
+}
+
+impl AdaptiveModel for MatchModel {
+    fn predict(&self) -> u16 {
+        match self.expected() {
+            Some((1, len)) => self.confidence[len].p(),
+            Some((_, len)) => u16::MAX - self.confidence[len].p(),
+            None => HALF,
+        }
+    }
+
+    fn adapt(&mut self, bit: u8) {
+        if let Some((expected, len)) = self.expected() {
+            self.confidence[len].update(u8::from(bit == expected));
+        }
+    }
+
+    fn update(&mut self, bit: u8) {
+        self.partial = (self.partial << 1) | u32::from(bit);
+        if self.partial < 0x100 {
+            return;
+        }
+
+        let byte = u8!(self.partial & 0xff);
+        self.partial = 1;
+        if self.len > 0 && self.history[self.ptr] == byte {
+            self.ptr += 1;
+            self.len += 1;
+        } else {
+            self.len = 0;
+        }
+        self.history.push(byte);
+        if self.history.len() < MIN_LEN {
+            return;
+        }
+
+        let slot = self.slot();
+        if self.len == 0 {
+            self.find_match(slot);
+        }
+        // positions past 4GB aren't indexed, matches into them still continue
+        if let Ok(entry) = u32::try_from(self.history.len() + 1) {
+            self.table[slot] = entry;
+        }
+    }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        let memory = size_of_val(self.table.as_slice()) + self.history.capacity();
+        vec![("MatchModel", memory)]
+    }
+}
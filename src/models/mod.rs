@@ -1,23 +1,41 @@
 pub mod ac_hash;
 pub mod counter;
 pub mod frozen;
+pub mod matcher;
 pub mod order0;
 pub mod order1;
 pub mod ordern;
+pub mod ordern_bytes;
+pub mod ordern_codeword;
 pub mod ordern_entropy;
+pub mod ordern_states;
+pub mod run;
+pub mod state_map;
 
-pub use self::{counter::*, frozen::*, order0::*, order1::*, ordern::*, ordern_entropy::*};
+#[cfg(test)]
+mod model_tests;
+
+pub use self::{
+    counter::*, frozen::*, matcher::*, order0::*, order1::*, ordern::*, ordern_bytes::*,
+    ordern_codeword::*, ordern_entropy::*, ordern_states::*, run::*, state_map::*,
+};
 pub use crate::state_table::*;
 
+use crate::state::State;
+use std::io::{self, Read, Write};
+
 pub trait Model {
     fn predict(&self) -> u16;
     fn update(&mut self, bit: u8);
+    /// Bytes allocated by each (sub)model, by name
+    fn memory_usage(&self) -> Vec<(&'static str, usize)>;
 }
 
 pub trait AdaptiveModel {
     fn predict(&self) -> u16;
     fn update(&mut self, bit: u8);
     fn adapt(&mut self, bit: u8);
+    fn memory_usage(&self) -> Vec<(&'static str, usize)>;
 }
 
 impl<T: AdaptiveModel> Model for T {
@@ -29,13 +47,102 @@ impl<T: AdaptiveModel> Model for T {
         T::adapt(self, bit);
         T::update(self, bit);
     }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        T::memory_usage(self)
+    }
+}
+
+/// A model whose learned tables can be saved and loaded, e.g. to start coding
+/// from a model trained on similar data instead of from scratch
+pub trait Tables {
+    /// Writes what the model learned, not the context it's in
+    fn write_tables(&self, writer: &mut impl Write) -> io::Result<()>;
+    /// Replaces the tables with what `write_tables` wrote for a model of the
+    /// same size
+    fn read_tables(&mut self, reader: &mut impl Read) -> io::Result<()>;
 }
 
-// ------------- unused -------------
+/// Feeds `corpus` to `model` as its bytes' bits, MSB first
+pub fn train(model: &mut impl Model, corpus: &[u8]) {
+    for &byte in corpus {
+        for i in (0..8).rev() {
+            model.update((byte >> i) & 1);
+        }
+    }
+}
 
+/// Model of the history bits for entropy hashing (`ACHistory`)
+///
+/// A hash pass codes the history from the newest bit to the oldest: `align`
+/// starts it, then each bit gets a `predict` followed by a `step`.
 pub trait ACHashModel {
     fn predict(&mut self) -> u16;
+    /// Starts a pass, `alignment` is the number of bits seen of the current byte
     fn align(&mut self, alignment: u8);
+    /// Moves the pass past `bit`, to the next older bit
+    fn step(&mut self, bit: u8);
+    /// Learns a new bit of the stream, adaptive models override this
+    fn update(&mut self, _bit: u8) {}
+    /// Changes whenever the probabilities passes are coded with change, so
+    /// coded passes can only be reused within one epoch
+    fn epoch(&self) -> u64 {
+        0
+    }
+    /// Bytes of tables the model allocated
+    fn memory(&self) -> usize {
+        0
+    }
+}
+
+/// A model that may be left out, e.g. of a `BestOfTwoModel`: without one it
+/// has no opinion (p = 1/2) and no tables
+impl<M: Model> Model for Option<M> {
+    fn predict(&self) -> u16 {
+        self.as_ref().map_or(1 << 15, M::predict)
+    }
+
+    fn update(&mut self, bit: u8) {
+        if let Some(model) = self {
+            model.update(bit);
+        }
+    }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        self.as_ref().map_or_else(Vec::new, M::memory_usage)
+    }
+}
+
+impl<M: Tables> Tables for Option<M> {
+    fn write_tables(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            Some(model) => model.write_tables(writer),
+            None => Ok(()),
+        }
+    }
+
+    fn read_tables(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        match self {
+            Some(model) => model.read_tables(reader),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<M: State> State for Option<M> {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            Some(model) => model.write_state(writer),
+            None => Ok(()),
+        }
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        match self {
+            Some(model) => model.read_state(reader),
+            None => Ok(()),
+        }
+    }
 }
 
 use crate::mixers::opinion_mixer2::OpinionMixer2;
@@ -72,4 +179,43 @@ where
         self.m1.update(bit);
         self.m2.update(bit);
     }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        let mut usage = self.m1.memory_usage();
+        usage.extend(self.m2.memory_usage());
+        usage
+    }
+}
+
+// the opinion mixer has no weights to save
+impl<T, U> Tables for BestOfTwoModel<T, U>
+where
+    T: Model + Tables,
+    U: Model + Tables,
+{
+    fn write_tables(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.m1.write_tables(writer)?;
+        self.m2.write_tables(writer)
+    }
+
+    fn read_tables(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.m1.read_tables(reader)?;
+        self.m2.read_tables(reader)
+    }
+}
+
+impl<T, U> State for BestOfTwoModel<T, U>
+where
+    T: Model + State,
+    U: Model + State,
+{
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.m1.write_state(writer)?;
+        self.m2.write_state(writer)
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.m1.read_state(reader)?;
+        self.m2.read_state(reader)
+    }
 }
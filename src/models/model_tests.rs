@@ -0,0 +1,357 @@
+use super::*;
+use crate::{
+    corpus::{corpus, generate, Kind, Rng},
+    entropy_coding::{
+        arithmetic_coder::ArithmeticCoder,
+        io::{ACReader, ACWriter},
+    },
+    history::*,
+};
+
+const LEN: usize = 1 << 12;
+
+fn encode(input: &[u8], model: &mut impl Model) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    let mut writer = ACWriter::new(&mut compressed);
+    let mut ac = ArithmeticCoder::new_coder();
+    for &byte in input {
+        for bit in (0..8).rev().map(|i| (byte >> i) & 1) {
+            let p = model.predict();
+            model.update(bit);
+            ac.encode(bit, p, &mut writer).unwrap();
+        }
+    }
+    ac.flush(&mut writer).unwrap();
+    compressed
+}
+
+fn decode(compressed: &[u8], len: usize, model: &mut impl Model) -> Vec<u8> {
+    let mut reader = ACReader::new(compressed);
+    let mut ac = ArithmeticCoder::new_decoder(&mut reader).unwrap();
+    let mut decompressed = Vec::with_capacity(len);
+    for _ in 0..len {
+        let mut byte = 0;
+        for _ in 0..8 {
+            let p = model.predict();
+            let bit = ac.decode(p, &mut reader).unwrap();
+            model.update(bit);
+            byte = (byte << 1) | bit;
+        }
+        decompressed.push(byte);
+    }
+    decompressed
+}
+
+/// Random inputs of random lengths, spliced from different kinds so contexts
+/// see both long predictable stretches and noise
+fn random_inputs(count: usize, seed: u64) -> Vec<Vec<u8>> {
+    let mut rng = Rng::new(seed);
+    (0..count)
+        .map(|_| {
+            let len = rng.below(LEN);
+            let mut input = Vec::with_capacity(len);
+            while input.len() < len {
+                let kind = Kind::ALL[rng.below(Kind::ALL.len())];
+                let chunk_len = 1 + rng.below(len - input.len());
+                input.extend(generate(kind, chunk_len, rng.next_u64()));
+            }
+            input.truncate(len);
+            input
+        })
+        .collect()
+}
+
+/// Round trips the synthetic corpus and random inputs with fresh models from `init`
+fn round_trip<M: Model>(mut init: impl FnMut(&[u8]) -> M) {
+    let inputs = corpus(LEN, 0)
+        .into_iter()
+        .map(|(kind, input)| (kind.name().to_string(), input))
+        .chain(
+            random_inputs(8, 0)
+                .into_iter()
+                .enumerate()
+                .map(|(i, input)| (format!("random input #{}", i), input)),
+        );
+
+    for (name, input) in inputs {
+        let compressed = encode(&input, &mut init(&input));
+        let decompressed = decode(&compressed, input.len(), &mut init(&input));
+        assert_eq!(input, decompressed, "{}", name);
+    }
+}
+
+#[test]
+fn order0() {
+    round_trip(|_| Order0::new());
+}
+
+#[test]
+fn order1() {
+    round_trip(|_| Order1::new());
+}
+
+#[test]
+fn ordern() {
+    round_trip(|_| OrderN::new(16, 3));
+}
+
+#[test]
+fn frozen() {
+    round_trip(|_| FrozenModel::new(Order0::new()));
+}
+
+#[test]
+fn ordern_entropy_raw_history() {
+    round_trip(|_| OrderNEntropy::new(16, 3, RawHistory::new()));
+}
+
+#[test]
+fn ordern_entropy_ac_history() {
+    round_trip(|_| {
+        OrderNEntropy::new(
+            11,
+            3,
+            ACHistory::new(8, ac_hash::StationaryModel::for_book1()),
+        )
+    });
+}
+
+#[test]
+fn ordern_entropy_long_ac_history() {
+    round_trip(|_| {
+        let model = ac_hash::StationaryModel::for_book1();
+        OrderNEntropy::new(16, 3, ACHistory::with_buffer(13, model, 0u128))
+    });
+    round_trip(|_| {
+        let model = ac_hash::StationaryModel::for_book1();
+        let history = ACHistoryCached::with_buffer(13, model, 12, ByteRing::new(16));
+        OrderNEntropy::new(16, 3, history)
+    });
+}
+
+#[test]
+fn ordern_entropy_ac_history_cached() {
+    round_trip(|_| {
+        let model = ac_hash::StationaryModel::for_enwik7();
+        OrderNEntropy::new(16, 3, ACHistoryCached::new(13, model, 12))
+    });
+}
+
+#[test]
+fn ordern_entropy_adaptive_ac_history() {
+    round_trip(|_| OrderNEntropy::new(16, 3, ACHistory::new(13, ac_hash::AdaptiveOrder0::new())));
+    round_trip(|_| {
+        let model = ac_hash::AdaptiveOrder1::new();
+        OrderNEntropy::new(16, 3, ACHistoryCached::new(13, model, 12))
+    });
+}
+
+#[test]
+fn ordern_entropy_combined_history() {
+    round_trip(|_| {
+        let model = ac_hash::StationaryModel::for_book1();
+        let long = ACHistory::with_buffer(12, model, ByteRing::new(16));
+        OrderNEntropy::new(22, 3, Concat::new(RawHistory::new(), 7, long))
+    });
+    round_trip(|_| {
+        let sparse = Sparse::new(RawHistory::new(), 0b1101_1111);
+        OrderNEntropy::new(16, 3, Mix::new(sparse, Skip::new(RawHistory::new(), 2)))
+    });
+    round_trip(|_| {
+        OrderNEntropy::new(
+            16,
+            3,
+            Xor::new(RawHistory::new(), Skip::new(RawHistory::new(), 1)),
+        )
+    });
+}
+
+#[test]
+fn ordern_entropy_huff_history() {
+    round_trip(|buf| {
+        // the histogram is computed up front, both sides get the same buffer
+        if buf.is_empty() {
+            return OrderNEntropy::new(16, 0, HuffHistory::new(&[0], 12, 8));
+        }
+        OrderNEntropy::new(16, 0, HuffHistory::new(buf, 12, 8))
+    });
+}
+
+#[test]
+fn ordern_bytes() {
+    for order in [1, 2, 3, 6, 12] {
+        round_trip(|_| OrderNBytes::new(order, 16));
+    }
+}
+
+#[test]
+fn ordern_bytes_context() {
+    let feed = |model: &mut OrderNBytes, bytes: &[u8]| {
+        for &byte in bytes {
+            for bit in (0..8).rev().map(|i| (byte >> i) & 1) {
+                Model::update(model, bit);
+            }
+        }
+    };
+    // only the last `order` bytes matter, not what came before them
+    let mut a = OrderNBytes::new(3, 16);
+    let mut b = OrderNBytes::new(3, 16);
+    feed(&mut a, b"the cat sat");
+    feed(&mut b, b"the cat sat");
+    feed(&mut a, b"ABCDEFG xyz");
+    feed(&mut b, b"1234567 xyz");
+    assert_eq!(a.idx(), b.idx());
+
+    let mut model = OrderNBytes::new(2, 16);
+    feed(&mut model, &b"abc".repeat(64));
+    feed(&mut model, b"a");
+    // "ca" was always followed by 'b' = 0b0110_0010
+    for bit in [0, 1, 1, 0, 0, 0, 1, 0] {
+        let p = Model::predict(&model);
+        assert!(if bit == 1 { p > 60000 } else { p < 5000 }, "p = {}", p);
+        Model::update(&mut model, bit);
+    }
+}
+
+#[test]
+fn ordern_states() {
+    round_trip(|_| OrderNStates::new(1, 12, NaiveStateTable));
+    round_trip(|_| OrderNStates::new(3, 16, NaiveStateTable));
+}
+
+#[test]
+fn run_model() {
+    round_trip(|_| RunModel::new(1, 12));
+    round_trip(|_| RunModel::new(4, 16));
+}
+
+#[test]
+fn run_model_opinion() {
+    let mut model = RunModel::new(2, 12);
+    // a fresh context has no opinion
+    assert_eq!(Model::predict(&model), 1 << 15);
+
+    let feed = |model: &mut RunModel, bytes: &[u8]| {
+        for &byte in bytes {
+            for bit in (0..8).rev().map(|i| (byte >> i) & 1) {
+                Model::update(model, bit);
+            }
+        }
+    };
+    feed(&mut model, &b"xyz".repeat(64));
+    // "yz" was always followed by 'x' (0b0111_1000), expect a confident 0
+    assert!(Model::predict(&model) < 1 << 12);
+    feed(&mut model, b"x");
+    // "zx" is followed by 'y' (0b0111_1001), expect a confident 0 as well
+    assert!(Model::predict(&model) < 1 << 12);
+    // the first bit disagrees with 'y', no opinion for the rest of the byte
+    Model::update(&mut model, 1);
+    assert_eq!(Model::predict(&model), 1 << 15);
+}
+
+#[test]
+fn match_model() {
+    round_trip(|_| MatchModel::new(12));
+    round_trip(|_| MatchModel::new(20));
+    // matches into what the model was trained on
+    let reference = generate(Kind::Markov, LEN, 22);
+    round_trip(|_| {
+        let mut model = MatchModel::new(16);
+        train(&mut model, &reference);
+        model
+    });
+}
+
+#[test]
+fn match_model_reference() {
+    let reference = generate(Kind::Random, LEN, 23);
+    // an edited copy of the reference, noise to every other model
+    let mut input = reference[..LEN / 2].to_vec();
+    input.extend(b"an insertion");
+    input.extend(&reference[LEN / 2 + 100..]);
+
+    let cold = encode(&input, &mut MatchModel::new(16)).len();
+    let mut model = MatchModel::new(16);
+    train(&mut model, &reference);
+    let warm = encode(&input, &mut model);
+    assert!(warm.len() < cold / 10, "{} vs {} bytes", warm.len(), cold);
+
+    let mut model = MatchModel::new(16);
+    train(&mut model, &reference);
+    assert_eq!(decode(&warm, input.len(), &mut model), input);
+}
+
+#[test]
+fn ordern_entropy_adaptive_huff_history() {
+    // nothing computed up front, the decoder rebuilds the same codes
+    round_trip(|_| OrderNEntropy::new(16, 0, HuffHistory::adaptive(12, 8, 256)));
+    round_trip(|_| OrderNEntropy::new(16, 3, HuffHistory::adaptive(8, 8, 64)));
+}
+
+#[test]
+fn best_of_two() {
+    round_trip(|_| BestOfTwoModel::new(Order0::new(), Order1::new()));
+    round_trip(|_| {
+        let history = ACHistory::new(8, ac_hash::StationaryModel::for_book1());
+        BestOfTwoModel::new(OrderN::new(16, 3), OrderNEntropy::new(11, 3, history))
+    });
+    round_trip(|_| BestOfTwoModel::new(OrderN::new(16, 3), RunModel::new(3, 16)));
+    round_trip(|_| BestOfTwoModel::new(Order1::new(), Some(Order0::new())));
+    round_trip(|_| BestOfTwoModel::new(Order1::new(), None::<Order0>));
+}
+
+/// `model` trained on `dictionary`, as loaded from its saved tables
+fn primed<M: Model + Tables>(mut init: impl FnMut() -> M, dictionary: &[u8]) -> M {
+    let mut trained = init();
+    train(&mut trained, dictionary);
+    let mut tables = Vec::new();
+    trained.write_tables(&mut tables).unwrap();
+    let mut model = init();
+    model.read_tables(&mut tables.as_slice()).unwrap();
+    model
+}
+
+#[test]
+fn trained_tables() {
+    let dictionary = generate(Kind::Markov, LEN, 20);
+    let input = generate(Kind::Markov, LEN / 4, 21);
+    let order1 = || Order1::with_ctx_bits(16);
+    let ordern_entropy = || {
+        let history = ACHistory::new(13, ac_hash::StationaryModel::for_book1());
+        OrderNEntropy::new(16, 3, history)
+    };
+    round_trip(|_| primed(order1, &dictionary));
+    round_trip(|_| primed(ordern_entropy, &dictionary));
+    round_trip(|_| primed(|| RunModel::new(3, 12), &dictionary));
+    round_trip(|_| {
+        primed(
+            || BestOfTwoModel::new(Order0::new(), OrderN::new(16, 3)),
+            &dictionary,
+        )
+    });
+
+    // a short input similar to the dictionary gains the most
+    let cold = encode(&input, &mut order1()).len();
+    let warm = encode(&input, &mut primed(order1, &dictionary)).len();
+    assert!(warm < cold * 9 / 10, "{} vs {} bytes", warm, cold);
+
+    // tables of a model of another size don't fit
+    let mut tables = Vec::new();
+    Order1::with_ctx_bits(8).write_tables(&mut tables).unwrap();
+    assert!(order1().read_tables(&mut tables.as_slice()).is_err());
+}
+
+#[test]
+fn frozen_trained() {
+    let dictionary = generate(Kind::Markov, LEN, 22);
+    let tables = |model: &FrozenModel<Order1>| {
+        let mut tables = Vec::new();
+        model.write_tables(&mut tables).unwrap();
+        tables
+    };
+    let mut model = FrozenModel::trained(Order1::new(), &dictionary);
+    let before = tables(&model);
+    encode(&generate(Kind::Markov, LEN, 23), &mut model);
+    assert_eq!(tables(&model), before);
+    round_trip(|_| FrozenModel::trained(Order1::new(), &dictionary));
+}
@@ -1,4 +1,10 @@
-use super::{counter::Counter, AdaptiveModel};
+use super::{
+    counter::{read_counters, write_counters, Counter},
+    AdaptiveModel, Tables,
+};
+use crate::state::{invalid_state, read_bytes, State};
+use std::io::{self, Read, Write};
+use std::mem::size_of_val;
 
 pub struct Order0 {
     stats: [Counter; 1 << 11],
@@ -18,6 +24,12 @@ impl Order0 {
     }
 }
 
+impl Default for Order0 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AdaptiveModel for Order0 {
     fn predict(&self) -> u16 {
         self.stats[usize::from(self.ctx)].p()
@@ -32,4 +44,37 @@ impl AdaptiveModel for Order0 {
         self.alignment = (self.alignment + 1) % 8;
         self.ctx = u16::from(self.alignment) << 8 | u16::from(self.history);
     }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        vec![("Order0", size_of_val(&self.stats))]
+    }
+}
+
+impl Tables for Order0 {
+    fn write_tables(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_counters(writer, &self.stats)
+    }
+
+    fn read_tables(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        read_counters(reader, &mut self.stats)
+    }
+}
+
+impl State for Order0 {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.write_tables(writer)?;
+        writer.write_all(&[self.history, self.alignment])?;
+        writer.write_all(&self.ctx.to_be_bytes())
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.read_tables(reader)?;
+        let [history, alignment] = read_bytes(reader)?;
+        let ctx = u16::from_be_bytes(read_bytes(reader)?);
+        if alignment >= 8 || usize::from(ctx) >= self.stats.len() {
+            return Err(invalid_state("Order0"));
+        }
+        (self.history, self.alignment, self.ctx) = (history, alignment, ctx);
+        Ok(())
+    }
 }
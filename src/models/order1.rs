@@ -1,24 +1,58 @@
-use super::{counter::Counter, AdaptiveModel};
-use crate::usize;
+use super::{
+    counter::{read_counters, write_counters, Counter},
+    AdaptiveModel, Tables,
+};
+use crate::state::{invalid_state, read_bytes, State};
+use crate::{helpers::table_bits, usize};
+use std::io::{self, Read, Write};
+use std::mem::{size_of, size_of_val};
 
 pub struct Order1 {
     stats: Vec<Counter>,
     history: u16,
     alignment: u8,
     ctx: u32,
+    mask: u32,
 }
 
 impl Order1 {
+    pub const MAX_CTX_BITS: u8 = 19; // 16 bits of history + 3 bits alignment
+
     pub fn new() -> Self {
+        Self::with_ctx_bits(Self::MAX_CTX_BITS)
+    }
+
+    /// Sizes the table to fit `memory` bytes, dropping the oldest history bits
+    /// from the context when it doesn't fit a full order-1 table
+    pub fn with_memory(memory: usize) -> Self {
+        let ctx_bits = table_bits(memory, size_of::<Counter>()).clamp(3, Self::MAX_CTX_BITS);
+        Self::with_ctx_bits(ctx_bits)
+    }
+
+    /// Contexts of the last `ctx_bits - 3` bits (at most `MAX_CTX_BITS`)
+    pub fn with_ctx_bits(ctx_bits: u8) -> Self {
+        assert!(
+            (3..=Self::MAX_CTX_BITS).contains(&ctx_bits),
+            "Order1 takes 3..={} context bits, got {}",
+            Self::MAX_CTX_BITS,
+            ctx_bits
+        );
         Self {
-            stats: vec![Counter::new(); 1 << 19],
+            stats: vec![Counter::new(); 1 << ctx_bits],
             history: 0,
             alignment: 0,
             ctx: 0,
+            mask: (1 << ctx_bits) - 1,
         }
     }
 }
 
+impl Default for Order1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AdaptiveModel for Order1 {
     fn predict(&self) -> u16 {
         self.stats[usize!(self.ctx)].p()
@@ -31,6 +65,41 @@ impl AdaptiveModel for Order1 {
     fn update(&mut self, bit: u8) {
         self.history = (self.history << 1) | u16::from(bit);
         self.alignment = (self.alignment + 1) % 8;
-        self.ctx = u32::from(self.alignment) << 16 | u32::from(self.history);
+        self.ctx = (u32::from(self.history) << 3 | u32::from(self.alignment)) & self.mask;
+    }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        vec![("Order1", size_of_val(self.stats.as_slice()))]
+    }
+}
+
+impl Tables for Order1 {
+    fn write_tables(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_counters(writer, &self.stats)
+    }
+
+    fn read_tables(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        read_counters(reader, &mut self.stats)
+    }
+}
+
+impl State for Order1 {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.write_tables(writer)?;
+        writer.write_all(&self.history.to_be_bytes())?;
+        writer.write_all(&[self.alignment])?;
+        writer.write_all(&self.ctx.to_be_bytes())
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.read_tables(reader)?;
+        let history = u16::from_be_bytes(read_bytes(reader)?);
+        let [alignment] = read_bytes(reader)?;
+        let ctx = u32::from_be_bytes(read_bytes(reader)?);
+        if alignment >= 8 || ctx > self.mask {
+            return Err(invalid_state("Order1"));
+        }
+        (self.history, self.alignment, self.ctx) = (history, alignment, ctx);
+        Ok(())
     }
 }
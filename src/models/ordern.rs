@@ -1,5 +1,11 @@
-use super::{counter::Counter, AdaptiveModel};
+use super::{
+    counter::{read_counters, write_counters, Counter},
+    AdaptiveModel, Tables,
+};
+use crate::state::{invalid_state, read_bytes, State};
 use crate::usize;
+use std::io::{self, Read, Write};
+use std::mem::size_of_val;
 
 pub struct OrderN {
     stats: Vec<Counter>,
@@ -41,4 +47,39 @@ impl AdaptiveModel for OrderN {
         self.alignment = (self.alignment + 1) & alignment_mask;
         self.ctx = (self.history << self.alignment_bits) | u32::from(self.alignment)
     }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        vec![("OrderN", size_of_val(self.stats.as_slice()))]
+    }
+}
+
+impl Tables for OrderN {
+    fn write_tables(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_counters(writer, &self.stats)
+    }
+
+    fn read_tables(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        read_counters(reader, &mut self.stats)
+    }
+}
+
+impl State for OrderN {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.write_tables(writer)?;
+        writer.write_all(&self.ctx.to_be_bytes())?;
+        writer.write_all(&self.history.to_be_bytes())?;
+        writer.write_all(&[self.alignment])
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.read_tables(reader)?;
+        let ctx = u32::from_be_bytes(read_bytes(reader)?);
+        let history = u32::from_be_bytes(read_bytes(reader)?);
+        let [alignment] = read_bytes(reader)?;
+        if usize!(ctx) >= self.stats.len() || alignment >> self.alignment_bits != 0 {
+            return Err(invalid_state("OrderN"));
+        }
+        (self.ctx, self.history, self.alignment) = (ctx, history, alignment);
+        Ok(())
+    }
 }
@@ -0,0 +1,135 @@
+use super::{
+    counter::{read_counters, write_counters, Counter},
+    AdaptiveModel, Tables,
+};
+use crate::state::{invalid_state, read_bytes, State};
+use crate::{helpers::hash_u64, u16, u64, u8, usize};
+use std::io::{self, Read, Write};
+use std::mem::size_of_val;
+
+// odd, so the rolling hash is a bijection of its last byte
+const ROLL_BASE: u64 = 0x100000001b3;
+
+/// Order-N model over whole bytes
+///
+/// The context is a hash of the last `order` bytes, computed once per byte,
+/// combined with the bits seen so far of the current byte. Unlike `OrderN`
+/// contexts never straddle byte boundaries, so orders 1..6 give the usual
+/// text statistics. Any order works, older bytes leave the rolling hash.
+pub struct OrderNBytes {
+    stats: Vec<Counter>,
+    window: Vec<u8>,
+    pos: usize,
+    // polynomial hash of `window`
+    rolling: u64,
+    // B^order, to take the oldest byte out of `rolling`
+    oldest_weight: u64,
+    base: usize,
+    // bits of the current byte behind a leading 1
+    partial: usize,
+    mask: usize,
+}
+
+impl OrderNBytes {
+    pub fn new(order: usize, table_bits: u8) -> Self {
+        assert!(order > 0, "OrderNBytes needs at least one byte of context");
+        assert!(
+            table_bits >= 8,
+            "OrderNBytes needs a table of at least 2^8 counters, got 2^{}",
+            table_bits
+        );
+        let oldest_weight = (0..order).fold(1u64, |acc, _| acc.wrapping_mul(ROLL_BASE));
+        let mut model = Self {
+            stats: vec![Counter::new(); 1 << table_bits],
+            window: vec![0; order],
+            pos: 0,
+            rolling: 0,
+            oldest_weight,
+            base: 0,
+            partial: 1,
+            mask: (1 << table_bits) - 1,
+        };
+        model.select_base();
+        model
+    }
+
+    fn select_base(&mut self) {
+        let order = self.window.len() as u64;
+        let hash = hash_u64(self.rolling ^ (order << 56));
+        self.base = usize!(hash >> 32);
+    }
+
+    pub(crate) fn idx(&self) -> usize {
+        // the partial byte only flips the low 8 bits, its 255 contexts stay
+        // within a few cache lines
+        (self.base ^ self.partial) & self.mask
+    }
+}
+
+impl AdaptiveModel for OrderNBytes {
+    fn predict(&self) -> u16 {
+        self.stats[self.idx()].p()
+    }
+
+    fn adapt(&mut self, bit: u8) {
+        let idx = self.idx();
+        self.stats[idx].update(bit);
+    }
+
+    fn update(&mut self, bit: u8) {
+        self.partial = (self.partial << 1) | usize::from(bit);
+        if self.partial < 0x100 {
+            return;
+        }
+
+        let byte = u8!(self.partial & 0xff);
+        let oldest = u64::from(self.window[self.pos]);
+        self.rolling = self
+            .rolling
+            .wrapping_mul(ROLL_BASE)
+            .wrapping_add(u64::from(byte))
+            .wrapping_sub(oldest.wrapping_mul(self.oldest_weight));
+        self.window[self.pos] = byte;
+        self.pos = (self.pos + 1) % self.window.len();
+        self.partial = 1;
+        self.select_base();
+    }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        vec![("OrderNBytes", size_of_val(self.stats.as_slice()))]
+    }
+}
+
+impl Tables for OrderNBytes {
+    fn write_tables(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_counters(writer, &self.stats)
+    }
+
+    fn read_tables(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        read_counters(reader, &mut self.stats)
+    }
+}
+
+impl State for OrderNBytes {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.write_tables(writer)?;
+        writer.write_all(&self.window)?;
+        writer.write_all(&u64!(self.pos).to_be_bytes())?;
+        writer.write_all(&self.rolling.to_be_bytes())?;
+        writer.write_all(&u16!(self.partial).to_be_bytes())
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.read_tables(reader)?;
+        reader.read_exact(&mut self.window)?;
+        let pos = u64::from_be_bytes(read_bytes(reader)?);
+        let rolling = u64::from_be_bytes(read_bytes(reader)?);
+        let partial = usize::from(u16::from_be_bytes(read_bytes(reader)?));
+        if pos >= self.window.len() as u64 || !(1..0x100).contains(&partial) {
+            return Err(invalid_state("OrderNBytes"));
+        }
+        (self.pos, self.rolling, self.partial) = (usize!(pos), rolling, partial);
+        self.select_base();
+        Ok(())
+    }
+}
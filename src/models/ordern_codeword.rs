@@ -0,0 +1,73 @@
+use super::{
+    counter::{read_counters, write_counters, Counter},
+    AdaptiveModel, Tables,
+};
+use crate::entropy_coding::huffman::{CanonicalCode, TreePos};
+use crate::history::History;
+use crate::usize;
+use std::io::{self, Read, Write};
+use std::mem::size_of_val;
+
+/// Enough to tell apart every position of a `CanonicalCode::MAX_LEN` codeword
+pub const CODEWORD_ALIGNMENT_BITS: u8 = 4;
+
+/// `OrderNEntropy` for bytes coded as Huffman codewords
+///
+/// The alignment is the position within the current codeword instead of a bit
+/// counter, so the model walks the code tree alongside the coder.
+pub struct OrderNCodeword<H: History> {
+    stats: Vec<Counter>,
+    ctx: u32,
+    history: H,
+    code: CanonicalCode,
+    pos: TreePos,
+    bits_in_context: u8,
+}
+
+impl<H: History> OrderNCodeword<H> {
+    pub fn new(bits_in_context: u8, code: CanonicalCode, history: H) -> Self {
+        assert!(bits_in_context > CODEWORD_ALIGNMENT_BITS);
+        Self {
+            stats: vec![Counter::new(); 1 << bits_in_context],
+            ctx: 0,
+            history,
+            code,
+            pos: TreePos::default(),
+            bits_in_context,
+        }
+    }
+}
+
+impl<H: History> AdaptiveModel for OrderNCodeword<H> {
+    fn predict(&self) -> u16 {
+        self.stats[usize!(self.ctx)].p()
+    }
+
+    fn adapt(&mut self, bit: u8) {
+        self.stats[usize!(self.ctx)].update(bit);
+    }
+
+    fn update(&mut self, bit: u8) {
+        let mask_bits = self.bits_in_context - CODEWORD_ALIGNMENT_BITS;
+        let mask = (1 << mask_bits) - 1;
+
+        self.history.update(bit);
+        self.code.step(&mut self.pos, bit);
+        let hash = self.history.hash() & mask;
+        self.ctx = (hash << CODEWORD_ALIGNMENT_BITS) | u32::from(self.pos.depth());
+    }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        vec![("OrderNCodeword", size_of_val(self.stats.as_slice()))]
+    }
+}
+
+impl<H: History> Tables for OrderNCodeword<H> {
+    fn write_tables(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_counters(writer, &self.stats)
+    }
+
+    fn read_tables(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        read_counters(reader, &mut self.stats)
+    }
+}
@@ -1,6 +1,12 @@
-use super::{counter::Counter, AdaptiveModel};
+use super::{
+    counter::{read_counters, write_counters, Counter},
+    AdaptiveModel, Tables,
+};
 use crate::history::History;
+use crate::state::{invalid_state, read_bytes, State};
 use crate::usize;
+use std::io::{self, Read, Write};
+use std::mem::size_of_val;
 
 pub struct OrderNEntropy<H: History> {
     stats: Vec<Counter>,
@@ -22,6 +28,10 @@ impl<H: History> OrderNEntropy<H> {
             alignment_bits,
         }
     }
+
+    pub fn history(&self) -> &H {
+        &self.history
+    }
 }
 
 impl<H: History> AdaptiveModel for OrderNEntropy<H> {
@@ -43,4 +53,39 @@ impl<H: History> AdaptiveModel for OrderNEntropy<H> {
         let hash = self.history.hash() & mask;
         self.ctx = (hash << self.alignment_bits) | u32::from(self.alignment);
     }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        let memory = size_of_val(self.stats.as_slice()) + self.history.memory();
+        vec![("OrderNEntropy", memory)]
+    }
+}
+
+impl<H: History> Tables for OrderNEntropy<H> {
+    fn write_tables(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_counters(writer, &self.stats)
+    }
+
+    fn read_tables(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        read_counters(reader, &mut self.stats)
+    }
+}
+
+impl<H: History + State> State for OrderNEntropy<H> {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.write_tables(writer)?;
+        writer.write_all(&self.ctx.to_be_bytes())?;
+        writer.write_all(&[self.alignment])?;
+        self.history.write_state(writer)
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.read_tables(reader)?;
+        let ctx = u32::from_be_bytes(read_bytes(reader)?);
+        let [alignment] = read_bytes(reader)?;
+        if usize!(ctx) >= self.stats.len() || alignment >> self.alignment_bits != 0 {
+            return Err(invalid_state("OrderNEntropy"));
+        }
+        (self.ctx, self.alignment) = (ctx, alignment);
+        self.history.read_state(reader)
+    }
 }
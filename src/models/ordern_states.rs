@@ -0,0 +1,138 @@
+use super::{AdaptiveModel, StateMap, StateTable, Tables};
+use crate::state::{invalid_state, read_bytes, State};
+use crate::{helpers::hash_u64, u16, u8, usize};
+use std::io::{self, Read, Write};
+use std::mem::{size_of, size_of_val};
+
+/// Order-N model over bit-history states instead of counters
+///
+/// The context is a hash of the last `order` bytes combined with the bits
+/// seen so far of the current byte, as in `OrderNBytes`. Every context holds
+/// a state of the `StateTable`, and a `StateMap` shared by all contexts learns
+/// what each state predicts - a rarely seen context predicts what the same
+/// history did in all the others.
+pub struct OrderNStates<T: StateTable> {
+    states: Vec<u16>,
+    map: StateMap,
+    table: T,
+    history: u64,
+    // bits of the current byte behind a leading 1
+    partial: usize,
+    base: usize,
+    mask: usize,
+    order: u8,
+}
+
+impl<T: StateTable> OrderNStates<T> {
+    pub fn new(order: u8, table_bits: u8, table: T) -> Self {
+        assert!(
+            (1..=8).contains(&order),
+            "OrderNStates order must be 1..=8, got {}",
+            order
+        );
+        assert!(
+            (8..=32).contains(&table_bits),
+            "OrderNStates takes tables of 2^8..=2^32 states, got 2^{}",
+            table_bits
+        );
+        let mut model = Self {
+            states: vec![0; 1 << table_bits],
+            map: StateMap::new(&table),
+            table,
+            history: 0,
+            partial: 1,
+            base: 0,
+            mask: (1 << table_bits) - 1,
+            order,
+        };
+        model.select_base();
+        model
+    }
+
+    /// Bytes `new` allocates for a table of `2^table_bits` contexts
+    pub fn memory(table_bits: u8, table: &T) -> usize {
+        (1 << table_bits) * size_of::<u16>() + StateMap::memory_for(table, 1)
+    }
+
+    fn select_base(&mut self) {
+        let bits = u32::from(self.order) * 8;
+        let ctx = self.history & (u64::MAX >> (u64::BITS - bits));
+        let hash = hash_u64(ctx ^ (u64::from(self.order) << 56));
+        self.base = usize!(hash >> 32);
+    }
+
+    fn idx(&self) -> usize {
+        (self.base ^ self.partial) & self.mask
+    }
+}
+
+impl<T: StateTable> AdaptiveModel for OrderNStates<T> {
+    fn predict(&self) -> u16 {
+        self.map.p(0, self.states[self.idx()])
+    }
+
+    fn adapt(&mut self, bit: u8) {
+        let idx = self.idx();
+        let state = self.states[idx];
+        self.map.update(0, state, bit);
+        self.states[idx] = self.table.next(state, bit);
+    }
+
+    fn update(&mut self, bit: u8) {
+        self.partial = (self.partial << 1) | usize::from(bit);
+        if self.partial < 0x100 {
+            return;
+        }
+
+        self.history = (self.history << 8) | u64::from(u8!(self.partial & 0xff));
+        self.partial = 1;
+        self.select_base();
+    }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        vec![(
+            "OrderNStates",
+            size_of_val(self.states.as_slice()) + self.map.memory(),
+        )]
+    }
+}
+
+impl<T: StateTable> Tables for OrderNStates<T> {
+    fn write_tables(&self, writer: &mut impl Write) -> io::Result<()> {
+        let bytes: Vec<u8> = self.states.iter().flat_map(|s| s.to_be_bytes()).collect();
+        writer.write_all(&bytes)?;
+        self.map.write_state(writer)
+    }
+
+    fn read_tables(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        let mut bytes = vec![0; self.states.len() * size_of::<u16>()];
+        reader.read_exact(&mut bytes)?;
+        for (state, bytes) in self.states.iter_mut().zip(bytes.chunks_exact(2)) {
+            *state = u16::from_be_bytes([bytes[0], bytes[1]]);
+            if usize::from(*state) >= self.table.size() {
+                return Err(invalid_state("OrderNStates"));
+            }
+        }
+        self.map.read_state(reader)
+    }
+}
+
+impl<T: StateTable> State for OrderNStates<T> {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.write_tables(writer)?;
+        writer.write_all(&self.history.to_be_bytes())?;
+        writer.write_all(&u16!(self.partial).to_be_bytes())
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.read_tables(reader)?;
+        let history = u64::from_be_bytes(read_bytes(reader)?);
+        let partial = usize::from(u16::from_be_bytes(read_bytes(reader)?));
+        if !(1..0x100).contains(&partial) {
+            return Err(invalid_state("OrderNStates"));
+        }
+        (self.history, self.partial) = (history, partial);
+        self.select_base();
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use super::{
+    counter::{read_counters, write_counters, Counter},
+    AdaptiveModel, Tables,
+};
+use crate::state::{invalid_state, read_bytes, State};
+use crate::{helpers::hash_u64, u16, u8, usize};
+use std::io::{self, Read, Write};
+use std::mem::{size_of, size_of_val};
+
+const HALF: u16 = 1 << 15;
+// runs longer than this share a confidence counter
+const MAX_RUN: usize = 15;
+
+#[derive(Clone, Copy, Default)]
+struct RunSlot {
+    check: u8,
+    byte: u8,
+    count: u8,
+}
+
+/// Predicts that a byte context is followed by the same byte as last time
+///
+/// Every hashed context of the last `order` bytes remembers the byte that
+/// followed it and how many times in a row it did. While the current byte
+/// still agrees with that byte, its next bit is predicted with a confidence
+/// learned per run length, otherwise the model has no opinion (p = 1/2).
+pub struct RunModel {
+    table: Vec<RunSlot>,
+    confidence: [Counter; MAX_RUN + 1],
+    history: u64,
+    // bits of the current byte behind a leading 1
+    partial: u32,
+    idx: usize,
+    check: u8,
+    order: u8,
+    table_bits: u8,
+}
+
+impl RunModel {
+    pub fn new(order: u8, table_bits: u8) -> Self {
+        assert!(
+            (1..=8).contains(&order),
+            "RunModel order must be 1..=8, got {}",
+            order
+        );
+        let mut model = Self {
+            table: vec![RunSlot::default(); 1 << table_bits],
+            confidence: [Counter::new(); MAX_RUN + 1],
+            history: 0,
+            partial: 1,
+            idx: 0,
+            check: 0,
+            order,
+            table_bits,
+        };
+        model.select_slot();
+        model
+    }
+
+    /// Bytes of a context in the table
+    pub const SLOT_SIZE: usize = size_of::<RunSlot>();
+
+    /// The bit the context expects next and its run length, if it has an opinion
+    fn expected(&self) -> Option<(u8, usize)> {
+        let slot = self.table[self.idx];
+        let bits_seen = self.partial.ilog2();
+        let expected_prefix = (0x100 | u32::from(slot.byte)) >> (8 - bits_seen);
+        if slot.count == 0 || slot.check != self.check || expected_prefix != self.partial {
+            return None;
+        }
+        let bit = (slot.byte >> (7 - bits_seen)) & 1;
+        Some((bit, usize::from(slot.count).min(MAX_RUN)))
+    }
+
+    fn select_slot(&mut self) {
+        let bits = u32::from(self.order) * 8;
+        let ctx = self.history & (u64::MAX >> (u64::BITS - bits));
+        let hash = hash_u64(ctx ^ (u64::from(self.order) << 56));
+        self.idx = usize!(hash >> (u64::BITS - u32::from(self.table_bits)));
+        self.check = u8!(hash & 0xff);
+    }
+}
+
+impl AdaptiveModel for RunModel {
+    fn predict(&self) -> u16 {
+        match self.expected() {
+            Some((1, run)) => self.confidence[run].p(),
+            Some((_, run)) => u16::MAX - self.confidence[run].p(),
+            None => HALF,
+        }
+    }
+
+    fn adapt(&mut self, bit: u8) {
+        if let Some((expected, run)) = self.expected() {
+            self.confidence[run].update(u8::from(bit == expected));
+        }
+    }
+
+    fn update(&mut self, bit: u8) {
+        self.partial = (self.partial << 1) | u32::from(bit);
+        if self.partial < 0x100 {
+            return;
+        }
+
+        let byte = u8!(self.partial & 0xff);
+        let slot = &mut self.table[self.idx];
+        if slot.check == self.check && slot.byte == byte && slot.count > 0 {
+            slot.count = slot.count.saturating_add(1);
+        } else {
+            *slot = RunSlot { check: self.check, byte, count: 1 };
+        }
+
+        self.history = (self.history << 8) | u64::from(byte);
+        self.partial = 1;
+        self.select_slot();
+    }
+
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        vec![("RunModel", size_of_val(self.table.as_slice()))]
+    }
+}
+
+impl Tables for RunModel {
+    fn write_tables(&self, writer: &mut impl Write) -> io::Result<()> {
+        let slots: Vec<u8> = self
+            .table
+            .iter()
+            .flat_map(|slot| [slot.check, slot.byte, slot.count])
+            .collect();
+        writer.write_all(&slots)?;
+        write_counters(writer, &self.confidence)
+    }
+
+    fn read_tables(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        let mut slots = vec![0; self.table.len() * 3];
+        reader.read_exact(&mut slots)?;
+        for (slot, bytes) in self.table.iter_mut().zip(slots.chunks_exact(3)) {
+            *slot = RunSlot { check: bytes[0], byte: bytes[1], count: bytes[2] };
+        }
+        read_counters(reader, &mut self.confidence)
+    }
+}
+
+impl State for RunModel {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.write_tables(writer)?;
+        writer.write_all(&self.history.to_be_bytes())?;
+        writer.write_all(&u16!(self.partial).to_be_bytes())
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.read_tables(reader)?;
+        let history = u64::from_be_bytes(read_bytes(reader)?);
+        let partial = u32::from(u16::from_be_bytes(read_bytes(reader)?));
+        if !(1..0x100).contains(&partial) {
+            return Err(invalid_state("RunModel"));
+        }
+        (self.history, self.partial) = (history, partial);
+        self.select_slot();
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use crate::state::{invalid_state, read_bytes, State};
+use crate::state_table::StateTable;
+use std::io::{self, Read, Write};
+use std::mem::{size_of, size_of_val};
+
+/// Learns the probability of a 1 for every bit-history state online
+///
+/// Starts from the fixed probabilities of a `StateTable` and moves towards what
+/// the coded bits actually were. States can optionally be split by a slot
+/// `kind` (e.g. the bit position within a nibble), each kind learns separately.
+#[derive(Clone)]
+pub struct StateMap {
+    probs: Vec<u16>,
+    counts: Vec<u8>,
+    states: usize,
+    limit: u8,
+}
+
+impl StateMap {
+    /// Adaptation slows down until 1/(limit + 2), then stays there
+    pub const DEFAULT_LIMIT: u8 = 127;
+
+    pub fn new(table: &impl StateTable) -> Self {
+        Self::with_kinds(table, 1)
+    }
+
+    pub fn with_kinds(table: &impl StateTable, kinds: usize) -> Self {
+        let states = table.size();
+        let probs: Vec<u16> = (0..states)
+            .map(|state| table.p(u16::try_from(state).unwrap()))
+            .collect();
+        Self {
+            probs: probs.repeat(kinds),
+            counts: vec![0; states * kinds],
+            states,
+            limit: Self::DEFAULT_LIMIT,
+        }
+    }
+
+    pub fn limit(mut self, limit: u8) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn p(&self, kind: usize, state: u16) -> u16 {
+        self.probs[self.idx(kind, state)]
+    }
+
+    pub fn update(&mut self, kind: usize, state: u16, bit: u8) {
+        let idx = self.idx(kind, state);
+        let n = self.counts[idx];
+        let p = i32::from(self.probs[idx]);
+        let target = if bit == 1 { i32::from(u16::MAX) } else { 0 };
+        let p = p + (target - p) / (i32::from(n) + 2);
+        self.probs[idx] = u16::try_from(p).unwrap();
+        if n < self.limit {
+            self.counts[idx] = n + 1;
+        }
+    }
+
+    pub fn memory(&self) -> usize {
+        size_of_val(self.probs.as_slice()) + size_of_val(self.counts.as_slice())
+    }
+
+    /// Bytes `with_kinds` allocates, known without allocating them
+    pub fn memory_for(table: &impl StateTable, kinds: usize) -> usize {
+        table.size() * kinds * (size_of::<u16>() + size_of::<u8>())
+    }
+
+    fn idx(&self, kind: usize, state: u16) -> usize {
+        debug_assert!(usize::from(state) < self.states);
+        kind * self.states + usize::from(state)
+    }
+}
+
+impl State for StateMap {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        let probs: Vec<u8> = self.probs.iter().flat_map(|p| p.to_be_bytes()).collect();
+        writer.write_all(&probs)?;
+        writer.write_all(&self.counts)?;
+        writer.write_all(&[self.limit])
+    }
+
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        let mut probs = vec![0; self.probs.len() * size_of::<u16>()];
+        reader.read_exact(&mut probs)?;
+        let mut counts = vec![0; self.counts.len()];
+        reader.read_exact(&mut counts)?;
+        let [limit] = read_bytes(reader)?;
+        if limit != self.limit || counts.iter().any(|&n| n > limit) {
+            return Err(invalid_state("StateMap"));
+        }
+        for (p, bytes) in self.probs.iter_mut().zip(probs.chunks_exact(2)) {
+            *p = u16::from_be_bytes([bytes[0], bytes[1]]);
+        }
+        self.counts = counts;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{corpus::Rng, state_table::NaiveStateTable};
+
+    #[test]
+    fn starts_from_table() {
+        let map = StateMap::with_kinds(&NaiveStateTable, 4);
+        for kind in 0..4 {
+            for state in [0, 1, 100, 3962] {
+                assert_eq!(map.p(kind, state), NaiveStateTable.p(state));
+            }
+        }
+    }
+
+    #[test]
+    fn learns_frequency() {
+        let mut map = StateMap::new(&NaiveStateTable);
+        let mut rng = Rng::new(0);
+        // state 0 says 50%, the data says 90%
+        for _ in 0..10_000 {
+            let bit = u8::from(rng.below(10) != 0);
+            map.update(0, 0, bit);
+        }
+        let p = f64::from(map.p(0, 0)) / 65536.0;
+        assert!((p - 0.9).abs() < 0.05, "p = {}", p);
+    }
+
+    #[test]
+    fn kinds_are_independent() {
+        let mut map = StateMap::with_kinds(&NaiveStateTable, 2);
+        for _ in 0..100 {
+            map.update(1, 5, 1);
+        }
+        assert_eq!(map.p(0, 5), NaiveStateTable.p(5));
+        assert!(map.p(1, 5) > 60000);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut map = StateMap::with_kinds(&NaiveStateTable, 2);
+        for i in 0..100 {
+            map.update(i % 2, 5, 1);
+        }
+        let mut state = Vec::new();
+        map.write_state(&mut state).unwrap();
+        let mut restored = StateMap::with_kinds(&NaiveStateTable, 2);
+        restored.read_state(&mut state.as_slice()).unwrap();
+        assert_eq!(restored.probs, map.probs);
+        assert_eq!(restored.counts, map.counts);
+
+        let mut other = StateMap::with_kinds(&NaiveStateTable, 2).limit(10);
+        assert!(other.read_state(&mut state.as_slice()).is_err());
+    }
+
+    #[test]
+    fn stays_in_range() {
+        let mut map = StateMap::new(&NaiveStateTable).limit(0);
+        for _ in 0..1000 {
+            map.update(0, 7, 1);
+        }
+        assert!(map.p(0, 7) > u16::MAX - 16);
+        for _ in 0..1000 {
+            map.update(0, 7, 0);
+        }
+        assert!(map.p(0, 7) < 16);
+    }
+}
@@ -0,0 +1,25 @@
+//! Saving and restoring the complete state of coding components, so a long
+//! compression can continue from a checkpoint exactly where it left off
+
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+/// A component whose state can be written out and read back into a component
+/// built with the same parameters, which then behaves exactly like the original
+pub trait State {
+    fn write_state(&self, writer: &mut impl Write) -> io::Result<()>;
+    /// Replaces the state with what `write_state` wrote, rejecting states the
+    /// component can't be in
+    fn read_state(&mut self, reader: &mut impl Read) -> io::Result<()>;
+}
+
+/// Reads `N` bytes of a state
+pub fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// The error for a state `what` can't be in
+pub fn invalid_state(what: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Invalid {} state", what))
+}
@@ -0,0 +1,208 @@
+use super::{naive::MAX_LEVEL, RuntimeStateTable, StateEntry, StateTable};
+
+/// Parameters of count based state tables, the defaults build `NaiveStateTable`
+///
+/// A state at level `l` has seen `l - 1` bits, `node` of which were ones.
+/// Once `max_level` is reached, the counts are divided by `discount` - the next
+/// state keeps the ratio of ones but forgets old bits, so it can adapt again.
+#[derive(Clone, Debug)]
+pub struct StateTableParams {
+    pub max_level: usize,
+    pub discount: usize,
+}
+
+impl Default for StateTableParams {
+    fn default() -> Self {
+        Self { max_level: MAX_LEVEL, discount: 2 }
+    }
+}
+
+impl StateTableParams {
+    /// Max level that still fits all states in 12 bits
+    pub const MAX_LEVEL: usize = MAX_LEVEL;
+
+    /// Number of states the table will have
+    pub fn size(&self) -> usize {
+        3 + 4 * subtable_size(self.max_level)
+    }
+}
+
+/// Generates the state table described by `params`
+pub fn generate_state_table(params: &StateTableParams) -> RuntimeStateTable {
+    assert!(params.max_level >= 1, "Max level is too small");
+    assert!(
+        params.max_level <= StateTableParams::MAX_LEVEL,
+        "Max level is too big for 12-bit states"
+    );
+    assert!(params.discount >= 1, "Discount must be at least 1");
+
+    let subtable_size = subtable_size(params.max_level);
+    let offset = u16::try_from(subtable_size).unwrap();
+    let (a, b, c, d) = (3, 3 + offset, 3 + 2 * offset, 3 + 3 * offset);
+    let half = 1 << (u16::BITS - 1);
+
+    // Entry nodes, then 4 copies of the auxiliary table - one per last 2 bits
+    let mut entries = vec![
+        StateEntry::new(half, [1, 2]),
+        StateEntry::new(half, [a, b]),
+        StateEntry::new(half, [c, d]),
+    ];
+    entries.resize(params.size(), StateEntry::new(0, [0; 2]));
+
+    let aux = gen_auxiliary_table(params);
+    for (i, entry) in aux.iter().enumerate() {
+        let [n0, n1] = entry.next;
+        let p = entry.prob;
+        entries[usize::from(a) + i] = StateEntry::new(p, [a + n0, b + n1]);
+        entries[usize::from(b) + i] = StateEntry::new(p, [c + n0, d + n1]);
+        entries[usize::from(c) + i] = StateEntry::new(p, [a + n0, b + n1]);
+        entries[usize::from(d) + i] = StateEntry::new(p, [c + n0, d + n1]);
+    }
+
+    RuntimeStateTable::from_entries(entries).unwrap()
+}
+
+/// Replaces the probabilities of `table` with the ones observed on `buf`, when
+/// the states track bit histories in an order-1 context
+///
+/// States that are never visited keep their probability, rarely visited ones
+/// are pulled towards it.
+pub fn train_state_table(table: &impl StateTable, buf: &[u8]) -> RuntimeStateTable {
+    let mut counts = vec![[0u64; 2]; table.size()];
+    let mut states = vec![0u16; 1 << 16];
+    let mut prev_byte = 0;
+    for &byte in buf {
+        let mut partial = 1; // leading 1 marks how many bits we've seen
+        for bit in (0..8).rev().map(|i| (byte >> i) & 1) {
+            let ctx = usize::from(prev_byte) << 8 | partial;
+            let state = states[ctx];
+            counts[usize::from(state)][usize::from(bit)] += 1;
+            states[ctx] = table.next(state, bit);
+            partial = (partial << 1) | usize::from(bit);
+        }
+        prev_byte = byte;
+    }
+
+    let entries = counts
+        .iter()
+        .enumerate()
+        .map(|(state, &[n0, n1])| {
+            let state = u16::try_from(state).unwrap();
+            let prior = u64::from(table.p(state));
+            let p = ((n1 << 16) + 2 * prior) / (n0 + n1 + 2);
+            let p = u16::try_from(p).unwrap_or(u16::MAX);
+            let next = [table.next(state, 0), table.next(state, 1)];
+            StateEntry::new(p, next)
+        })
+        .collect();
+    RuntimeStateTable::from_entries(entries).unwrap()
+}
+
+fn subtable_size(max_level: usize) -> usize {
+    max_level * (max_level + 1) / 2
+}
+
+fn gen_auxiliary_table(params: &StateTableParams) -> Vec<StateEntry> {
+    let mut at = Vec::with_capacity(subtable_size(params.max_level));
+    let mut filled = 0; // number of nodes filled up until level-1
+    for level in 1..=params.max_level {
+        // at level i, we need to fill i nodes
+        for node in 0..level {
+            let prob = calc_prob(node, level - 1);
+            let next = get_next_nodes(params, level, filled, node);
+            at.push(StateEntry::new(prob, next));
+        }
+        filled += level;
+    }
+    at
+}
+
+fn get_next_nodes(params: &StateTableParams, level: usize, filled: usize, node: usize) -> [u16; 2] {
+    let to_state = |x: usize| u16::try_from(x).unwrap();
+    if level < params.max_level {
+        let next_node = to_state(filled + level + node);
+        return [next_node, next_node + 1];
+    }
+
+    // find node that has similiar probability at the discounted level
+    let next_level = (level / params.discount).max(1);
+    let next_node_idx = node * next_level / level;
+    let next_node = to_state(next_node_idx + (next_level - 1) * next_level / 2);
+    let curr_node = to_state(filled + node);
+
+    let mut next = [next_node, next_node];
+    if node == 0 {
+        next[0] = curr_node;
+    }
+    if node == level - 1 {
+        next[1] = curr_node;
+    }
+    next
+}
+
+// Same as Counter prob calculation
+fn calc_prob(count: usize, total: usize) -> u16 {
+    let p = (1 << 16) * (count as u64 + 1) / (total as u64 + 2);
+    u16::try_from(p).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        corpus::{generate, Kind},
+        state_table::NaiveStateTable,
+    };
+
+    #[test]
+    fn default_is_naive() {
+        let table = generate_state_table(&StateTableParams::default());
+        assert_eq!(table.size(), NaiveStateTable.size());
+        for state in 0..u16::try_from(table.size()).unwrap() {
+            assert_eq!(table.p(state), NaiveStateTable.p(state), "state {}", state);
+            for bit in 0..2 {
+                let expected = NaiveStateTable.next(state, bit);
+                assert_eq!(table.next(state, bit), expected, "state {}", state);
+            }
+        }
+    }
+
+    #[test]
+    fn all_params_are_valid() {
+        for max_level in [1, 2, 3, 10, 31, 44] {
+            for discount in [1, 2, 3, 8] {
+                let params = StateTableParams { max_level, discount };
+                // `from_entries` checks all transitions stay in the table
+                let table = generate_state_table(&params);
+                assert_eq!(table.size(), params.size());
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Max level is too big for 12-bit states")]
+    fn too_many_states() {
+        generate_state_table(&StateTableParams { max_level: 45, discount: 2 });
+    }
+
+    #[test]
+    fn train_on_zeros() {
+        let table = train_state_table(&NaiveStateTable, &generate(Kind::Zeros, 1 << 12, 0));
+        // only ever saw zeros, so every visited state should predict them
+        let mut state = 0;
+        for _ in 0..100 {
+            state = table.next(state, 0);
+            assert!(table.p(state) < NaiveStateTable.p(state).max(1 << 10));
+        }
+    }
+
+    #[test]
+    fn train_keeps_transitions() {
+        let table = train_state_table(&NaiveStateTable, &generate(Kind::Markov, 1 << 12, 0));
+        for state in 0..u16::try_from(table.size()).unwrap() {
+            for bit in 0..2 {
+                assert_eq!(table.next(state, bit), NaiveStateTable.next(state, bit));
+            }
+        }
+    }
+}
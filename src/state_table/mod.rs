@@ -1,37 +1,52 @@
+pub mod generator;
 pub mod naive;
+pub mod runtime;
+
+pub use self::{generator::*, naive::*, runtime::*};
 
 pub trait StateTable {
-    fn next(state: u16, bit: u8) -> u16;
-    fn next4(states: [u16; 4], nib: u8) -> [u16; 4] {
+    fn next(&self, state: u16, bit: u8) -> u16;
+    fn next4(&self, states: [u16; 4], nib: u8) -> [u16; 4] {
         [
-            Self::next(states[0], nib >> 3),
-            Self::next(states[1], (nib >> 2) & 1),
-            Self::next(states[2], (nib >> 1) & 1),
-            Self::next(states[3], nib & 1),
+            self.next(states[0], nib >> 3),
+            self.next(states[1], (nib >> 2) & 1),
+            self.next(states[2], (nib >> 1) & 1),
+            self.next(states[3], nib & 1),
         ]
     }
 
-    fn p(state: u16) -> u16;
-    fn p4(states: [u16; 4]) -> [u16; 4] {
+    fn p(&self, state: u16) -> u16;
+    fn p4(&self, states: [u16; 4]) -> [u16; 4] {
         [
-            Self::p(states[0]),
-            Self::p(states[1]),
-            Self::p(states[2]),
-            Self::p(states[3]),
+            self.p(states[0]),
+            self.p(states[1]),
+            self.p(states[2]),
+            self.p(states[3]),
         ]
     }
+
+    /// Number of states, valid states are `0..size()`
+    fn size(&self) -> usize;
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct StateEntry {
     prob: u16,
     next: [u16; 2],
 }
 
 impl StateEntry {
-    const fn new(prob: u16, next: [u16; 2]) -> Self {
+    pub const fn new(prob: u16, next: [u16; 2]) -> Self {
         Self { prob, next }
     }
+
+    pub fn prob(&self) -> u16 {
+        self.prob
+    }
+
+    pub fn next(&self) -> [u16; 2] {
+        self.next
+    }
 }
 
 // TODO: Derive macro for trait StateTable, with params - TABLE
@@ -40,13 +55,17 @@ macro_rules! impl_state_table_from {
         // struct $state_table_name;
 
         impl StateTable for $state_table_name {
-            fn next(state: u16, bit: u8) -> u16 {
+            fn next(&self, state: u16, bit: u8) -> u16 {
                 $table[usize::from(state)].next[usize::from(bit)]
             }
 
-            fn p(state: u16) -> u16 {
+            fn p(&self, state: u16) -> u16 {
                 $table[usize::from(state)].prob
             }
+
+            fn size(&self) -> usize {
+                $table.len()
+            }
         }
     };
 }
@@ -1,15 +1,14 @@
-use std::char::MAX;
-
 use super::{impl_state_table_from, StateEntry, StateTable};
 
 // TODO: Docs
 // notes are from 22.09.2022
+// `gen-state-table` with default parameters reproduces this table at runtime
 pub struct NaiveStateTable;
 
-const MAX_LEVEL: usize = 44;
+pub(super) const MAX_LEVEL: usize = 44;
 const SUBTABLE_SIZE: usize = MAX_LEVEL * (MAX_LEVEL + 1) / 2; // 990
 const SIZE: usize = 3 + 4 * SUBTABLE_SIZE; // 3963 <= 4096 = 1 << 12
-const TABLE: [StateEntry; SIZE] = gen_table();
+static TABLE: [StateEntry; SIZE] = gen_table();
 
 const HALF: u16 = 1 << (u16::BITS - 1);
 const OFFSET: u16 = SUBTABLE_SIZE as u16;
@@ -98,9 +97,7 @@ const fn get_next_nodes(level: usize, filled: usize, node: usize) -> [u16; 2] {
     }
 
     let next_node = (filled + level + node) as u16;
-    let next = [next_node, next_node + 1];
-
-    next
+    [next_node, next_node + 1]
 }
 
 // TODO: Try rounding (instead of flooring)
@@ -113,19 +110,3 @@ const fn calc_prob(count: u16, total: u16) -> u16 {
 }
 
 impl_state_table_from!(NaiveStateTable, TABLE);
-
-// // Add `weath3rb0i::state_table::naive::print();` to main
-// // Resolve dependencies - make the state_table module public - `pub mod state_table`
-// // Run `cargo run > state_table.csv`
-// // Delete error message at the end of the file
-// pub fn print() {
-//     println!("state,tr0,tr1,prob");
-//     for i in 0..SIZE {
-//         let s = i as u16;
-//         let s0 = NaiveStateTable::next(s, 0);
-//         let s1 = NaiveStateTable::next(s, 1);
-//         let p = NaiveStateTable::p(s);
-
-//         println!("{},{},{},{}", s, s0, s1, p);
-//     }
-// }
@@ -0,0 +1,179 @@
+use std::io::{self, BufRead, Error, ErrorKind, Write};
+
+use super::{StateEntry, StateTable};
+
+/// A state table loaded at runtime, e.g. from a CSV exported by `gen-state-table`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuntimeStateTable {
+    entries: Vec<StateEntry>,
+}
+
+impl RuntimeStateTable {
+    pub const MAX_SIZE: usize = 1 << 12;
+    const CSV_HEADER: &'static str = "state,tr0,tr1,prob";
+
+    /// Checks the table fits 12-bit states and every transition stays inside it
+    pub fn from_entries(entries: Vec<StateEntry>) -> io::Result<Self> {
+        if entries.is_empty() || entries.len() > Self::MAX_SIZE {
+            let msg = format!(
+                "State table has {} states, expected 1..=4096",
+                entries.len()
+            );
+            return Err(Error::new(ErrorKind::InvalidData, msg));
+        }
+        for (state, entry) in entries.iter().enumerate() {
+            if entry
+                .next
+                .iter()
+                .any(|&next| usize::from(next) >= entries.len())
+            {
+                let msg = format!("State {} transitions outside the table", state);
+                return Err(Error::new(ErrorKind::InvalidData, msg));
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    /// Copies any state table, e.g. to export a compiled-in one
+    pub fn from_table(table: &impl StateTable) -> Self {
+        let entries = (0..table.size())
+            .map(|state| {
+                let state = u16::try_from(state).unwrap();
+                StateEntry::new(table.p(state), [table.next(state, 0), table.next(state, 1)])
+            })
+            .collect();
+        Self { entries }
+    }
+
+    pub fn entries(&self) -> &[StateEntry] {
+        &self.entries
+    }
+
+    /// Reads the `state,tr0,tr1,prob` format of `docs/state_table/`
+    pub fn read_csv(reader: impl BufRead) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        match lines.next().transpose()? {
+            Some(header) if header.trim() == Self::CSV_HEADER => {}
+            _ => {
+                let msg = format!("Expected a `{}` header", Self::CSV_HEADER);
+                return Err(Error::new(ErrorKind::InvalidData, msg));
+            }
+        }
+
+        let mut entries = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let bad_line = || Error::new(ErrorKind::InvalidData, format!("Bad line: {}", line));
+            let fields = line
+                .split(',')
+                .map(|x| x.trim().parse::<u16>().map_err(|_| bad_line()))
+                .collect::<io::Result<Vec<_>>>()?;
+            let &[state, tr0, tr1, prob] = fields.as_slice() else {
+                return Err(bad_line());
+            };
+            if usize::from(state) != entries.len() {
+                return Err(bad_line()); // states must be listed in order
+            }
+            entries.push(StateEntry::new(prob, [tr0, tr1]));
+        }
+        Self::from_entries(entries)
+    }
+
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "{}", Self::CSV_HEADER)?;
+        for (state, entry) in self.entries.iter().enumerate() {
+            let [tr0, tr1] = entry.next;
+            writeln!(writer, "{},{},{},{}", state, tr0, tr1, entry.prob)?;
+        }
+        writer.flush()
+    }
+
+    /// Graphviz DOT, nodes are labeled with their probability of a 1
+    pub fn write_dot(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "digraph state_table {{")?;
+        writeln!(writer, "    node [shape=circle];")?;
+        for (state, entry) in self.entries.iter().enumerate() {
+            let p = f64::from(entry.prob) / 65536.0;
+            writeln!(writer, "    {} [label=\"{}\\n{:.3}\"];", state, state, p)?;
+            let [tr0, tr1] = entry.next;
+            writeln!(writer, "    {} -> {} [label=\"0\"];", state, tr0)?;
+            writeln!(
+                writer,
+                "    {} -> {} [label=\"1\", style=dashed];",
+                state, tr1
+            )?;
+        }
+        writeln!(writer, "}}")?;
+        writer.flush()
+    }
+}
+
+impl StateTable for RuntimeStateTable {
+    fn next(&self, state: u16, bit: u8) -> u16 {
+        self.entries[usize::from(state)].next[usize::from(bit)]
+    }
+
+    fn p(&self, state: u16) -> u16 {
+        self.entries[usize::from(state)].prob
+    }
+
+    fn size(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_table::NaiveStateTable;
+
+    const DOCS_CSV: &str = include_str!("../../docs/state_table/state_table.csv");
+
+    #[test]
+    fn docs_csv_is_naive() {
+        let table = RuntimeStateTable::read_csv(DOCS_CSV.as_bytes()).unwrap();
+        assert_eq!(table, RuntimeStateTable::from_table(&NaiveStateTable));
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let table = RuntimeStateTable::from_table(&NaiveStateTable);
+        let mut csv = Vec::new();
+        table.write_csv(&mut csv).unwrap();
+        assert_eq!(csv, DOCS_CSV.as_bytes());
+        assert_eq!(RuntimeStateTable::read_csv(csv.as_slice()).unwrap(), table);
+    }
+
+    #[test]
+    fn dot() {
+        let table =
+            RuntimeStateTable::read_csv("state,tr0,tr1,prob\n0,0,1,100\n1,1,0,200\n".as_bytes())
+                .unwrap();
+        let mut dot = Vec::new();
+        table.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph state_table {"));
+        assert!(dot.contains("0 -> 1 [label=\"1\", style=dashed];"));
+        assert!(dot.contains("1 -> 0 [label=\"1\", style=dashed];"));
+    }
+
+    #[test]
+    fn invalid_csv() {
+        let cases = [
+            "",
+            "state,tr0,tr1\n0,0,0\n",
+            "state,tr0,tr1,prob\n",
+            "state,tr0,tr1,prob\n0,0,2,100\n1,0,0,100\n",
+            "state,tr0,tr1,prob\n1,0,0,100\n",
+            "state,tr0,tr1,prob\n0,0,0\n",
+            "state,tr0,tr1,prob\n0,0,0,65536\n",
+        ];
+        for csv in cases {
+            let err = RuntimeStateTable::read_csv(csv.as_bytes()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{:?}", csv);
+        }
+    }
+}
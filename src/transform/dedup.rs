@@ -0,0 +1,302 @@
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+use crate::helpers::{hash_u64, table_bits};
+use crate::{u8, usize};
+
+/// Shortest repeat worth a reference, also the rolling hash window
+pub const MIN_MATCH: usize = 32;
+
+// rolling hash multiplier, the window's bytes are the digits of a number in this base
+const ROLL_BASE: u64 = 0x100000001b3;
+// only every INSERT_STRIDE-th window goes into the match table, so any repeat
+// of MIN_MATCH + INSERT_STRIDE - 1 bytes is found, and matches found late are
+// extended backwards
+const INSERT_STRIDE: usize = 16;
+// the match table has about 2 entries per inserted window, at most 2^MAX_TABLE_BITS
+const MIN_TABLE_BITS: u8 = 10;
+const MAX_TABLE_BITS: u8 = 22;
+
+/// A repeat of earlier output, preceded by `literals` literal bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub literals: u64,
+    /// How far back the repeat starts, may be less than `len` (overlapping)
+    pub distance: u64,
+    pub len: u64,
+}
+
+/// Long-range deduplication, run before modeling
+///
+/// Repeats of at least `MIN_MATCH` bytes are cut out of the input and listed
+/// in the header, the models only see the remaining literal bytes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Dedup {
+    /// Number of literal bytes
+    pub len: u64,
+    pub matches: Vec<Match>,
+}
+
+impl Dedup {
+    /// Finds the repeats in `buf` and returns the literal bytes
+    pub fn build(buf: &[u8]) -> (Self, Vec<u8>) {
+        let bits =
+            table_bits(buf.len() / INSERT_STRIDE * 2, 1).clamp(MIN_TABLE_BITS, MAX_TABLE_BITS);
+        let mut table = vec![usize::MAX; 1 << bits];
+        let slot = |hash: u64| usize!(hash_u64(hash) >> (u64::BITS - u32::from(bits)));
+        // weight of the byte leaving the window
+        let out_weight = ROLL_BASE.wrapping_pow(MIN_MATCH as u32 - 1);
+
+        let mut dedup = Self::default();
+        let mut literals = Vec::with_capacity(buf.len());
+        let mut literal_start = 0;
+        let mut pos = 0;
+        let mut hash = None;
+        while pos + MIN_MATCH <= buf.len() {
+            let h = *hash.get_or_insert_with(|| window_hash(&buf[pos..pos + MIN_MATCH]));
+            let slot = &mut table[slot(h)];
+            let candidate = *slot;
+            if pos.is_multiple_of(INSERT_STRIDE) {
+                *slot = pos;
+            }
+            let len = match candidate {
+                usize::MAX => 0,
+                src => common_prefix(&buf[src..], &buf[pos..]),
+            };
+
+            if len >= MIN_MATCH {
+                let back = buf[literal_start..pos]
+                    .iter()
+                    .rev()
+                    .zip(buf[..candidate].iter().rev())
+                    .take_while(|(x, y)| x == y)
+                    .count();
+                let start = pos - back;
+                literals.extend_from_slice(&buf[literal_start..start]);
+                dedup.matches.push(Match {
+                    literals: (start - literal_start) as u64,
+                    distance: (pos - candidate) as u64,
+                    len: (back + len) as u64,
+                });
+                pos += len;
+                literal_start = pos;
+                hash = None;
+            } else {
+                hash = buf.get(pos + MIN_MATCH).map(|&byte| {
+                    let h = h.wrapping_sub(u64::from(buf[pos]).wrapping_mul(out_weight));
+                    h.wrapping_mul(ROLL_BASE).wrapping_add(u64::from(byte))
+                });
+                pos += 1;
+            }
+        }
+        literals.extend_from_slice(&buf[literal_start..]);
+        dedup.len = literals.len() as u64;
+        (dedup, literals)
+    }
+
+    /// Length of the original input
+    pub fn output_len(&self) -> u64 {
+        self.matches
+            .iter()
+            .fold(self.len, |len, m| len.saturating_add(m.len))
+    }
+
+    /// Puts the repeats back between the `literals`
+    pub fn inverse(&self, literals: &[u8]) -> io::Result<Vec<u8>> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_owned());
+        if literals.len() as u64 != self.len {
+            return Err(invalid("Literal length doesn't match the dedup header"));
+        }
+
+        let mut out = Vec::with_capacity(literals.len());
+        let mut rest = literals;
+        for m in &self.matches {
+            let Some((head, tail)) = rest.split_at_checked(usize!(m.literals)) else {
+                return Err(invalid("Dedup match after the last literal"));
+            };
+            out.extend_from_slice(head);
+            rest = tail;
+            let Some(src) = (out.len() as u64).checked_sub(m.distance) else {
+                return Err(invalid("Dedup match before the start of the output"));
+            };
+            // byte by byte, the repeat may overlap itself
+            for i in 0..usize!(m.len) {
+                out.push(out[usize!(src) + i]);
+            }
+        }
+        out.extend_from_slice(rest);
+        Ok(out)
+    }
+
+    /// Bytes `write` produces
+    pub fn size(&self) -> usize {
+        let varints: usize = self
+            .matches
+            .iter()
+            .map(|m| varint_size(m.literals) + varint_size(m.distance) + varint_size(m.len))
+            .sum();
+        2 * std::mem::size_of::<u64>() + varints
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.len.to_be_bytes())?;
+        writer.write_all(&(self.matches.len() as u64).to_be_bytes())?;
+        for m in &self.matches {
+            write_varint(writer, m.literals)?;
+            write_varint(writer, m.distance)?;
+            write_varint(writer, m.len)?;
+        }
+        Ok(())
+    }
+
+    /// Parses what `write` wrote, rejecting matches `build` can't produce
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let mut buf = [0; 16];
+        reader.read_exact(&mut buf)?;
+        let len = u64::from_be_bytes(buf[..8].try_into().unwrap());
+        let count = u64::from_be_bytes(buf[8..].try_into().unwrap());
+
+        // don't trust the count with an allocation, the reads fail soon enough
+        let mut matches = Vec::new();
+        let mut literals = 0_u64;
+        for _ in 0..count {
+            let m = Match {
+                literals: read_varint(reader)?,
+                distance: read_varint(reader)?,
+                len: read_varint(reader)?,
+            };
+            literals = literals.saturating_add(m.literals);
+            if m.distance == 0 || m.len < MIN_MATCH as u64 || literals > len {
+                return Err(Error::new(ErrorKind::InvalidData, "Invalid dedup match"));
+            }
+            matches.push(m);
+        }
+        Ok(Self { len, matches })
+    }
+}
+
+fn window_hash(window: &[u8]) -> u64 {
+    window.iter().fold(0, |h: u64, &byte| {
+        h.wrapping_mul(ROLL_BASE).wrapping_add(u64::from(byte))
+    })
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+fn varint_size(mut x: u64) -> usize {
+    let mut size = 1;
+    while x >= 0x80 {
+        x >>= 7;
+        size += 1;
+    }
+    size
+}
+
+// LEB128, 7 bits at a time starting from the lowest
+fn write_varint(writer: &mut impl Write, mut x: u64) -> io::Result<()> {
+    while x >= 0x80 {
+        writer.write_all(&[u8!(x & 0x7f) | 0x80])?;
+        x >>= 7;
+    }
+    writer.write_all(&[u8!(x)])
+}
+
+fn read_varint(reader: &mut impl Read) -> io::Result<u64> {
+    let mut x = 0;
+    for shift in (0..u64::BITS).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        let bits = u64::from(byte[0] & 0x7f);
+        // the last byte holds the top bit, a zero continuation isn't canonical
+        if (shift > 0 && byte[0] == 0) || bits.checked_shl(shift).map(|b| b >> shift) != Some(bits)
+        {
+            break;
+        }
+        x |= bits << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(x);
+        }
+    }
+    Err(Error::new(ErrorKind::InvalidData, "Invalid varint"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::{corpus, generate, Kind};
+
+    fn round_trip(input: &[u8]) -> Dedup {
+        let (dedup, literals) = Dedup::build(input);
+        assert_eq!(dedup.len, literals.len() as u64);
+        assert_eq!(dedup.output_len(), input.len() as u64);
+        assert_eq!(dedup.inverse(&literals).unwrap(), input);
+
+        let mut buf = Vec::new();
+        dedup.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), dedup.size());
+        assert_eq!(Dedup::read(&mut buf.as_slice()).unwrap(), dedup);
+        dedup
+    }
+
+    #[test]
+    fn reversible() {
+        for (kind, input) in corpus(1 << 14, 8) {
+            let dedup = round_trip(&input);
+            if kind == Kind::Random {
+                assert!(dedup.matches.is_empty());
+            }
+        }
+        round_trip(&[7; MIN_MATCH]);
+        round_trip(&[7; MIN_MATCH + 1]);
+    }
+
+    #[test]
+    fn long_repeats() {
+        let block = generate(Kind::Random, 1000, 3);
+        let mut input = block.clone();
+        input.extend(generate(Kind::Random, 5000, 4));
+        input.extend(&block);
+        let dedup = round_trip(&input);
+        assert_eq!(dedup.len, 6000);
+        assert_eq!(
+            dedup.matches,
+            [Match { literals: 6000, distance: 6000, len: 1000 }]
+        );
+
+        // overlapping the bytes it copies
+        let dedup = round_trip(&[1; 1 << 12]);
+        assert_eq!(dedup.len, 1);
+        assert_eq!(dedup.matches[0].distance, 1);
+    }
+
+    #[test]
+    fn varints() {
+        for x in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u64::MAX >> 1, u64::MAX] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, x).unwrap();
+            assert_eq!(buf.len(), varint_size(x));
+            assert_eq!(read_varint(&mut buf.as_slice()).unwrap(), x);
+        }
+        for bad in [&[0x80, 0x00][..], &[0xff; 10], &[0xff; 11]] {
+            assert!(read_varint(&mut &bad[..]).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_bad_matches() {
+        let dedup = Dedup {
+            len: 4,
+            matches: vec![Match { literals: 4, distance: 5, len: 32 }],
+        };
+        let err = dedup.inverse(&[1, 2, 3, 4]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = dedup.inverse(&[1, 2, 3]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut buf = Vec::new();
+        Dedup { len: 3, ..dedup }.write(&mut buf).unwrap();
+        let err = Dedup::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,6 @@
+//! Reversible preprocessing stages, run on the input before modeling and
+//! undone after decoding
+pub mod dedup;
+pub mod text;
+
+pub use self::{dedup::*, text::*};
@@ -0,0 +1,592 @@
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+use crate::helpers::histogram;
+
+/// Which words the dictionary holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dictionary {
+    None,
+    /// The most common English words (`STATIC_WORDS`), only their codes are stored
+    Static,
+    /// The words that save the most in this input, stored in the header
+    Built,
+}
+
+/// What `TextTransform::build` may use
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextOptions {
+    /// Escape capitalized and all-caps words so they share contexts with the
+    /// lowercase ones
+    pub capitals: bool,
+    /// Turn CRLF line endings into LF when every line ends in CRLF
+    pub eol: bool,
+    pub dictionary: Dictionary,
+}
+
+impl TextOptions {
+    pub fn capitals(mut self, capitals: bool) -> Self {
+        self.capitals = capitals;
+        self
+    }
+
+    pub fn eol(mut self, eol: bool) -> Self {
+        self.eol = eol;
+        self
+    }
+
+    pub fn dictionary(mut self, dictionary: Dictionary) -> Self {
+        self.dictionary = dictionary;
+        self
+    }
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        Self {
+            capitals: true,
+            eol: false,
+            dictionary: Dictionary::Built,
+        }
+    }
+}
+
+/// Common English words for `Dictionary::Static`, most frequent first
+pub const STATIC_WORDS: &[&str] = &[
+    "the", "of", "and", "to", "in", "is", "that", "for", "it", "as", "was", "with", "be", "by",
+    "on", "not", "he", "this", "are", "or", "his", "from", "at", "which", "but", "have", "an",
+    "had", "they", "you", "were", "their", "one", "all", "we", "can", "her", "has", "there",
+    "been", "if", "more", "when", "will", "would", "who", "so", "no", "she", "other", "its", "may",
+    "these", "what", "them", "than", "some", "him", "time", "into", "only", "do", "my", "then",
+    "also", "any", "new", "like", "could", "our", "two", "first", "such", "after", "about",
+    "should", "over", "very", "people", "through", "where", "most", "made", "between", "before",
+    "those", "because", "each", "many", "under", "while", "being", "same", "upon", "down", "said",
+    "little", "great", "might", "know", "again", "never", "without", "himself",
+];
+
+// words are runs of ASCII letters, shorter ones can't save anything
+const MIN_WORD_LEN: usize = 2;
+const MAX_WORD_LEN: usize = 32;
+// the word count is stored in a byte
+const MAX_WORDS: usize = 255;
+
+// bits of the transform's flags byte
+const FLAG_EOL: u8 = 1;
+const FLAG_CAPITALS: u8 = 2;
+const FLAG_STATIC: u8 = 4;
+const KNOWN_FLAGS: u8 = FLAG_EOL | FLAG_CAPITALS | FLAG_STATIC;
+
+/// A strictly reversible text preprocessing step, run before modeling
+///
+/// Escapes and word codes are bytes that don't occur in the input, so no
+/// literal byte ever needs escaping. An input without enough unused bytes
+/// simply gets fewer (or none) of the substitutions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextTransform {
+    /// Length of the transformed text
+    pub len: u64,
+    /// Every CRLF line ending was turned into LF
+    pub eol: bool,
+    /// Escape bytes for a capitalized word and an all-caps word
+    pub capitals: Option<[u8; 2]>,
+    /// Whether `words` are the first entries of `STATIC_WORDS`
+    pub is_static: bool,
+    /// (code, word) pairs, words are lowercase ASCII letters
+    pub words: Vec<(u8, Vec<u8>)>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Case {
+    Lower,
+    Capitalized,
+    Upper,
+    Mixed,
+}
+
+impl TextTransform {
+    /// Picks the substitutions for `buf` and transforms it
+    pub fn build(buf: &[u8], options: &TextOptions) -> (Self, Vec<u8>) {
+        let eol = options.eol && uses_crlf(buf);
+        let normalized;
+        let buf = match eol {
+            true => {
+                normalized = strip_cr(buf);
+                &normalized
+            }
+            false => buf,
+        };
+
+        // letters are never free, the capital escapes lowercase them
+        let counts = histogram(buf);
+        let mut unused = (0..=255)
+            .filter(|&byte: &u8| counts[usize::from(byte)] == 0 && !byte.is_ascii_alphabetic());
+        let capitals = match options.capitals {
+            true => unused
+                .next()
+                .zip(unused.next())
+                .map(|(cap, upper)| [cap, upper]),
+            false => None,
+        };
+        let codes: Vec<u8> = unused.take(MAX_WORDS).collect();
+        let words = match options.dictionary {
+            Dictionary::None => Vec::new(),
+            Dictionary::Static => STATIC_WORDS
+                .iter()
+                .map(|word| word.as_bytes().to_vec())
+                .collect(),
+            Dictionary::Built => best_words(buf, capitals.is_some(), codes.len()),
+        };
+        let words = codes.into_iter().zip(words).collect();
+
+        let mut transform = Self {
+            len: 0,
+            eol,
+            capitals,
+            is_static: options.dictionary == Dictionary::Static,
+            words,
+        };
+        let transformed = transform.forward(buf);
+        transform.len = transformed.len() as u64;
+        (transform, transformed)
+    }
+
+    // `buf` already has its line endings normalized
+    fn forward(&self, buf: &[u8]) -> Vec<u8> {
+        let dictionary: HashMap<&[u8], u8> = self
+            .words
+            .iter()
+            .map(|(code, word)| (word.as_slice(), *code))
+            .collect();
+        let mut out = Vec::with_capacity(buf.len());
+        let mut word = Vec::new();
+        for token in Tokens::new(buf) {
+            if !token[0].is_ascii_alphabetic() {
+                out.extend_from_slice(token);
+                continue;
+            }
+            let case = case(token);
+            let escape = match (self.capitals, case) {
+                (_, Case::Lower) => None,
+                (Some([cap, _]), Case::Capitalized) => Some(cap),
+                (Some([_, upper]), Case::Upper) => Some(upper),
+                (Some([cap, _]), Case::Mixed) => {
+                    for &byte in token {
+                        if byte.is_ascii_uppercase() {
+                            out.push(cap);
+                        }
+                        out.push(byte.to_ascii_lowercase());
+                    }
+                    continue;
+                }
+                (None, _) => {
+                    out.extend_from_slice(token);
+                    continue;
+                }
+            };
+            out.extend(escape);
+            word.clear();
+            word.extend(token.iter().map(u8::to_ascii_lowercase));
+            match dictionary.get(word.as_slice()) {
+                Some(&code) => out.push(code),
+                None => out.extend_from_slice(&word),
+            }
+        }
+        out
+    }
+
+    /// Most bytes `build` turns `len` bytes of text into: capital escapes at
+    /// most double mixed case words, everything else keeps its length or shrinks
+    pub fn max_len(len: u64) -> u64 {
+        len.saturating_mul(2)
+    }
+
+    /// Restores the original `len` bytes of text, fails on anything `forward`
+    /// can't produce - as soon as the output grows past `len`
+    pub fn inverse(&self, buf: &[u8], len: u64) -> io::Result<Vec<u8>> {
+        let mut words: [Option<&[u8]>; 256] = [None; 256];
+        for (code, word) in &self.words {
+            words[usize::from(*code)] = Some(word);
+        }
+        let invalid = || Error::new(ErrorKind::InvalidData, "Invalid escape in transformed text");
+
+        let wrong_len = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                "Text transform doesn't restore the length declared in the header",
+            )
+        };
+
+        let mut out = Vec::with_capacity(buf.len());
+        let mut iter = buf.iter().copied().peekable();
+        while let Some(mut byte) = iter.next() {
+            // restoring CRs only adds bytes
+            if out.len() as u64 > len {
+                return Err(wrong_len());
+            }
+            let escape = match self.capitals {
+                Some([cap, upper]) if byte == cap || byte == upper => {
+                    let all_caps = byte == upper;
+                    byte = iter.next().ok_or_else(invalid)?;
+                    Some(all_caps)
+                }
+                _ => None,
+            };
+            match (words[usize::from(byte)], escape) {
+                (Some(word), None) => out.extend_from_slice(word),
+                (Some(word), Some(false)) => {
+                    out.push(word[0].to_ascii_uppercase());
+                    out.extend_from_slice(&word[1..]);
+                }
+                (Some(word), Some(true)) => out.extend(word.iter().map(u8::to_ascii_uppercase)),
+                (None, None) => out.push(byte),
+                (None, Some(_)) if !byte.is_ascii_lowercase() => return Err(invalid()),
+                (None, Some(false)) => out.push(byte.to_ascii_uppercase()),
+                (None, Some(true)) => {
+                    out.push(byte.to_ascii_uppercase());
+                    while let Some(byte) = iter.next_if(u8::is_ascii_lowercase) {
+                        out.push(byte.to_ascii_uppercase());
+                    }
+                }
+            }
+        }
+
+        if self.eol {
+            out = add_cr(&out);
+        }
+        match out.len() as u64 == len {
+            true => Ok(out),
+            false => Err(wrong_len()),
+        }
+    }
+
+    /// Bytes `write` produces
+    pub fn size(&self) -> usize {
+        let capitals = self.capitals.map_or(0, |escapes| escapes.len());
+        let words = match self.is_static {
+            true => 0,
+            false => self.words.iter().map(|(_, word)| 1 + word.len()).sum(),
+        };
+        std::mem::size_of::<u64>() + 2 + capitals + self.words.len() + words
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut flags = 0;
+        if self.eol {
+            flags |= FLAG_EOL;
+        }
+        if self.capitals.is_some() {
+            flags |= FLAG_CAPITALS;
+        }
+        if self.is_static {
+            flags |= FLAG_STATIC;
+        }
+        writer.write_all(&self.len.to_be_bytes())?;
+        writer.write_all(&[flags])?;
+        if let Some(escapes) = &self.capitals {
+            writer.write_all(escapes)?;
+        }
+        writer.write_all(&[u8::try_from(self.words.len()).expect("At most MAX_WORDS words")])?;
+        for (code, _) in &self.words {
+            writer.write_all(&[*code])?;
+        }
+        if !self.is_static {
+            for (_, word) in &self.words {
+                writer.write_all(&[u8::try_from(word.len()).expect("Words are short")])?;
+                writer.write_all(word)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses what `write` wrote, rejecting transforms `build` can't produce
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_owned());
+        let mut buf = [0; 9];
+        reader.read_exact(&mut buf)?;
+        let len = u64::from_be_bytes(buf[..8].try_into().unwrap());
+        let flags = buf[8];
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(invalid("Unknown text transform flags"));
+        }
+
+        let mut escapes = [0; 2];
+        let capitals = match flags & FLAG_CAPITALS != 0 {
+            true => {
+                reader.read_exact(&mut escapes)?;
+                Some(escapes)
+            }
+            false => None,
+        };
+        let mut count = [0];
+        reader.read_exact(&mut count)?;
+        let mut codes = vec![0; usize::from(count[0])];
+        reader.read_exact(&mut codes)?;
+
+        let is_static = flags & FLAG_STATIC != 0;
+        let words: Vec<Vec<u8>> = match is_static {
+            true if codes.len() > STATIC_WORDS.len() => {
+                return Err(invalid("More codes than static dictionary words"))
+            }
+            true => STATIC_WORDS[..codes.len()]
+                .iter()
+                .map(|word| word.as_bytes().to_vec())
+                .collect(),
+            false => {
+                let mut words = Vec::with_capacity(codes.len());
+                for _ in 0..codes.len() {
+                    let mut word_len = [0];
+                    reader.read_exact(&mut word_len)?;
+                    let mut word = vec![0; usize::from(word_len[0])];
+                    reader.read_exact(&mut word)?;
+                    if !(MIN_WORD_LEN..=MAX_WORD_LEN).contains(&word.len())
+                        || !word.iter().all(u8::is_ascii_lowercase)
+                    {
+                        return Err(invalid("Dictionary words must be lowercase letters"));
+                    }
+                    words.push(word);
+                }
+                words
+            }
+        };
+
+        // every escape and code stands for exactly one thing
+        let mut seen = [false; 256];
+        for &byte in escapes
+            .iter()
+            .take(2 * usize::from(capitals.is_some()))
+            .chain(&codes)
+        {
+            if std::mem::replace(&mut seen[usize::from(byte)], true) {
+                return Err(invalid("Text transform reuses an escape byte"));
+            }
+            if byte.is_ascii_alphabetic() {
+                return Err(invalid("Text transform escapes a letter"));
+            }
+        }
+
+        Ok(Self {
+            len,
+            eol: flags & FLAG_EOL != 0,
+            capitals,
+            is_static,
+            words: codes.into_iter().zip(words).collect(),
+        })
+    }
+}
+
+/// Splits text into maximal runs of ASCII letters and single other bytes
+struct Tokens<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Tokens<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let first = *self.buf.first()?;
+        let len = match first.is_ascii_alphabetic() {
+            true => self
+                .buf
+                .iter()
+                .position(|byte| !byte.is_ascii_alphabetic())
+                .unwrap_or(self.buf.len()),
+            false => 1,
+        };
+        let (token, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some(token)
+    }
+}
+
+fn case(word: &[u8]) -> Case {
+    let (first, rest) = word.split_first().expect("Words aren't empty");
+    match (first.is_ascii_uppercase(), rest) {
+        (false, _) if rest.iter().all(u8::is_ascii_lowercase) => Case::Lower,
+        (true, _) if rest.iter().all(u8::is_ascii_lowercase) => Case::Capitalized,
+        (true, [_, ..]) if rest.iter().all(u8::is_ascii_uppercase) => Case::Upper,
+        _ => Case::Mixed,
+    }
+}
+
+/// The words worth a code, best savings first
+fn best_words(buf: &[u8], capitals: bool, max_words: usize) -> Vec<Vec<u8>> {
+    let mut counts: HashMap<Vec<u8>, u64> = HashMap::new();
+    for token in Tokens::new(buf) {
+        let dictionary_case = match case(token) {
+            Case::Lower => true,
+            Case::Capitalized | Case::Upper => capitals,
+            Case::Mixed => false,
+        };
+        if dictionary_case && (MIN_WORD_LEN..=MAX_WORD_LEN).contains(&token.len()) {
+            *counts.entry(token.to_ascii_lowercase()).or_default() += 1;
+        }
+    }
+
+    // every use saves len - 1 bytes, the header pays len + 2 once
+    let mut words: Vec<(u64, Vec<u8>)> = counts
+        .into_iter()
+        .map(|(word, count)| {
+            let len = word.len() as u64;
+            let savings = (count * (len - 1)).saturating_sub(len + 2);
+            (savings, word)
+        })
+        .filter(|&(savings, _)| savings > 0)
+        .collect();
+    words.sort_unstable_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    words.truncate(max_words);
+    words.into_iter().map(|(_, word)| word).collect()
+}
+
+// every '\n' is preceded by a '\r' (and there is at least one)
+fn uses_crlf(buf: &[u8]) -> bool {
+    let mut lines = 0;
+    for (i, &byte) in buf.iter().enumerate() {
+        if byte == b'\n' {
+            if i == 0 || buf[i - 1] != b'\r' {
+                return false;
+            }
+            lines += 1;
+        }
+    }
+    lines > 0
+}
+
+fn strip_cr(buf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len());
+    for (i, &byte) in buf.iter().enumerate() {
+        if !(byte == b'\r' && buf.get(i + 1) == Some(&b'\n')) {
+            out.push(byte);
+        }
+    }
+    out
+}
+
+fn add_cr(buf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len() + buf.len() / 32);
+    for &byte in buf {
+        if byte == b'\n' {
+            out.push(b'\r');
+        }
+        out.push(byte);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::{corpus, generate, Kind};
+
+    const TEXT: &[u8] = b"The cat and THE dog.\r\nA McDonald's in the USA, the end\r\n\
+        the the the the the and and and and cat cat cat IT It it\r\n";
+
+    fn all_options() -> Vec<TextOptions> {
+        let mut all = Vec::new();
+        for capitals in [false, true] {
+            for eol in [false, true] {
+                for dictionary in [Dictionary::None, Dictionary::Static, Dictionary::Built] {
+                    all.push(TextOptions { capitals, eol, dictionary });
+                }
+            }
+        }
+        all
+    }
+
+    fn round_trip(input: &[u8], options: &TextOptions) -> TextTransform {
+        let (transform, transformed) = TextTransform::build(input, options);
+        assert_eq!(transform.len, transformed.len() as u64);
+        assert!(transform.len <= TextTransform::max_len(input.len() as u64));
+        assert_eq!(
+            transform.inverse(&transformed, input.len() as u64).unwrap(),
+            input,
+            "{:?}",
+            options
+        );
+
+        let mut buf = Vec::new();
+        transform.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), transform.size());
+        assert_eq!(TextTransform::read(&mut buf.as_slice()).unwrap(), transform);
+        transform
+    }
+
+    #[test]
+    fn reversible() {
+        for options in all_options() {
+            round_trip(TEXT, &options);
+            round_trip(b"\r\n\n\r\rA", &options);
+            round_trip(b"\n", &options);
+            for (_, input) in corpus(1 << 12, 5) {
+                round_trip(&input, &options);
+            }
+        }
+    }
+
+    #[test]
+    fn substitutions() {
+        let options = TextOptions::default().eol(true);
+        let (transform, transformed) = TextTransform::build(TEXT, &options);
+        assert!(transform.eol);
+        assert!(transform.capitals.is_some());
+        assert!(transform.words.iter().any(|(_, word)| word == b"the"));
+        assert!(transformed.len() < TEXT.len());
+        assert!(!transformed.contains(&b'\r'));
+        assert!(!transformed.iter().any(u8::is_ascii_uppercase));
+
+        // mixed line endings stay as they are
+        let (transform, _) = TextTransform::build(b"a\r\nb\n", &options);
+        assert!(!transform.eol);
+    }
+
+    #[test]
+    fn no_unused_bytes() {
+        let input: Vec<u8> = (0..=255).chain(*b"The the the the").collect();
+        let transform = round_trip(&input, &TextOptions::default());
+        assert_eq!(transform.capitals, None);
+        assert!(transform.words.is_empty());
+    }
+
+    #[test]
+    fn markov_shrinks() {
+        let input = generate(Kind::Markov, 1 << 14, 1);
+        let (_, transformed) = TextTransform::build(&input, &TextOptions::default());
+        assert!(transformed.len() < input.len());
+    }
+
+    #[test]
+    fn mixed_case_doubles() {
+        let input = b"aBCD eFGH".repeat(10);
+        let options = TextOptions::default().dictionary(Dictionary::None);
+        let transform = round_trip(&input, &options);
+        assert!(transform.len > input.len() as u64 * 3 / 2);
+    }
+
+    #[test]
+    fn rejects_bad_streams() {
+        let (transform, _) = TextTransform::build(TEXT, &TextOptions::default());
+        let [cap, upper] = transform.capitals.unwrap();
+        for bad in [vec![cap], vec![upper, b'.'], vec![cap, cap, b'a']] {
+            let err = transform.inverse(&bad, 3).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+        // every code expands to a word, only a few fit
+        let (code, _) = transform.words[0];
+        for len in [0, 10, 1 << 20] {
+            let err = transform.inverse(&[code; 1 << 12], len).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+
+        let mut buf = Vec::new();
+        transform.write(&mut buf).unwrap();
+        // make the all-caps escape the same as the capital one
+        buf[10] = buf[9];
+        let err = TextTransform::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}